@@ -9,9 +9,11 @@
 use clap::crate_description;
 use clap::{Arg, ArgMatches};
 
+use crate::server::DEFAULT_LOG_FORMAT;
+
 const ABOUT: &str = concat!("\n", crate_description!()); // Add extra newline.
 
-fn app() -> clap::Command<'static> {
+pub(super) fn app() -> clap::Command<'static> {
     let arg_port = Arg::new("port")
         .short('p')
         .long("port")
@@ -71,16 +73,403 @@ fn app() -> clap::Command<'static> {
         .long("--follow-links")
         .help("Follow symlinks outside current serving base path");
 
+    let arg_disable_symlinks = Arg::new("disable-symlinks")
+        .long("disable-symlinks")
+        .help("Refuse to serve any symlink, regardless of --follow-links");
+
     let arg_render_index = Arg::new("render-index")
         .short('r')
         .long("--render-index")
         .help("Render existing index.html when requesting a directory.");
 
+    let arg_server_timing = Arg::new("server-timing")
+        .long("server-timing")
+        .help("Report processing durations via Server-Timing header");
+
+    let arg_debug_mime = Arg::new("debug-mime")
+        .long("debug-mime")
+        .help("Report guessed MIME type via X-Guessed-Mime header for debugging");
+
     let arg_path_prefix = Arg::new("path-prefix")
         .long("path-prefix")
         .help("Specify an url path prefix, helpful when running behing a reverse proxy")
         .value_name("path");
 
+    let arg_coi_html_only = Arg::new("coi-html-only")
+        .long("coi-html-only")
+        .requires("coi")
+        .help("Apply cross-origin isolation to HTML documents only");
+
+    let arg_log_progress = Arg::new("log-progress")
+        .long("log-progress")
+        .help("Log a progress line every time given bytes are sent")
+        .value_name("bytes");
+
+    let arg_hidden_status = Arg::new("hidden-status")
+        .long("hidden-status")
+        .default_value("404")
+        .possible_values(["404", "403"])
+        .help("Specify status code responded for hidden and ignored paths")
+        .value_name("status");
+
+    let arg_compress_adaptive = Arg::new("compress-adaptive")
+        .long("compress-adaptive")
+        .help("Skip compression while the server is under heavy load");
+
+    let arg_compress_adaptive_threshold = Arg::new("compress-adaptive-threshold")
+        .long("compress-adaptive-threshold")
+        .default_value("100")
+        .help("Specify average milliseconds spent serving a request, including streaming compression, above which the server is under heavy load")
+        .value_name("ms");
+
+    let arg_strip_ansi = Arg::new("strip-ansi")
+        .long("strip-ansi")
+        .help("Strip ANSI escape sequences from plain text files");
+
+    let arg_deny_dotfiles_in_archives = Arg::new("deny-dotfiles-in-archives")
+        .long("deny-dotfiles-in-archives")
+        .help("Exclude hidden and dot (.) files from directory downloads, even with --all");
+
+    let arg_readme = Arg::new("readme")
+        .long("readme")
+        .help("Render README.md below the listing when requesting a directory");
+
+    let arg_no_index_robots = Arg::new("no-index-robots")
+        .long("no-index-robots")
+        .help("Ask search engines not to index served files via X-Robots-Tag header");
+
+    let arg_sniff = Arg::new("sniff")
+        .long("sniff")
+        .help("Detect MIME type of extensionless files from their content");
+
+    let arg_transparent_gz = Arg::new("transparent-gz")
+        .long("transparent-gz")
+        .help("Serve decompressed <file>.gz when requested <file> doesn't exist");
+
+    let arg_parent_link = Arg::new("no-parent-link")
+        .long("no-parent-link")
+        .help("Don't show the parent directory link in listings");
+
+    let arg_timeout_body = Arg::new("timeout-body")
+        .long("timeout-body")
+        .help("Abort response body transfers making no progress for given seconds")
+        .value_name("secs");
+
+    let arg_cors_origin_regex = Arg::new("cors-origin-regex")
+        .long("cors-origin-regex")
+        .conflicts_with("cors")
+        .help("Enable Cross-Origin Resource Sharing from origins matching given regex")
+        .value_name("regex");
+
+    let arg_max_path_length = Arg::new("max-path-length")
+        .long("max-path-length")
+        .default_value("4096")
+        .help("Specify max length of decoded request paths in bytes")
+        .value_name("bytes");
+
+    let arg_expose_version = Arg::new("expose-version")
+        .long("expose-version")
+        .help("Expose version information as JSON at /__sfz/version");
+
+    let arg_access_log_format = Arg::new("access-log-format")
+        .long("access-log-format")
+        .default_value(DEFAULT_LOG_FORMAT)
+        .help("Specify access log format with nginx-style variables, such as $remote_addr, $status, and $http_<header>, or `combined` for nginx combined format")
+        .value_name("format");
+
+    let arg_auth = Arg::new("auth")
+        .long("auth")
+        .conflicts_with_all(&["auth-user", "htpasswd"])
+        .help(
+            "Require HTTP Basic authentication with given credentials in the form of user:password",
+        )
+        .value_name("user:password");
+
+    let arg_auth_user = Arg::new("auth-user")
+        .long("auth-user")
+        .requires("ask-password")
+        .help("Require HTTP Basic authentication with given username")
+        .value_name("user");
+
+    let arg_ask_password = Arg::new("ask-password")
+        .long("ask-password")
+        .requires("auth-user")
+        .help("Prompt for the authentication password at startup");
+
+    let arg_htpasswd = Arg::new("htpasswd")
+        .long("htpasswd")
+        .conflicts_with("auth-user")
+        .help("Require HTTP Basic authentication against users in given htpasswd file")
+        .value_name("path");
+
+    let arg_exclude = Arg::new("exclude-from")
+        .long("exclude-from")
+        .help("Exclude paths matching glob patterns read from given file, one per line")
+        .value_name("path");
+
+    let arg_empty_zip_status = Arg::new("empty-zip-status")
+        .long("empty-zip-status")
+        .default_value("200")
+        .possible_values(["200", "204"])
+        .help("Specify status code responded for downloading an empty directory")
+        .value_name("status");
+
+    let arg_listen_fd = Arg::new("listen-fd")
+        .long("listen-fd")
+        .help("Serve on an inherited listening socket instead of binding, e.g. 3 for systemd socket activation")
+        .value_name("fd");
+
+    let arg_hide_index_in_listing = Arg::new("hide-index-in-listing")
+        .long("hide-index-in-listing")
+        .help("Hide index.html from directory listings while keeping it accessible");
+
+    let arg_error_page_dir = Arg::new("error-page-dir")
+        .long("error-page-dir")
+        .help("Use pages named after status codes in given directory, such as 404.html, for error responses")
+        .value_name("dir");
+
+    let arg_strip_prefix_from_listing = Arg::new("strip-prefix-from-listing")
+        .long("strip-prefix-from-listing")
+        .help("Generate relative links in listings, omitting path prefix added by a reverse proxy");
+
+    let arg_compress_if_smaller = Arg::new("compress-if-smaller")
+        .long("compress-if-smaller")
+        .help("Compress small responses in memory and send them compressed only if smaller");
+
+    let arg_watch_ignore = Arg::new("watch-ignore")
+        .long("watch-ignore")
+        .help("Reload .gitignore and exclude rules when they change");
+
+    let arg_admin_bind = Arg::new("admin-bind")
+        .long("admin-bind")
+        .help("Serve health, metrics and version endpoints on a separate address")
+        .value_name("addr:port");
+
+    let arg_no_canonicalize = Arg::new("no-canonicalize")
+        .long("no-canonicalize")
+        .help("Normalize paths lexically instead of resolving them, for filesystems where canonicalization fails");
+
+    let arg_immutable_pattern = Arg::new("immutable-pattern")
+        .long("immutable-pattern")
+        .help("Cache files whose request path matches given regex for a year as immutable, e.g. content-hashed names")
+        .value_name("regex");
+
+    let arg_sitemap = Arg::new("sitemap")
+        .long("sitemap")
+        .help("Serve a generated sitemap.xml listing all HTML files");
+
+    let arg_sitemap_base_url = Arg::new("sitemap-base-url")
+        .long("sitemap-base-url")
+        .requires("sitemap")
+        .help("Absolute http(s) base URL of sitemap locations [default: address served on]")
+        .value_name("url");
+
+    let arg_charset_detect_bytes = Arg::new("charset-detect-bytes")
+        .long("charset-detect-bytes")
+        .default_value("4096")
+        .help("Bytes of each text file sampled to verify it is UTF-8 before labeling it so. 0 to disable")
+        .value_name("n");
+
+    let arg_attachment_ext = Arg::new("attachment-ext")
+        .long("attachment-ext")
+        .help("Download files with given comma-separated extensions as attachments instead of displaying inline, e.g. csv,bin,exe")
+        .value_name("exts");
+
+    let arg_connection_log = Arg::new("connection-log")
+        .long("connection-log")
+        .help("Log when connections are accepted and closed, with duration and bytes sent");
+
+    let arg_root_redirect = Arg::new("root-redirect")
+        .long("root-redirect")
+        .help(
+            "Redirect requests to the root, under path prefix if any, to given subpath, e.g. /app/",
+        )
+        .value_name("path");
+
+    let arg_walk_threads = Arg::new("walk-threads")
+        .long("walk-threads")
+        .default_value("1")
+        .help("Number of threads walking directories for listings and archives, which helps on high-latency filesystems")
+        .value_name("n");
+
+    let arg_no_last_modified = Arg::new("no-last-modified")
+        .long("no-last-modified")
+        .help("Omit Last-Modified header and validate conditional requests with ETag only");
+
+    let arg_buffer_pool = Arg::new("buffer-pool")
+        .long("buffer-pool")
+        .help("Reuse read buffers of file transfers to reduce allocations");
+
+    let arg_list_json_pretty = Arg::new("list-json-pretty")
+        .long("list-json-pretty")
+        .help("Indent JSON directory listings for human readers");
+
+    let arg_log_timezone = Arg::new("log-timezone")
+        .long("log-timezone")
+        .default_value("local")
+        .help(
+            "Specify timezone of access log timestamps, either utc, local or an offset like +08:00. Timestamps in other than local time carry the offset",
+        )
+        .value_name("tz");
+
+    let arg_max_listing_entries = Arg::new("max-listing-entries")
+        .long("max-listing-entries")
+        .help("Cap the number of entries listed in a directory")
+        .value_name("n");
+
+    let arg_no_ranges_for = Arg::new("no-ranges-for")
+        .long("no-ranges-for")
+        .help("Disable range requests of files with given comma-separated MIME type globs, e.g. text/*,application/json")
+        .value_name("mime-globs");
+
+    let arg_etag = Arg::new("etag")
+        .long("etag")
+        .default_value("mtime")
+        .possible_values(["mtime", "xxhash", "sha256"])
+        .help("Specify how ETags of files are computed, from modified time and size, or content digests")
+        .value_name("algorithm");
+
+    let arg_etag_hash = Arg::new("etag-hash")
+        .long("etag-hash")
+        .conflicts_with("etag")
+        .help("Compute ETags of files from content digests, same as --etag xxhash");
+
+    let arg_robots = Arg::new("robots")
+        .long("robots")
+        .help("Serve given file at /robots.txt")
+        .value_name("path");
+
+    let arg_no_robots = Arg::new("no-robots")
+        .long("no-robots")
+        .conflicts_with("robots")
+        .help("Serve a robots.txt disallowing all crawlers at /robots.txt");
+
+    let arg_download_rate_per_ip = Arg::new("download-rate-per-ip")
+        .long("download-rate-per-ip")
+        .help("Cap download bandwidth of each client IP across all its connections, in bytes per second")
+        .value_name("bytes");
+
+    let arg_x_accel = Arg::new("x-accel")
+        .long("x-accel")
+        .help("Delegate large files to nginx via X-Accel-Redirect under given internal location prefix")
+        .value_name("prefix");
+
+    let arg_x_accel_min_size = Arg::new("x-accel-min-size")
+        .long("x-accel-min-size")
+        .requires("x-accel")
+        .default_value("1048576")
+        .help("Specify minimum size in bytes of files delegated via X-Accel-Redirect")
+        .value_name("bytes");
+
+    let arg_root_page = Arg::new("root-page")
+        .long("root-page")
+        .conflicts_with("root-redirect")
+        .help("Serve given file at the root instead of listing it, while other directories are listed as usual")
+        .value_name("path");
+
+    let arg_compression_threads = Arg::new("compression-threads")
+        .long("compression-threads")
+        .help("Limit concurrent on-the-fly compressions. Responses beyond the limit are sent uncompressed")
+        .value_name("n");
+
+    let arg_probe_exts = Arg::new("probe-exts")
+        .long("probe-exts")
+        .help("Serve the first existing file with given comma-separated extensions for extensionless paths not found, e.g. html,htm")
+        .value_name("exts");
+
+    let arg_allow_git = Arg::new("allow-git")
+        .long("allow-git")
+        .help("Serve paths inside .git directories, which are refused even with --all");
+
+    let arg_listing_footer = Arg::new("listing-footer")
+        .long("listing-footer")
+        .help("Show given text, or content of given file, at the bottom of directory listings. Escaped unless --listing-footer-raw")
+        .value_name("html-or-path");
+
+    let arg_listing_footer_raw = Arg::new("listing-footer-raw")
+        .long("listing-footer-raw")
+        .help("Trust --listing-footer as HTML instead of escaping it. Never use with untrusted content")
+        .requires("listing-footer");
+
+    let arg_allowed_hosts = Arg::new("allowed-host")
+        .long("allowed-host")
+        .help("Only serve requests with given Host header, to prevent DNS rebinding. Hosts without a port match any port. Can be specified multiple times")
+        .value_name("host")
+        .multiple_occurrences(true);
+
+    let arg_compression_level = Arg::new("compression-level")
+        .long("compression-level")
+        .help("Specify compression level from 1 to 11, with levels above 9 clamped to 9 for gzip and deflate. Use --unzipped to disable compression. Defaults to the fastest for brotli and the default of others")
+        .value_name("level");
+
+    let arg_compress_threshold = Arg::new("compress-threshold")
+        .long("compress-threshold")
+        .default_value("1024")
+        .help("Specify minimum size in bytes of responses to compress")
+        .value_name("bytes");
+
+    let arg_tls_cert = Arg::new("tls-cert")
+        .long("tls-cert")
+        .allow_invalid_utf8(true)
+        .requires("tls-key")
+        .help("Serve HTTPS with given PEM certificate chain")
+        .value_name("path");
+
+    let arg_tls_key = Arg::new("tls-key")
+        .long("tls-key")
+        .allow_invalid_utf8(true)
+        .requires("tls-cert")
+        .help("Specify PEM private key of the certificate given by --tls-cert")
+        .value_name("path");
+
+    let arg_tls_self_signed = Arg::new("tls-self-signed")
+        .long("tls-self-signed")
+        .help("Serve HTTPS with a certificate for localhost generated at startup, unless --tls-cert is given");
+
+    let arg_extra_headers = Arg::new("header")
+        .long("header")
+        .help(
+            "Add given header to responses, e.g. \"Name: Value\". Can be specified multiple times",
+        )
+        .value_name("header")
+        .multiple_occurrences(true);
+
+    let arg_hsts = Arg::new("hsts")
+        .long("hsts")
+        .min_values(0)
+        .max_values(1)
+        .require_equals(true)
+        .default_missing_value("31536000")
+        .help("Send Strict-Transport-Security header with given max-age in seconds, for serving behind HTTPS proxies. Defaults to a year")
+        .value_name("secs");
+
+    let arg_hsts_include_subdomains = Arg::new("hsts-include-subdomains")
+        .long("hsts-include-subdomains")
+        .requires("hsts")
+        .help("Apply Strict-Transport-Security to subdomains as well");
+
+    let arg_spa = Arg::new("spa")
+        .long("spa")
+        .help("Serve index.html at the root for nonexistent paths without extensions, for single page applications routing by themselves");
+
+    let arg_not_found_page = Arg::new("404-page")
+        .long("404-page")
+        .allow_invalid_utf8(true)
+        .help("Use given HTML file for 404 Not Found responses, overriding --error-page-dir")
+        .value_name("path");
+
+    let arg_forbidden_page = Arg::new("403-page")
+        .long("403-page")
+        .allow_invalid_utf8(true)
+        .help("Use given HTML file for 403 Forbidden responses, overriding --error-page-dir")
+        .value_name("path");
+
+    let arg_internal_error_page = Arg::new("500-page")
+        .long("500-page")
+        .allow_invalid_utf8(true)
+        .help("Use given HTML file for 500 Internal Server Error responses, overriding --error-page-dir")
+        .value_name("path");
+
     clap::command!()
         .about(ABOUT)
         .arg(arg_address)
@@ -94,8 +483,82 @@ fn app() -> clap::Command<'static> {
         .arg(arg_no_ignore)
         .arg(arg_no_log)
         .arg(arg_follow_links)
+        .arg(arg_disable_symlinks)
         .arg(arg_render_index)
         .arg(arg_path_prefix)
+        .arg(arg_server_timing)
+        .arg(arg_debug_mime)
+        .arg(arg_coi_html_only)
+        .arg(arg_log_progress)
+        .arg(arg_hidden_status)
+        .arg(arg_compress_adaptive)
+        .arg(arg_compress_adaptive_threshold)
+        .arg(arg_strip_ansi)
+        .arg(arg_deny_dotfiles_in_archives)
+        .arg(arg_readme)
+        .arg(arg_no_index_robots)
+        .arg(arg_sniff)
+        .arg(arg_transparent_gz)
+        .arg(arg_parent_link)
+        .arg(arg_timeout_body)
+        .arg(arg_cors_origin_regex)
+        .arg(arg_max_path_length)
+        .arg(arg_expose_version)
+        .arg(arg_access_log_format)
+        .arg(arg_auth)
+        .arg(arg_auth_user)
+        .arg(arg_ask_password)
+        .arg(arg_htpasswd)
+        .arg(arg_exclude)
+        .arg(arg_empty_zip_status)
+        .arg(arg_listen_fd)
+        .arg(arg_hide_index_in_listing)
+        .arg(arg_error_page_dir)
+        .arg(arg_strip_prefix_from_listing)
+        .arg(arg_compress_if_smaller)
+        .arg(arg_watch_ignore)
+        .arg(arg_admin_bind)
+        .arg(arg_no_canonicalize)
+        .arg(arg_immutable_pattern)
+        .arg(arg_sitemap)
+        .arg(arg_sitemap_base_url)
+        .arg(arg_charset_detect_bytes)
+        .arg(arg_attachment_ext)
+        .arg(arg_connection_log)
+        .arg(arg_root_redirect)
+        .arg(arg_walk_threads)
+        .arg(arg_no_last_modified)
+        .arg(arg_buffer_pool)
+        .arg(arg_list_json_pretty)
+        .arg(arg_log_timezone)
+        .arg(arg_max_listing_entries)
+        .arg(arg_no_ranges_for)
+        .arg(arg_etag)
+        .arg(arg_etag_hash)
+        .arg(arg_robots)
+        .arg(arg_no_robots)
+        .arg(arg_download_rate_per_ip)
+        .arg(arg_x_accel)
+        .arg(arg_x_accel_min_size)
+        .arg(arg_root_page)
+        .arg(arg_compression_threads)
+        .arg(arg_probe_exts)
+        .arg(arg_allow_git)
+        .arg(arg_listing_footer)
+        .arg(arg_listing_footer_raw)
+        .arg(arg_allowed_hosts)
+        .arg(arg_compression_level)
+        .arg(arg_compress_threshold)
+        .arg(arg_tls_cert)
+        .arg(arg_tls_key)
+        .arg(arg_tls_self_signed)
+        .arg(arg_extra_headers)
+        .arg(arg_hsts)
+        .arg(arg_hsts_include_subdomains)
+        .arg(arg_spa)
+        .arg(arg_not_found_page)
+        .arg(arg_forbidden_page)
+        .arg(arg_internal_error_page)
 }
 
 pub fn matches() -> ArgMatches {
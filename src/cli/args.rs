@@ -7,14 +7,29 @@
 // except according to those terms.
 
 use std::env;
-use std::fs::canonicalize;
+use std::fs::{self, canonicalize};
+use std::io;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 use clap::ArgMatches;
+use hyper::header::{HeaderName, HeaderValue};
+use regex::Regex;
 
+use crate::extensions::PathExt;
+use crate::server::{
+    mime_globs, read_patterns, EtagAlgorithm, Exclude, Htpasswd, LogFormat, LogTimezone,
+};
 use crate::BoxResult;
 
+/// Content of robots.txt served by `--no-robots`.
+const DISALLOW_ALL_ROBOTS: &str = "User-agent: *\nDisallow: /\n";
+
+/// Maximum compression level, that of brotli.
+const MAX_COMPRESSION_LEVEL: u8 = 11;
+/// Maximum compression level of gzip and deflate.
+const MAX_FLATE_LEVEL: u8 = 9;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Args {
     pub address: String,
@@ -27,9 +42,94 @@ pub struct Args {
     pub all: bool,
     pub ignore: bool,
     pub follow_links: bool,
+    pub disable_symlinks: bool,
     pub render_index: bool,
     pub log: bool,
     pub path_prefix: Option<String>,
+    pub server_timing: bool,
+    pub debug_mime: bool,
+    pub coi_html_only: bool,
+    pub log_progress: Option<u64>,
+    pub hidden_status: u16,
+    pub compress_adaptive: bool,
+    pub compress_adaptive_threshold: u64,
+    pub strip_ansi: bool,
+    pub deny_dotfiles_in_archives: bool,
+    pub readme: bool,
+    pub no_index_robots: bool,
+    pub sniff: bool,
+    pub transparent_gz: bool,
+    pub parent_link: bool,
+    pub timeout_body: Option<u64>,
+    pub cors_origin_regex: Option<String>,
+    pub max_path_length: usize,
+    pub expose_version: bool,
+    pub access_log_format: String,
+    pub auth: Option<(String, String)>,
+    pub htpasswd: Option<Htpasswd>,
+    pub exclude_from: Option<PathBuf>,
+    pub exclude: Vec<String>,
+    pub empty_zip_status: u16,
+    pub listen_fd: Option<i32>,
+    pub hide_index_in_listing: bool,
+    pub error_page_dir: Option<PathBuf>,
+    pub strip_prefix_from_listing: bool,
+    pub compress_if_smaller: bool,
+    pub watch_ignore: bool,
+    pub admin_bind: Option<SocketAddr>,
+    pub no_canonicalize: bool,
+    pub immutable_pattern: Option<String>,
+    pub sitemap: bool,
+    pub sitemap_base_url: Option<String>,
+    pub charset_detect_bytes: usize,
+    pub attachment_ext: Vec<String>,
+    pub connection_log: bool,
+    pub root_redirect: Option<String>,
+    pub walk_threads: usize,
+    pub no_last_modified: bool,
+    pub buffer_pool: bool,
+    pub list_json_pretty: bool,
+    pub log_timezone: LogTimezone,
+    pub max_listing_entries: Option<usize>,
+    pub no_ranges_for: Vec<String>,
+    pub etag: EtagAlgorithm,
+    pub robots: Option<String>,
+    pub download_rate_per_ip: Option<u64>,
+    pub x_accel: Option<String>,
+    pub x_accel_min_size: u64,
+    pub root_page: Option<PathBuf>,
+    pub compression_threads: Option<usize>,
+    pub probe_exts: Vec<String>,
+    pub allow_git: bool,
+    pub listing_footer: Option<String>,
+    pub listing_footer_raw: bool,
+    pub allowed_hosts: Vec<String>,
+    pub compression_level: Option<u8>,
+    pub compress_threshold: u64,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_self_signed: bool,
+    pub extra_headers: Vec<(HeaderName, HeaderValue)>,
+    pub hsts: Option<u64>,
+    pub hsts_include_subdomains: bool,
+    pub spa: bool,
+    pub not_found_page: Option<PathBuf>,
+    pub forbidden_page: Option<PathBuf>,
+    pub internal_error_page: Option<PathBuf>,
+}
+
+/// Interactive source of passwords, abstracted to be testable without a TTY.
+trait PasswordPrompt {
+    fn prompt_password(&self, prompt: &str) -> io::Result<String>;
+}
+
+/// Read passwords from the terminal without echoing.
+struct TerminalPrompt;
+
+impl PasswordPrompt for TerminalPrompt {
+    fn prompt_password(&self, prompt: &str) -> io::Result<String> {
+        rpassword::prompt_password(prompt)
+    }
 }
 
 impl Args {
@@ -44,17 +144,232 @@ impl Args {
         let cors = matches.is_present("cors");
         let coi = matches.is_present("coi");
         let path = matches.value_of_os("path").unwrap_or_default();
-        let path = Args::parse_path(path)?;
+        let no_canonicalize = matches.is_present("no-canonicalize");
+        let path = if no_canonicalize {
+            Args::parse_path_lexically(path)?
+        } else {
+            Args::parse_path(path)?
+        };
 
         let compress = !matches.is_present("unzipped");
         let all = matches.is_present("all");
         let ignore = !matches.is_present("no-ignore");
         let follow_links = matches.is_present("follow-links");
+        let disable_symlinks = matches.is_present("disable-symlinks");
         let render_index = matches.is_present("render-index");
         let log = !matches.is_present("no-log");
         let path_prefix = matches
             .value_of("path-prefix")
             .map(|s| format!("/{}", s.trim_start_matches('/')));
+        let server_timing = matches.is_present("server-timing");
+        let debug_mime = matches.is_present("debug-mime");
+        let coi_html_only = matches.is_present("coi-html-only");
+        let log_progress = matches
+            .is_present("log-progress")
+            .then(|| matches.value_of_t::<u64>("log-progress"))
+            .transpose()?;
+        let hidden_status = matches.value_of_t::<u16>("hidden-status")?;
+        let compress_adaptive = matches.is_present("compress-adaptive");
+        let compress_adaptive_threshold =
+            matches.value_of_t::<u64>("compress-adaptive-threshold")?;
+        let strip_ansi = matches.is_present("strip-ansi");
+        let deny_dotfiles_in_archives = matches.is_present("deny-dotfiles-in-archives");
+        let readme = matches.is_present("readme");
+        let no_index_robots = matches.is_present("no-index-robots");
+        let sniff = matches.is_present("sniff");
+        let transparent_gz = matches.is_present("transparent-gz");
+        let parent_link = !matches.is_present("no-parent-link");
+        let timeout_body = matches
+            .is_present("timeout-body")
+            .then(|| matches.value_of_t::<u64>("timeout-body"))
+            .transpose()?;
+        let cors_origin_regex = matches.value_of("cors-origin-regex").map(str::to_owned);
+        if let Some(regex) = &cors_origin_regex {
+            if let Err(err) = Regex::new(regex) {
+                bail!("error: invalid regex \"{}\": {}", regex, err);
+            }
+        }
+        let max_path_length = matches.value_of_t::<usize>("max-path-length")?;
+        let expose_version = matches.is_present("expose-version");
+        let access_log_format = matches
+            .value_of("access-log-format")
+            .unwrap_or_default()
+            .to_owned();
+        if let Err(err) = access_log_format.parse::<LogFormat>() {
+            bail!("error: invalid access log format: {}", err);
+        }
+        let auth = match matches.value_of("auth") {
+            Some(credentials) => Some(Args::parse_credentials(credentials)?),
+            None => Args::parse_auth(
+                matches.value_of("auth-user"),
+                matches.is_present("ask-password"),
+                &TerminalPrompt,
+            )?,
+        };
+        let htpasswd = matches
+            .value_of_os("htpasswd")
+            .map(Args::parse_htpasswd)
+            .transpose()?;
+        let exclude_from = matches
+            .value_of_os("exclude-from")
+            .map(Args::parse_path)
+            .transpose()?;
+        let exclude = exclude_from
+            .as_ref()
+            .map(|file| Args::parse_exclude_from(file, &path))
+            .transpose()?
+            .unwrap_or_default();
+        let empty_zip_status = matches.value_of_t::<u16>("empty-zip-status")?;
+        let listen_fd = matches
+            .is_present("listen-fd")
+            .then(|| matches.value_of_t::<i32>("listen-fd"))
+            .transpose()?;
+        let hide_index_in_listing = matches.is_present("hide-index-in-listing");
+        let error_page_dir = matches
+            .value_of_os("error-page-dir")
+            .map(Args::parse_path)
+            .transpose()?;
+        let strip_prefix_from_listing = matches.is_present("strip-prefix-from-listing");
+        let compress_if_smaller = matches.is_present("compress-if-smaller");
+        let watch_ignore = matches.is_present("watch-ignore");
+        let admin_bind = matches
+            .is_present("admin-bind")
+            .then(|| matches.value_of_t::<SocketAddr>("admin-bind"))
+            .transpose()?;
+        let immutable_pattern = matches.value_of("immutable-pattern").map(str::to_owned);
+        if let Some(regex) = &immutable_pattern {
+            if let Err(err) = Regex::new(regex) {
+                bail!("error: invalid regex \"{}\": {}", regex, err);
+            }
+        }
+        let sitemap = matches.is_present("sitemap");
+        let sitemap_base_url = matches
+            .value_of("sitemap-base-url")
+            .map(Args::parse_base_url)
+            .transpose()?;
+        let charset_detect_bytes = matches.value_of_t::<usize>("charset-detect-bytes")?;
+        let attachment_ext = matches
+            .value_of("attachment-ext")
+            .map(Args::parse_extensions)
+            .unwrap_or_default();
+        let connection_log = matches.is_present("connection-log");
+        let root_redirect = matches.value_of("root-redirect").map(str::to_owned);
+        if let Some(target) = root_redirect.as_deref().filter(|t| !t.starts_with('/')) {
+            bail!(
+                "error: redirect target \"{}\" must start with a slash",
+                target
+            );
+        }
+        let walk_threads = matches.value_of_t::<usize>("walk-threads")?;
+        let no_last_modified = matches.is_present("no-last-modified");
+        let buffer_pool = matches.is_present("buffer-pool");
+        let list_json_pretty = matches.is_present("list-json-pretty");
+        let log_timezone = match matches
+            .value_of("log-timezone")
+            .unwrap_or_default()
+            .parse::<LogTimezone>()
+        {
+            Ok(timezone) => timezone,
+            Err(err) => bail!("error: invalid log timezone: {}", err),
+        };
+        let max_listing_entries = matches
+            .is_present("max-listing-entries")
+            .then(|| matches.value_of_t::<usize>("max-listing-entries"))
+            .transpose()?;
+        let no_ranges_for = matches
+            .value_of("no-ranges-for")
+            .map(Args::parse_mime_globs)
+            .transpose()?
+            .unwrap_or_default();
+        let etag = match matches
+            .value_of("etag")
+            .unwrap_or_default()
+            .parse::<EtagAlgorithm>()
+        {
+            _ if matches.is_present("etag-hash") => EtagAlgorithm::Xxhash,
+            Ok(etag) => etag,
+            Err(err) => bail!("error: invalid ETag algorithm: {}", err),
+        };
+        let robots = match matches.value_of_os("robots") {
+            Some(path) => Some(Args::parse_robots(path)?),
+            None => matches
+                .is_present("no-robots")
+                .then(|| DISALLOW_ALL_ROBOTS.to_owned()),
+        };
+        let download_rate_per_ip = matches
+            .is_present("download-rate-per-ip")
+            .then(|| matches.value_of_t::<u64>("download-rate-per-ip"))
+            .transpose()?;
+        if download_rate_per_ip == Some(0) {
+            bail!("error: download rate must be greater than zero");
+        }
+        let x_accel = matches.value_of("x-accel").map(str::to_owned);
+        if let Some(prefix) = x_accel.as_deref().filter(|p| !p.starts_with('/')) {
+            bail!(
+                "error: internal location \"{}\" must start with a slash",
+                prefix
+            );
+        }
+        let x_accel_min_size = matches.value_of_t::<u64>("x-accel-min-size")?;
+        let root_page = matches
+            .value_of_os("root-page")
+            .map(Args::parse_path)
+            .transpose()?;
+        let compression_threads = matches
+            .is_present("compression-threads")
+            .then(|| matches.value_of_t::<usize>("compression-threads"))
+            .transpose()?;
+        let probe_exts = matches
+            .value_of("probe-exts")
+            .map(Args::parse_extensions)
+            .unwrap_or_default();
+        let allow_git = matches.is_present("allow-git");
+        let listing_footer = matches
+            .value_of("listing-footer")
+            .map(Args::parse_listing_footer)
+            .transpose()?;
+        let listing_footer_raw = matches.is_present("listing-footer-raw");
+        let allowed_hosts = matches
+            .values_of("allowed-host")
+            .map(|hosts| hosts.map(str::to_owned).collect())
+            .unwrap_or_default();
+        let compression_level = matches
+            .value_of("compression-level")
+            .map(Args::parse_compression_level)
+            .transpose()?;
+        let compress_threshold = matches.value_of_t::<u64>("compress-threshold")?;
+        let tls_cert = matches
+            .value_of_os("tls-cert")
+            .map(Args::parse_path)
+            .transpose()?;
+        let tls_key = matches
+            .value_of_os("tls-key")
+            .map(Args::parse_path)
+            .transpose()?;
+        let tls_self_signed = matches.is_present("tls-self-signed");
+        let extra_headers = matches
+            .values_of("header")
+            .map(|headers| headers.map(Args::parse_header).collect::<BoxResult<_>>())
+            .transpose()?
+            .unwrap_or_default();
+        let hsts = matches
+            .is_present("hsts")
+            .then(|| matches.value_of_t::<u64>("hsts"))
+            .transpose()?;
+        let hsts_include_subdomains = matches.is_present("hsts-include-subdomains");
+        let spa = matches.is_present("spa");
+        let not_found_page = matches
+            .value_of_os("404-page")
+            .map(Args::parse_path)
+            .transpose()?;
+        let forbidden_page = matches
+            .value_of_os("403-page")
+            .map(Args::parse_path)
+            .transpose()?;
+        let internal_error_page = matches
+            .value_of_os("500-page")
+            .map(Args::parse_path)
+            .transpose()?;
 
         Ok(Args {
             address,
@@ -67,12 +382,230 @@ impl Args {
             all,
             ignore,
             follow_links,
+            disable_symlinks,
             render_index,
             log,
             path_prefix,
+            server_timing,
+            debug_mime,
+            coi_html_only,
+            log_progress,
+            hidden_status,
+            compress_adaptive,
+            compress_adaptive_threshold,
+            strip_ansi,
+            deny_dotfiles_in_archives,
+            readme,
+            no_index_robots,
+            sniff,
+            transparent_gz,
+            parent_link,
+            timeout_body,
+            cors_origin_regex,
+            max_path_length,
+            expose_version,
+            access_log_format,
+            auth,
+            htpasswd,
+            exclude_from,
+            exclude,
+            empty_zip_status,
+            listen_fd,
+            hide_index_in_listing,
+            error_page_dir,
+            strip_prefix_from_listing,
+            compress_if_smaller,
+            watch_ignore,
+            admin_bind,
+            no_canonicalize,
+            immutable_pattern,
+            sitemap,
+            sitemap_base_url,
+            charset_detect_bytes,
+            attachment_ext,
+            connection_log,
+            root_redirect,
+            walk_threads,
+            no_last_modified,
+            buffer_pool,
+            list_json_pretty,
+            log_timezone,
+            max_listing_entries,
+            no_ranges_for,
+            etag,
+            robots,
+            download_rate_per_ip,
+            x_accel,
+            x_accel_min_size,
+            root_page,
+            compression_threads,
+            probe_exts,
+            allow_git,
+            listing_footer,
+            listing_footer_raw,
+            allowed_hosts,
+            compression_level,
+            compress_threshold,
+            tls_cert,
+            tls_key,
+            tls_self_signed,
+            extra_headers,
+            hsts,
+            hsts_include_subdomains,
+            spa,
+            not_found_page,
+            forbidden_page,
+            internal_error_page,
         })
     }
 
+    /// Split Basic authentication credentials given as `user:password` on the
+    /// first colon, so passwords may contain colons.
+    fn parse_credentials(credentials: &str) -> BoxResult<(String, String)> {
+        let (user, password) = match credentials.split_once(':') {
+            Some(pair) => pair,
+            None => bail!("error: credentials must be in the form of \"user:password\""),
+        };
+        if user.is_empty() {
+            bail!("error: username must not be empty");
+        }
+        if password.is_empty() {
+            bail!("error: password must not be empty");
+        }
+        Ok((user.to_owned(), password.to_owned()))
+    }
+
+    /// Combine the username given by flag with the password read from the
+    /// prompt into Basic authentication credentials.
+    fn parse_auth(
+        user: Option<&str>,
+        ask_password: bool,
+        prompt: &impl PasswordPrompt,
+    ) -> BoxResult<Option<(String, String)>> {
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        if user.is_empty() || user.contains(':') {
+            bail!("error: invalid username \"{}\"", user);
+        }
+        if !ask_password {
+            bail!("error: no password given for user \"{}\"", user);
+        }
+        let password = match prompt.prompt_password(&format!("Password for {}: ", user)) {
+            Ok(password) => password,
+            Err(err) => bail!("error: failed to read password: {}", err),
+        };
+        if password.is_empty() {
+            bail!("error: password must not be empty");
+        }
+        Ok(Some((user.to_owned(), password)))
+    }
+
+    /// Load users from an htpasswd file.
+    fn parse_htpasswd<P: AsRef<Path>>(path: P) -> BoxResult<Htpasswd> {
+        let path = path.as_ref();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => bail!("error: failed to read \"{}\": {}", path.display(), err),
+        };
+        content
+            .parse()
+            .or_else(|err| bail!("error: invalid htpasswd \"{}\": {}", path.display(), err))
+    }
+
+    /// Read content of a robots.txt file.
+    fn parse_robots<P: AsRef<Path>>(path: P) -> BoxResult<String> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .or_else(|err| bail!("error: failed to read \"{}\": {}", path.display(), err))
+    }
+
+    /// Footer of listings is either literal content or read from a file
+    /// if it exists.
+    fn parse_listing_footer(value: &str) -> BoxResult<String> {
+        let path = Path::new(value);
+        if !path.is_file() {
+            return Ok(value.to_owned());
+        }
+        fs::read_to_string(path)
+            .or_else(|err| bail!("error: failed to read \"{}\": {}", path.display(), err))
+    }
+
+    /// Parse a header in the form of `Name: Value`.
+    fn parse_header(header: &str) -> BoxResult<(HeaderName, HeaderValue)> {
+        let (name, value) = match header.split_once(':') {
+            Some(pair) => pair,
+            None => bail!(
+                "error: header \"{}\" must be in the form of \"Name: Value\"",
+                header
+            ),
+        };
+        let name = match HeaderName::from_bytes(name.trim().as_bytes()) {
+            Ok(name) => name,
+            Err(_) => bail!("error: invalid header name \"{}\"", name.trim()),
+        };
+        let value = match HeaderValue::from_str(value.trim()) {
+            Ok(value) => value,
+            Err(_) => bail!("error: invalid value of header \"{}\"", name),
+        };
+        Ok((name, value))
+    }
+
+    /// Parse an absolute `http` or `https` URL, without query or fragment.
+    fn parse_base_url(url: &str) -> BoxResult<String> {
+        let uri = match url.parse::<hyper::Uri>() {
+            Ok(uri) => uri,
+            Err(err) => bail!("error: invalid base URL \"{}\": {}", url, err),
+        };
+        let is_http = matches!(uri.scheme_str(), Some("http" | "https"));
+        if !is_http || uri.authority().is_none() || uri.query().is_some() || url.contains('#') {
+            bail!(
+                "error: base URL \"{}\" must be an absolute http(s) URL without query",
+                url
+            );
+        }
+        Ok(url.to_owned())
+    }
+
+    /// Parse a compression level from 1 to the maximum of brotli. Level 0 is
+    /// rejected, as `--unzipped` is the way to disable compression.
+    fn parse_compression_level(value: &str) -> BoxResult<u8> {
+        let level = match value.parse::<u8>() {
+            Ok(0) => bail!(
+                "error: compression level must be from 1 to {}, got \"0\"; use --unzipped to disable compression",
+                MAX_COMPRESSION_LEVEL
+            ),
+            Ok(level) if level <= MAX_COMPRESSION_LEVEL => level,
+            _ => bail!(
+                "error: compression level must be from 1 to {}, got \"{}\"",
+                MAX_COMPRESSION_LEVEL,
+                value
+            ),
+        };
+        if level > MAX_FLATE_LEVEL {
+            eprintln!(
+                "warning: compression level {} is clamped to {} for gzip and deflate",
+                level, MAX_FLATE_LEVEL
+            );
+        }
+        Ok(level)
+    }
+
+    /// Read glob patterns from a file, one per line. Blank lines and comments
+    /// are ignored.
+    fn parse_exclude_from<P: AsRef<Path>>(file: P, base_path: &Path) -> BoxResult<Vec<String>> {
+        let file = file.as_ref();
+        let patterns = match read_patterns(file) {
+            Ok(patterns) => patterns,
+            Err(err) => bail!("error: failed to read \"{}\": {}", file.display(), err),
+        };
+        if let Err(err) = Exclude::new(base_path, &patterns) {
+            bail!("error: invalid pattern in \"{}\": {}", file.display(), err);
+        }
+        Ok(patterns)
+    }
+
     /// Parse path.
     fn parse_path<P: AsRef<Path>>(path: P) -> BoxResult<PathBuf> {
         let path = path.as_ref();
@@ -94,6 +627,47 @@ impl Args {
             })
     }
 
+    /// Parse path without resolving symlinks.
+    ///
+    /// The path is made absolute and then normalized lexically.
+    fn parse_path_lexically<P: AsRef<Path>>(path: P) -> BoxResult<PathBuf> {
+        let path = path.as_ref();
+        if !path.exists() {
+            bail!("error: path \"{}\" doesn't exist", path.display());
+        }
+
+        match env::current_dir() {
+            Ok(current_dir) => Ok(current_dir.join(path).lexically_normalize()),
+            Err(err) => bail!(
+                "error: failed to access path \"{}\": {}",
+                path.display(),
+                err,
+            ),
+        }
+    }
+
+    /// Parse comma-separated file extensions, lowercased and without
+    /// leading dots.
+    fn parse_extensions(exts: &str) -> Vec<String> {
+        exts.split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect()
+    }
+
+    /// Parse comma-separated MIME type globs.
+    fn parse_mime_globs(globs: &str) -> BoxResult<Vec<String>> {
+        let globs = globs
+            .split(',')
+            .map(|glob| glob.trim().to_lowercase())
+            .filter(|glob| !glob.is_empty())
+            .collect::<Vec<_>>();
+        if let Err(err) = mime_globs(&globs) {
+            bail!("error: invalid MIME type glob: {}", err);
+        }
+        Ok(globs)
+    }
+
     /// Construct socket address from arguments.
     pub fn address(&self) -> BoxResult<SocketAddr> {
         format!("{}:{}", self.address, self.port)
@@ -112,7 +686,9 @@ impl Args {
 #[cfg(test)]
 mod t {
     use super::*;
+    use crate::cli::app::app;
     use crate::matches;
+    use crate::server::DEFAULT_LOG_FORMAT;
     use crate::test_utils::with_current_dir;
     use std::fs::File;
     use tempfile::Builder;
@@ -131,9 +707,80 @@ mod t {
                 all: true,
                 ignore: true,
                 follow_links: true,
+                disable_symlinks: false,
                 render_index: true,
                 log: true,
                 path_prefix: None,
+                server_timing: false,
+                debug_mime: false,
+                coi_html_only: false,
+                log_progress: None,
+                hidden_status: 404,
+                compress_adaptive: false,
+                compress_adaptive_threshold: 100,
+                strip_ansi: false,
+                deny_dotfiles_in_archives: false,
+                readme: false,
+                no_index_robots: false,
+                sniff: false,
+                transparent_gz: false,
+                parent_link: true,
+                timeout_body: None,
+                cors_origin_regex: None,
+                max_path_length: 4096,
+                expose_version: false,
+                access_log_format: DEFAULT_LOG_FORMAT.to_owned(),
+                auth: None,
+                htpasswd: None,
+                exclude_from: None,
+                exclude: Vec::new(),
+                empty_zip_status: 200,
+                listen_fd: None,
+                hide_index_in_listing: false,
+                error_page_dir: None,
+                strip_prefix_from_listing: false,
+                compress_if_smaller: false,
+                watch_ignore: false,
+                admin_bind: None,
+                no_canonicalize: false,
+                immutable_pattern: None,
+                sitemap: false,
+                sitemap_base_url: None,
+                charset_detect_bytes: 4096,
+                attachment_ext: Vec::new(),
+                connection_log: false,
+                root_redirect: None,
+                walk_threads: 1,
+                no_last_modified: false,
+                buffer_pool: false,
+                list_json_pretty: false,
+                log_timezone: LogTimezone::Local,
+                max_listing_entries: None,
+                no_ranges_for: Vec::new(),
+                etag: EtagAlgorithm::Mtime,
+                robots: None,
+                download_rate_per_ip: None,
+                x_accel: None,
+                x_accel_min_size: 1024 * 1024,
+                root_page: None,
+                compression_threads: None,
+                probe_exts: Vec::new(),
+                allow_git: false,
+                listing_footer: None,
+                listing_footer_raw: false,
+                allowed_hosts: Vec::new(),
+                compression_level: None,
+                compress_threshold: 0,
+                tls_cert: None,
+                tls_key: None,
+                tls_self_signed: false,
+                extra_headers: Vec::new(),
+                hsts: None,
+                hsts_include_subdomains: false,
+                spa: false,
+                not_found_page: None,
+                forbidden_page: None,
+                internal_error_page: None,
             }
         }
     }
@@ -165,12 +812,83 @@ mod t {
                     cors: false,
                     coi: false,
                     follow_links: false,
+                    disable_symlinks: false,
                     ignore: true,
                     log: true,
                     path,
                     path_prefix: None,
                     render_index: false,
-                    port: 5000
+                    port: 5000,
+                    server_timing: false,
+                    debug_mime: false,
+                    coi_html_only: false,
+                    log_progress: None,
+                    hidden_status: 404,
+                    compress_adaptive: false,
+                    compress_adaptive_threshold: 100,
+                    strip_ansi: false,
+                    deny_dotfiles_in_archives: false,
+                    readme: false,
+                    no_index_robots: false,
+                    sniff: false,
+                    transparent_gz: false,
+                    parent_link: true,
+                    timeout_body: None,
+                    cors_origin_regex: None,
+                    max_path_length: 4096,
+                    expose_version: false,
+                    access_log_format: DEFAULT_LOG_FORMAT.to_owned(),
+                    auth: None,
+                    htpasswd: None,
+                    exclude_from: None,
+                    exclude: Vec::new(),
+                    empty_zip_status: 200,
+                    listen_fd: None,
+                    hide_index_in_listing: false,
+                    error_page_dir: None,
+                    strip_prefix_from_listing: false,
+                    compress_if_smaller: false,
+                    watch_ignore: false,
+                    admin_bind: None,
+                    no_canonicalize: false,
+                    immutable_pattern: None,
+                    sitemap: false,
+                    sitemap_base_url: None,
+                    charset_detect_bytes: 4096,
+                    attachment_ext: Vec::new(),
+                    connection_log: false,
+                    root_redirect: None,
+                    walk_threads: 1,
+                    no_last_modified: false,
+                    buffer_pool: false,
+                    list_json_pretty: false,
+                    log_timezone: LogTimezone::Local,
+                    max_listing_entries: None,
+                    no_ranges_for: Vec::new(),
+                    etag: EtagAlgorithm::Mtime,
+                    robots: None,
+                    download_rate_per_ip: None,
+                    x_accel: None,
+                    x_accel_min_size: 1024 * 1024,
+                    root_page: None,
+                    compression_threads: None,
+                    probe_exts: Vec::new(),
+                    allow_git: false,
+                    listing_footer: None,
+                    listing_footer_raw: false,
+                    allowed_hosts: Vec::new(),
+                    compression_level: None,
+                    compress_threshold: 1024,
+                    tls_cert: None,
+                    tls_key: None,
+                    tls_self_signed: false,
+                    extra_headers: Vec::new(),
+                    hsts: None,
+                    hsts_include_subdomains: false,
+                    spa: false,
+                    not_found_page: None,
+                    forbidden_page: None,
+                    internal_error_page: None,
                 }
             );
         });
@@ -191,6 +909,40 @@ mod t {
         );
     }
 
+    #[test]
+    fn parse_path_lexically() {
+        let tmp_dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        with_current_dir(tmp_dir.path(), || {
+            assert!(Args::parse_path_lexically("temp.txt").is_err());
+            File::create("temp.txt").unwrap();
+            fs::create_dir("dir").unwrap();
+            let expected = env::current_dir().unwrap().join("temp.txt");
+            assert_eq!(Args::parse_path_lexically("./temp.txt").unwrap(), expected);
+            assert_eq!(
+                Args::parse_path_lexically("dir/../temp.txt").unwrap(),
+                expected,
+            );
+        });
+    }
+
+    #[test]
+    fn parse_extensions() {
+        assert_eq!(
+            Args::parse_extensions("csv, .BIN,,exe"),
+            ["csv", "bin", "exe"]
+        );
+        assert!(Args::parse_extensions("").is_empty());
+    }
+
+    #[test]
+    fn parse_mime_globs() {
+        assert_eq!(
+            Args::parse_mime_globs("video/*, Text/Plain,,").unwrap(),
+            ["video/*", "text/plain"]
+        );
+        assert!(Args::parse_mime_globs("text/[").is_err());
+    }
+
     #[test]
     fn parse_relative_path() {
         let tmp_dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
@@ -206,6 +958,111 @@ mod t {
         });
     }
 
+    #[test]
+    fn parse_exclude_from() {
+        let tmp_dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let file = tmp_dir.path().join("exclude");
+        std::fs::write(&file, "# comment\n*.log\n\n  secret  \n").unwrap();
+        assert_eq!(
+            Args::parse_exclude_from(&file, tmp_dir.path()).unwrap(),
+            ["*.log", "secret"],
+        );
+
+        std::fs::write(&file, "a[").unwrap();
+        assert!(Args::parse_exclude_from(&file, tmp_dir.path()).is_err());
+        assert!(Args::parse_exclude_from(tmp_dir.path().join("missing"), tmp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn parse_compression_level() {
+        assert_eq!(Args::parse_compression_level("1").unwrap(), 1);
+        assert_eq!(Args::parse_compression_level("11").unwrap(), 11);
+        assert!(Args::parse_compression_level("0").is_err());
+        assert!(Args::parse_compression_level("12").is_err());
+        assert!(Args::parse_compression_level("-1").is_err());
+        assert!(Args::parse_compression_level("fast").is_err());
+
+        let args =
+            Args::parse(app().get_matches_from(["sfz", "--compression-level", "5"])).unwrap();
+        assert_eq!(args.compression_level, Some(5));
+        assert!(args.compress);
+        assert!(Args::parse(app().get_matches_from(["sfz", "--compression-level", "0"])).is_err());
+        assert!(Args::parse(app().get_matches_from(["sfz", "--compression-level", "12"])).is_err());
+    }
+
+    #[test]
+    fn parse_header() {
+        let (name, value) = Args::parse_header("X-Robots-Tag: noindex, nofollow").unwrap();
+        assert_eq!(name, "x-robots-tag");
+        assert_eq!(value, "noindex, nofollow");
+        let (_, value) = Args::parse_header("Link:</a.css>; rel=preload").unwrap();
+        assert_eq!(value, "</a.css>; rel=preload");
+        assert!(Args::parse_header("X-Empty:").is_ok());
+
+        assert!(Args::parse_header("no-colon").is_err());
+        assert!(Args::parse_header("Bad Name: value").is_err());
+        assert!(Args::parse_header(": value").is_err());
+        assert!(Args::parse_header("X-Bad: line\nbreak").is_err());
+
+        let matches = app().get_matches_from(["sfz", "--header", "A: 1", "--header", "B: 2"]);
+        let args = Args::parse(matches).unwrap();
+        assert_eq!(args.extra_headers.len(), 2);
+        let matches = app().get_matches_from(["sfz", "--header", "A: 1", "--header", "bad"]);
+        assert!(Args::parse(matches).is_err());
+    }
+
+    #[test]
+    fn parse_hsts() {
+        let args = Args::parse(app().get_matches_from(["sfz", "--hsts"])).unwrap();
+        assert_eq!(args.hsts, Some(31_536_000));
+        let args = Args::parse(app().get_matches_from(["sfz", "--hsts=60"])).unwrap();
+        assert_eq!(args.hsts, Some(60));
+        let args = Args::parse(app().get_matches_from(["sfz"])).unwrap();
+        assert_eq!(args.hsts, None);
+        assert!(app()
+            .try_get_matches_from(["sfz", "--hsts-include-subdomains"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_base_url() {
+        for url in ["https://example.com", "http://example.com:8080/site/"] {
+            assert_eq!(Args::parse_base_url(url).unwrap(), url);
+        }
+        for url in [
+            "example.com",
+            "/site",
+            "ftp://example.com",
+            "https://a/?q",
+            "https://a/#f",
+        ] {
+            assert!(Args::parse_base_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn parse_etag_hash() {
+        let args = Args::parse(app().get_matches_from(["sfz", "--etag-hash"])).unwrap();
+        assert_eq!(args.etag, EtagAlgorithm::Xxhash);
+        assert!(app()
+            .try_get_matches_from(["sfz", "--etag-hash", "--etag", "sha256"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_tls_pair() {
+        // Either of the pair alone is rejected.
+        assert!(app()
+            .try_get_matches_from(["sfz", "--tls-cert", "."])
+            .is_err());
+        assert!(app()
+            .try_get_matches_from(["sfz", "--tls-key", "."])
+            .is_err());
+        let matches = app().get_matches_from(["sfz", "--tls-cert", ".", "--tls-key", "."]);
+        let args = Args::parse(matches).unwrap();
+        assert!(args.tls_cert.is_some() && args.tls_key.is_some());
+    }
+
     #[test]
     fn parse_addresses() {
         // IPv4
@@ -226,4 +1083,48 @@ mod t {
         };
         assert!(args.address().is_err());
     }
+
+    struct FakePrompt(&'static str);
+
+    impl PasswordPrompt for FakePrompt {
+        fn prompt_password(&self, _: &str) -> io::Result<String> {
+            Ok(self.0.to_owned())
+        }
+    }
+
+    #[test]
+    fn parse_credentials() {
+        assert_eq!(
+            Args::parse_credentials("user:se:cret").unwrap(),
+            ("user".to_owned(), "se:cret".to_owned()),
+        );
+        assert!(Args::parse_credentials("user").is_err());
+        assert!(Args::parse_credentials(":secret").is_err());
+        assert!(Args::parse_credentials("user:").is_err());
+
+        let args = Args::parse(app().get_matches_from(["sfz", "--auth", "user:secret"])).unwrap();
+        assert_eq!(args.auth, Some(("user".to_owned(), "secret".to_owned())));
+        assert!(Args::parse(app().get_matches_from(["sfz", "--auth", "user"])).is_err());
+        assert!(app()
+            .try_get_matches_from(["sfz", "--auth", "a:b", "--auth-user", "a"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_auth() {
+        let prompt = FakePrompt("secret");
+        assert_eq!(
+            Args::parse_auth(Some("user"), true, &prompt).unwrap(),
+            Some(("user".to_owned(), "secret".to_owned())),
+        );
+        // No auth at all
+        assert_eq!(Args::parse_auth(None, false, &prompt).unwrap(), None);
+        // Missing password
+        assert!(Args::parse_auth(Some("user"), false, &prompt).is_err());
+        // Invalid username
+        assert!(Args::parse_auth(Some("us:er"), true, &prompt).is_err());
+        assert!(Args::parse_auth(Some(""), true, &prompt).is_err());
+        // Empty password
+        assert!(Args::parse_auth(Some("user"), true, &FakePrompt("")).is_err());
+    }
 }
@@ -6,7 +6,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::path::{Component, Path};
+use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
 use mime_guess::{mime, Mime};
@@ -18,14 +18,14 @@ pub trait PathExt {
     fn is_relatively_hidden(&self) -> bool;
     fn mtime(&self) -> SystemTime;
     fn filename_str(&self) -> &str;
-    fn size(&self) -> u64;
     fn type_(&self) -> PathType;
+    fn lexically_normalize(&self) -> PathBuf;
 }
 
 impl PathExt for Path {
     /// Guess MIME type from a path.
     fn mime(&self) -> Option<Mime> {
-        mime_guess::from_path(&self).first()
+        mime_guess::from_path(self).first()
     }
 
     /// Check if a path is relatively hidden.
@@ -47,11 +47,6 @@ impl PathExt for Path {
         self.metadata().and_then(|meta| meta.modified()).unwrap()
     }
 
-    /// Get file size, in bytes, from a path.
-    fn size(&self) -> u64 {
-        self.metadata().map(|meta| meta.len()).unwrap_or_default()
-    }
-
     /// Get a filename `&str` from a path.
     fn filename_str(&self) -> &str {
         self.file_name()
@@ -74,6 +69,31 @@ impl PathExt for Path {
             })
             .unwrap_or(PathType::File)
     }
+
+    /// Resolve `.` and `..` components textually, without touching the
+    /// filesystem.
+    ///
+    /// Unlike `canonicalize`, symlinks are not resolved, and `..` right after
+    /// a symlink is resolved against the link itself rather than its target.
+    /// Leading `..` of a relative path are kept, while those above the root
+    /// are dropped.
+    fn lexically_normalize(&self) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in self.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => match normalized.components().next_back() {
+                    Some(Component::Normal(_)) => {
+                        normalized.pop();
+                    }
+                    Some(Component::RootDir | Component::Prefix(_)) => {}
+                    _ => normalized.push(".."),
+                },
+                component => normalized.push(component),
+            }
+        }
+        normalized
+    }
 }
 
 pub trait SystemTimeExt {
@@ -91,7 +111,7 @@ impl SystemTimeExt for SystemTime {
 
 pub trait MimeExt {
     fn is_compressed_format(&self) -> bool;
-    fn guess_charset(&self) -> Option<mime::Name>;
+    fn guess_charset(&self) -> Option<mime::Name<'_>>;
 }
 
 impl MimeExt for Mime {
@@ -125,7 +145,7 @@ impl MimeExt for Mime {
     /// - `text/*`, `*/xml`, `*/javascript`, `*/json` -> UTF-8
     /// - `*/*+xml`, `*/*+json` -> UTF-8
     /// - others -> leave it as is
-    fn guess_charset(&self) -> Option<mime::Name> {
+    fn guess_charset(&self) -> Option<mime::Name<'_>> {
         match (self.type_(), self.subtype(), self.suffix()) {
             (mime::TEXT, _, _)
             | (_, mime::XML | mime::JAVASCRIPT | mime::JSON, _)
@@ -138,7 +158,6 @@ impl MimeExt for Mime {
 #[cfg(test)]
 mod t_extensions {
     use super::*;
-    use std::path::PathBuf;
 
     fn file_txt_path() -> PathBuf {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -172,15 +191,24 @@ mod t_extensions {
         assert!(!PathBuf::from(path).is_relatively_hidden());
     }
 
-    #[ignore]
     #[test]
-    fn path_mtime() {}
+    fn path_lexically_normalize() {
+        let cases = [
+            ("/base/./dir/../file", "/base/file"),
+            ("/base/dir/../../etc", "/etc"),
+            ("/../../etc", "/etc"),
+            ("base/../../file", "../file"),
+            ("./dir/", "dir"),
+            ("", ""),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(Path::new(path).lexically_normalize(), Path::new(expected));
+        }
+    }
 
+    #[ignore]
     #[test]
-    fn path_size() {
-        assert_eq!(file_txt_path().size(), 8);
-        assert_eq!(hidden_html_path().size(), 0);
-    }
+    fn path_mtime() {}
 
     #[test]
     fn path_filename_str() {
@@ -224,13 +252,10 @@ mod t_extensions {
             assert!(mime.parse::<mime::Mime>().unwrap().is_compressed_format());
         }
 
-        assert_eq!(
-            "text/*"
-                .parse::<mime::Mime>()
-                .unwrap()
-                .is_compressed_format(),
-            false
-        );
+        assert!(!"text/*"
+            .parse::<mime::Mime>()
+            .unwrap()
+            .is_compressed_format());
     }
 
     #[test]
@@ -22,7 +22,13 @@ use crate::server::Request;
 /// handle caching responses by themselves.
 ///
 /// [1]: https://tools.ietf.org/html/rfc7232#section-6
-pub fn is_precondition_failed(req: &Request, etag: &ETag, last_modified: SystemTime) -> bool {
+///
+/// Without `last_modified`, `If-Unmodified-Since` is ignored.
+pub fn is_precondition_failed(
+    req: &Request,
+    etag: &ETag,
+    last_modified: Option<SystemTime>,
+) -> bool {
     // 3. Evaluate If-None-Match
     let eval_if_none_match = || {
         req.headers().typed_get::<IfNoneMatch>().is_some()
@@ -38,6 +44,7 @@ pub fn is_precondition_failed(req: &Request, etag: &ETag, last_modified: SystemT
 
     // 2. Evaluate If-Unmodified-Since
     let eval_if_unmodified_since = || {
+        let last_modified = last_modified?;
         req.headers()
             .typed_get::<IfUnmodifiedSince>()
             .map(|if_unmodified_since| {
@@ -57,11 +64,15 @@ pub fn is_precondition_failed(req: &Request, etag: &ETag, last_modified: SystemT
 /// See more on [RFC7234, 4.3.2. Handling a Received Validation Request][1].
 ///
 /// [1]: https://tools.ietf.org/html/rfc7234#section-4.3.2
-pub fn is_fresh(req: &Request, etag: &ETag, last_modified: SystemTime) -> bool {
+///
+/// Without `last_modified`, `If-Modified-Since` is ignored.
+pub fn is_fresh(req: &Request, etag: &ETag, last_modified: Option<SystemTime>) -> bool {
     // `If-None-Match` takes presedence over `If-Modified-Since`.
     if let Some(if_none_match) = req.headers().typed_get::<IfNoneMatch>() {
         !if_none_match.precondition_passes(etag)
-    } else if let Some(if_modified_since) = req.headers().typed_get::<IfModifiedSince>() {
+    } else if let (Some(if_modified_since), Some(last_modified)) =
+        (req.headers().typed_get::<IfModifiedSince>(), last_modified)
+    {
         !if_modified_since.is_modified(last_modified)
     } else {
         false
@@ -85,7 +96,7 @@ mod t_precondition {
     #[test]
     fn ok_without_any_precondition() {
         let (req, etag, date) = init_request();
-        assert!(!is_precondition_failed(&req, &etag, date));
+        assert!(!is_precondition_failed(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -93,7 +104,7 @@ mod t_precondition {
         let (mut req, etag, date) = init_request();
         let if_match = IfMatch::from("\"\"".to_string().parse::<ETag>().unwrap());
         req.headers_mut().typed_insert(if_match);
-        assert!(is_precondition_failed(&req, &etag, date));
+        assert!(is_precondition_failed(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -104,10 +115,10 @@ mod t_precondition {
         req.headers_mut().typed_insert(if_match);
         req.headers_mut().typed_insert(if_none_match);
         // OK with GET HEAD methods
-        assert!(!is_precondition_failed(&req, &etag, date));
+        assert!(!is_precondition_failed(&req, &etag, Some(date)));
         // Failed with method other than GET HEAD
         *req.method_mut() = Method::PUT;
-        assert!(is_precondition_failed(&req, &etag, date));
+        assert!(is_precondition_failed(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -116,7 +127,7 @@ mod t_precondition {
         let past = date - Duration::from_secs(1);
         let if_unmodified_since = IfUnmodifiedSince::from(past);
         req.headers_mut().typed_insert(if_unmodified_since);
-        assert!(is_precondition_failed(&req, &etag, date));
+        assert!(is_precondition_failed(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -127,10 +138,19 @@ mod t_precondition {
         req.headers_mut().typed_insert(if_unmodified_since);
         req.headers_mut().typed_insert(if_none_match);
         // OK with GET HEAD methods
-        assert!(!is_precondition_failed(&req, &etag, date));
+        assert!(!is_precondition_failed(&req, &etag, Some(date)));
         // Failed with method other than GET HEAD
         *req.method_mut() = Method::PUT;
-        assert!(is_precondition_failed(&req, &etag, date));
+        assert!(is_precondition_failed(&req, &etag, Some(date)));
+    }
+
+    #[test]
+    fn ignore_if_unmodified_since_without_last_modified() {
+        let (mut req, etag, date) = init_request();
+        let past = date - Duration::from_secs(1);
+        req.headers_mut()
+            .typed_insert(IfUnmodifiedSince::from(past));
+        assert!(!is_precondition_failed(&req, &etag, None));
     }
 }
 
@@ -142,7 +162,7 @@ mod t_fresh {
     #[test]
     fn no_precondition_header_fields() {
         let (req, etag, date) = init_request();
-        assert!(!is_fresh(&req, &etag, date));
+        assert!(!is_fresh(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -153,7 +173,7 @@ mod t_fresh {
         let if_modified_since = IfModifiedSince::from(future);
         req.headers_mut().typed_insert(if_none_match);
         req.headers_mut().typed_insert(if_modified_since);
-        assert!(is_fresh(&req, &etag, date));
+        assert!(is_fresh(&req, &etag, Some(date)));
     }
 
     #[test]
@@ -162,6 +182,15 @@ mod t_fresh {
         let future = date + Duration::from_secs(1);
         let if_modified_since = IfModifiedSince::from(future);
         req.headers_mut().typed_insert(if_modified_since);
-        assert!(is_fresh(&req, &etag, date));
+        assert!(is_fresh(&req, &etag, Some(date)));
+    }
+
+    #[test]
+    fn ignore_if_modified_since_without_last_modified() {
+        let (mut req, etag, date) = init_request();
+        let future = date + Duration::from_secs(1);
+        req.headers_mut()
+            .typed_insert(IfModifiedSince::from(future));
+        assert!(!is_fresh(&req, &etag, None));
     }
 }
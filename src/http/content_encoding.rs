@@ -9,10 +9,10 @@
 use std::cmp::Ordering;
 use std::io;
 
-use async_compression::{
-    tokio::bufread::{BrotliEncoder, DeflateEncoder, GzipEncoder},
-    Level,
+use async_compression::tokio::bufread::{
+    BrotliEncoder, DeflateEncoder, GzipDecoder, GzipEncoder, ZstdEncoder,
 };
+pub use async_compression::Level;
 use bytes::Bytes;
 use futures::Stream;
 use hyper::header::HeaderValue;
@@ -23,6 +23,8 @@ pub const IDENTITY: &str = "identity";
 pub const DEFLATE: &str = "deflate";
 pub const GZIP: &str = "gzip";
 pub const BR: &str = "br";
+pub const ZSTD: &str = "zstd";
+pub const ANY: &str = "*";
 
 /// Inner helper type to store quality values.
 ///
@@ -37,6 +39,7 @@ enum Encoding {
     Identity,
     Deflate,
     Gzip,
+    Zstandard,
     Brotli,
 }
 
@@ -45,6 +48,7 @@ impl From<&str> for Encoding {
         match s {
             DEFLATE => Self::Deflate,
             GZIP => Self::Gzip,
+            ZSTD => Self::Zstandard,
             BR => Self::Brotli,
             _ => Self::Identity,
         }
@@ -52,10 +56,11 @@ impl From<&str> for Encoding {
 }
 
 /// This match expression is necessary to return a `&'static str`.
-pub fn encoding_to_static_str<'a>(encoding: &'a str) -> &'static str {
+pub fn encoding_to_static_str(encoding: &str) -> &'static str {
     match encoding {
         DEFLATE => DEFLATE,
         GZIP => GZIP,
+        ZSTD => ZSTD,
         BR => BR,
         _ => IDENTITY,
     }
@@ -64,7 +69,7 @@ pub fn encoding_to_static_str<'a>(encoding: &'a str) -> &'static str {
 /// Sorting encodings according to the weight of quality values and then the
 /// intrinsic rank of `Encoding` enum varaint.
 ///
-/// The function only accecpt Brotli, Gzip and Deflate encodings, passing other
+/// The function only accecpt Brotli, Zstandard, Gzip and Deflate encodings, passing other
 /// encodings in may lead to a unexpected result.
 fn sort_encoding(a: &QualityValue, b: &QualityValue) -> Ordering {
     a.1.cmp(&b.1)
@@ -87,7 +92,7 @@ fn sort_encoding(a: &QualityValue, b: &QualityValue) -> Ordering {
 /// - We define unrecognized qvalue as zero.
 ///
 /// [1]: https://tools.ietf.org/html/rfc7231#section-5.3.1
-fn parse_qvalue(q: &str) -> Option<QualityValue> {
+fn parse_qvalue(q: &str) -> Option<QualityValue<'_>> {
     let mut iter = q.trim().split_terminator(';').take(2);
     let content = iter.next().map(str::trim_end)?;
     let weight = match iter.next() {
@@ -108,9 +113,11 @@ fn parse_qvalue(q: &str) -> Option<QualityValue> {
 ///
 /// Note that:
 ///
-/// - Only accept `br` / `gzip` / `deflate`
+/// - Only accept `br` / `zstd` / `gzip` / `deflate`
 /// - Highest non-zero qvalue is preferred.
-pub fn get_prior_encoding<'a>(accept_encoding: &'a HeaderValue) -> &'static str {
+/// - The `*` wildcard stands for the most preferred encoding not listed
+///   explicitly, in the order of `br`, `zstd`, `gzip` and `deflate`.
+pub fn get_prior_encoding(accept_encoding: &HeaderValue) -> &'static str {
     accept_encoding
         .to_str()
         .ok()
@@ -122,33 +129,96 @@ pub fn get_prior_encoding<'a>(accept_encoding: &'a HeaderValue) -> &'static str
             // Sort by quality value, than by encoding type.
             quality_values.sort_unstable_by(sort_encoding);
             // Get the last encoding (highest priority).
-            quality_values.last().map(|q| encoding_to_static_str(q.0))
+            let prior = quality_values.last().filter(|q| q.1 > 0)?;
+            if prior.0 != ANY {
+                return Some(encoding_to_static_str(prior.0));
+            }
+            [BR, ZSTD, GZIP, DEFLATE]
+                .into_iter()
+                .find(|encoding| quality_values.iter().all(|q| q.0 != *encoding))
         })
         // Default using identity encoding, which means no content encoding.
         .unwrap_or(IDENTITY)
 }
 
+/// Get the weight of given encoding from `Accept-Encoding` header field,
+/// from 0 (unacceptable) to 1000.
+///
+/// Encodings not listed explicitly take the weight of the `*` wildcard, if
+/// any.
+pub fn get_encoding_quality(accept_encoding: &HeaderValue, encoding: &str) -> u32 {
+    let quality_values = accept_encoding
+        .to_str()
+        .map(|accept_encoding| {
+            accept_encoding
+                .split(',')
+                .filter_map(parse_qvalue)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let weight_of = |content: &str| quality_values.iter().find(|q| q.0 == content).map(|q| q.1);
+    weight_of(encoding)
+        .or_else(|| weight_of(ANY))
+        .unwrap_or_default()
+}
+
 /// Compress data stream.
 ///
 /// # Parameters
 ///
 /// * `input` - [`futures::stream::Stream`] to be compressed, e.g. [`hyper::body::Body`].
-/// * `encoding` - Only support `br`, `deflate`, `gzip` and `identity`.
+/// * `encoding` - Only support `br`, `zstd`, `deflate`, `gzip` and `identity`.
+/// * `level` - Compression level. `None` uses the default level of each
+///   encoding, which is the fastest one for brotli. Precise levels beyond
+///   the maximum of an encoding are clamped.
 pub fn compress_stream(
     input: impl Stream<Item = io::Result<Bytes>> + Send + 'static,
     encoding: &str,
+    level: Option<Level>,
 ) -> io::Result<hyper::Body> {
+    // Levels above 9 are not part of the zlib format.
+    let flate_level = level.map(|level| match level {
+        Level::Precise(quality) => Level::Precise(quality.min(9)),
+        level => level,
+    });
     match encoding {
         BR => Ok(Body::wrap_stream(ReaderStream::new(
-            BrotliEncoder::with_quality(StreamReader::new(input), Level::Fastest),
+            BrotliEncoder::with_quality(StreamReader::new(input), level.unwrap_or(Level::Fastest)),
         ))),
-        DEFLATE => Ok(Body::wrap_stream(ReaderStream::new(DeflateEncoder::new(
-            StreamReader::new(input),
-        )))),
-        GZIP => Ok(Body::wrap_stream(ReaderStream::new(GzipEncoder::new(
+        ZSTD => Ok(Body::wrap_stream(ReaderStream::new(
+            ZstdEncoder::with_quality(StreamReader::new(input), level.unwrap_or(Level::Default)),
+        ))),
+        DEFLATE => Ok(Body::wrap_stream(ReaderStream::new(
+            DeflateEncoder::with_quality(
+                StreamReader::new(input),
+                flate_level.unwrap_or(Level::Default),
+            ),
+        ))),
+        GZIP => Ok(Body::wrap_stream(ReaderStream::new(
+            GzipEncoder::with_quality(
+                StreamReader::new(input),
+                flate_level.unwrap_or(Level::Default),
+            ),
+        ))),
+        _ => Err(io::Error::other("Unsupported Encoding")),
+    }
+}
+
+/// Decompress data stream.
+///
+/// # Parameters
+///
+/// * `input` - [`futures::stream::Stream`] to be decompressed.
+/// * `encoding` - Only support `gzip`.
+pub fn decompress_stream(
+    input: impl Stream<Item = io::Result<Bytes>> + Send + 'static,
+    encoding: &str,
+) -> io::Result<hyper::Body> {
+    match encoding {
+        GZIP => Ok(Body::wrap_stream(ReaderStream::new(GzipDecoder::new(
             StreamReader::new(input),
         )))),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Unsupported Encoding")),
+        _ => Err(io::Error::other("Unsupported Encoding")),
     }
 }
 
@@ -192,6 +262,9 @@ mod t_sort {
         let brotli = &QualityValue(BR, 1000);
         let gzip = &QualityValue(GZIP, 1000);
         let deflate = &QualityValue(DEFLATE, 1000);
+        let zstd = &QualityValue(ZSTD, 1000);
+        assert_eq!(sort_encoding(brotli, zstd), Ordering::Greater);
+        assert_eq!(sort_encoding(zstd, gzip), Ordering::Greater);
         assert_eq!(sort_encoding(brotli, gzip), Ordering::Greater);
         assert_eq!(sort_encoding(brotli, deflate), Ordering::Greater);
         assert_eq!(sort_encoding(gzip, deflate), Ordering::Greater);
@@ -233,6 +306,9 @@ mod t_prior {
             (BR, "deflate,gzip,br"),
             (BR, "br;q=0.8,gzip;q=0.5,deflate;q=0.2"),
             (GZIP, "br;q=0.5,gzip,deflate;q=0.8"),
+            (ZSTD, "gzip,zstd,deflate"),
+            (BR, "zstd,br"),
+            (ZSTD, "br;q=0.5,zstd"),
         ];
         for case in cases {
             let accept_encoding = HeaderValue::from_static(case.1);
@@ -241,6 +317,36 @@ mod t_prior {
         }
     }
 
+    #[test]
+    fn wildcard() {
+        let cases = vec![
+            (BR, "*"),
+            (BR, "gzip;q=0.5,*"),
+            (ZSTD, "br;q=0,*"),
+            (GZIP, "br;q=0,zstd;q=0,*"),
+            (GZIP, "gzip,*;q=0.5"),
+            (IDENTITY, "br;q=0,zstd;q=0,gzip;q=0,deflate;q=0,*"),
+            (IDENTITY, "*;q=0"),
+        ];
+        for case in cases {
+            let accept_encoding = HeaderValue::from_static(case.1);
+            let encoding = get_prior_encoding(&accept_encoding);
+            assert_eq!(encoding, case.0, "failed on case: {:?}", case);
+        }
+    }
+
+    #[test]
+    fn encoding_quality() {
+        let accept_encoding = HeaderValue::from_static("br;q=0.5, gzip;q=0, *;q=0.2");
+        assert_eq!(get_encoding_quality(&accept_encoding, BR), 500);
+        assert_eq!(get_encoding_quality(&accept_encoding, GZIP), 0);
+        assert_eq!(get_encoding_quality(&accept_encoding, ZSTD), 200);
+
+        let accept_encoding = HeaderValue::from_static("gzip");
+        assert_eq!(get_encoding_quality(&accept_encoding, GZIP), 1000);
+        assert_eq!(get_encoding_quality(&accept_encoding, BR), 0);
+    }
+
     #[test]
     fn filter_out_zero_quality() {
         let accept_encoding = HeaderValue::from_static("brotli;q=0,gzip;q=0,deflate");
@@ -256,22 +362,96 @@ mod t_compress {
     #[test]
     fn failed() {
         let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"hello"))]);
-        let error = compress_stream(s, "unrecognized").unwrap_err();
+        let error = compress_stream(s, "unrecognized", None).unwrap_err();
         assert_eq!(error.kind(), io::ErrorKind::Other);
     }
 
     #[tokio::test]
     async fn compressed() {
         let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
-        let body = compress_stream(s, BR).unwrap();
+        let body = compress_stream(s, BR, None).unwrap();
         assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 9);
 
         let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
-        let body = compress_stream(s, DEFLATE).unwrap();
+        let body = compress_stream(s, DEFLATE, None).unwrap();
         assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 5);
 
         let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
-        let body = compress_stream(s, GZIP).unwrap();
+        let body = compress_stream(s, GZIP, None).unwrap();
         assert_eq!(hyper::body::to_bytes(body).await.unwrap().len(), 23);
+
+        let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
+        let body = compress_stream(s, ZSTD, None).unwrap();
+        let compressed = hyper::body::to_bytes(body).await.unwrap();
+        // Zstandard frames start with the magic number 0xFD2FB528.
+        assert!(compressed.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+    }
+
+    #[test]
+    fn zstd_to_static_str() {
+        assert_eq!(encoding_to_static_str(ZSTD), ZSTD);
+        assert_eq!(Encoding::from(ZSTD), Encoding::Zstandard);
+    }
+
+    #[tokio::test]
+    async fn decompressed() {
+        let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
+        let compressed = hyper::body::to_bytes(compress_stream(s, GZIP, None).unwrap())
+            .await
+            .unwrap();
+        let s = futures::stream::iter(vec![Ok::<_, io::Error>(compressed)]);
+        let body = decompress_stream(s, GZIP).unwrap();
+        assert_eq!(&hyper::body::to_bytes(body).await.unwrap()[..], b"xxxxx");
+
+        let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"xxxxx"))]);
+        let error = decompress_stream(s, BR).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn compressed_with_level() {
+        let text = (0..2000)
+            .map(|i| format!("line {}\n", i % 97))
+            .collect::<String>();
+        let compress = |level| {
+            let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from(text.clone()))]);
+            compress_stream(s, BR, level).unwrap()
+        };
+        let fastest = hyper::body::to_bytes(compress(None)).await.unwrap();
+        let best = hyper::body::to_bytes(compress(Some(Level::Best)))
+            .await
+            .unwrap();
+        assert!(best.len() < fastest.len());
+
+        // Precise levels are passed through, and clamped to the maximum.
+        let precise = hyper::body::to_bytes(compress(Some(Level::Precise(11))))
+            .await
+            .unwrap();
+        assert_eq!(precise, best);
+        for encoding in [GZIP, DEFLATE, ZSTD] {
+            let compress = |level| {
+                let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from(text.clone()))]);
+                compress_stream(s, encoding, Some(level)).unwrap()
+            };
+            let fast = hyper::body::to_bytes(compress(Level::Precise(1)))
+                .await
+                .unwrap();
+            let clamped = hyper::body::to_bytes(compress(Level::Precise(11)))
+                .await
+                .unwrap();
+            assert!(clamped.len() < fast.len());
+        }
+        let clamped = |level| {
+            let s = futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from(text.clone()))]);
+            compress_stream(s, GZIP, Some(level)).unwrap()
+        };
+        assert_eq!(
+            hyper::body::to_bytes(clamped(Level::Precise(11)))
+                .await
+                .unwrap(),
+            hyper::body::to_bytes(clamped(Level::Precise(9)))
+                .await
+                .unwrap(),
+        );
     }
 }
@@ -6,6 +6,8 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::ops::Bound::{self, Included, Unbounded};
+
 use headers::{ContentRange, ETag, HeaderMapExt, IfRange, LastModified, Range};
 
 use crate::server::Request;
@@ -14,7 +16,9 @@ use crate::server::Request;
 ///
 /// According to RFC7232, to validate `If-Range` header, the implementation
 /// must use a strong comparison.
-pub fn is_range_fresh(req: &Request, etag: &ETag, last_modified: &LastModified) -> bool {
+///
+/// Without `last_modified`, a date in `If-Range` never matches.
+pub fn is_range_fresh(req: &Request, etag: &ETag, last_modified: Option<&LastModified>) -> bool {
     // Ignore `If-Range` if `Range` header is not present.
     if req.headers().typed_get::<Range>().is_none() {
         return false;
@@ -22,53 +26,111 @@ pub fn is_range_fresh(req: &Request, etag: &ETag, last_modified: &LastModified)
 
     req.headers()
         .typed_get::<IfRange>()
-        .map(|if_range| !if_range.is_modified(Some(etag), Some(last_modified)))
+        .map(|if_range| !if_range.is_modified(Some(etag), last_modified))
         // Always be fresh if there is no validators
         .unwrap_or(true)
 }
 
+/// Maximum number of byte-ranges in a request. Requests with more are
+/// ignored, so that clients cannot make a file sent many times over.
+const MAX_RANGES: usize = 32;
+
+/// Outcome of evaluating `Range` header field against a representation.
+#[derive(Debug, PartialEq)]
+pub enum RangeResult {
+    /// One satisfiable byte-range, as `Content-Range` of the response.
+    Satisfiable(ContentRange),
+    /// Two or more satisfiable byte-ranges, as `Content-Range` of each part
+    /// of a `multipart/byteranges` response.
+    Multiple(Vec<ContentRange>),
+    /// No byte-range is satisfiable, which must be responded with 416.
+    Unsatisfiable,
+    /// Ranges not supported or invalid, which are ignored to respond the
+    /// entire entity.
+    Ignored,
+}
+
 /// Convert `Range` header field in incoming request to `Content-Range` header
 /// field for response.
 ///
-/// Here are all situations mapped to returning `Option`:
+/// Here are all situations mapped to returning `RangeResult`:
 ///
-/// - None byte-range -> None
-/// - One satisfiable byte-range -> Some
-/// - One not satisfiable byte-range -> None
-/// - Two or more byte-ranges -> None
-/// - bytes-units are not in "bytes" -> None
+/// - None byte-range -> Ignored
+/// - Any invalid byte-range -> Ignored
+/// - More than `MAX_RANGES` byte-ranges -> Ignored
+/// - No satisfiable byte-range -> Unsatisfiable
+/// - One satisfiable byte-range -> Satisfiable
+/// - Two or more satisfiable byte-ranges -> Multiple
 ///
-/// A satisfiable byte range must conform to following criteria:
+/// Unsatisfiable byte-ranges among satisfiable ones are dropped. Satisfiable
+/// ones are sorted and coalesced when they overlap or are adjacent, so no
+/// byte is sent twice however the ranges are crafted.
+///
+/// A byte range is invalid if the last-byte-pos is present and less than
+/// the first-byte-pos. A valid byte range is satisfiable if it conforms to
+/// following criteria:
 ///
-/// - Invalid if the last-byte-pos is present and less than the first-byte-pos.
 /// - First-byte-pos must be less than complete length of the representation.
 /// - If suffix-byte-range-spec is present, it must not be zero.
-pub fn is_satisfiable_range(range: &Range, complete_length: u64) -> Option<ContentRange> {
-    use core::ops::Bound::{Included, Unbounded};
-    let mut iter = range.iter();
-    let bounds = iter.next();
-
-    if iter.next().is_some() {
-        // Found multiple byte-range-spec. Drop.
-        return None;
+pub fn is_satisfiable_range(range: &Range, complete_length: u64) -> RangeResult {
+    let results = range
+        .iter()
+        .take(MAX_RANGES + 1)
+        .map(|bounds| evaluate_range(bounds, complete_length))
+        .collect::<Vec<_>>();
+    if results.is_empty() || results.len() > MAX_RANGES || results.contains(&RangeResult::Ignored) {
+        return RangeResult::Ignored;
+    }
+    let mut ranges = results
+        .into_iter()
+        .filter_map(|result| match result {
+            RangeResult::Satisfiable(content_range) => content_range.bytes_range(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut content_ranges = coalesce(&mut ranges)
+        .into_iter()
+        .filter_map(|(start, end)| ContentRange::bytes(start..=end, complete_length).ok())
+        .collect::<Vec<_>>();
+    match content_ranges.len() {
+        0 => RangeResult::Unsatisfiable,
+        1 => RangeResult::Satisfiable(content_ranges.remove(0)),
+        _ => RangeResult::Multiple(content_ranges),
     }
+}
 
-    bounds.and_then(|b| match b {
-        (Included(start), Included(end)) if start <= end && start < complete_length => {
-            ContentRange::bytes(
-                start..=end.min(complete_length.saturating_sub(1)),
-                complete_length,
-            )
-            .ok()
+/// Merge overlapping or adjacent inclusive byte ranges, in ascending order.
+fn coalesce(ranges: &mut [(u64, u64)]) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for &(start, end) in ranges.iter() {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = end.max(*last_end);
+            }
+            _ => merged.push((start, end)),
         }
+    }
+    merged
+}
+
+/// Evaluate one byte-range-spec, which is never `Multiple`.
+fn evaluate_range(bounds: (Bound<u64>, Bound<u64>), complete_length: u64) -> RangeResult {
+    let content_range = match bounds {
+        (Included(start), Included(end)) if start > end => return RangeResult::Ignored,
+        (Included(start), Included(end)) if start < complete_length => ContentRange::bytes(
+            start..=end.min(complete_length.saturating_sub(1)),
+            complete_length,
+        ),
         (Included(start), Unbounded) if start < complete_length => {
-            ContentRange::bytes(start.., complete_length).ok()
+            ContentRange::bytes(start.., complete_length)
         }
-        (Unbounded, Included(end)) if end > 0 => {
-            ContentRange::bytes(complete_length.saturating_sub(end).., complete_length).ok()
+        (Unbounded, Included(end)) if end > 0 && complete_length > 0 => {
+            ContentRange::bytes(complete_length.saturating_sub(end).., complete_length)
         }
-        _ => None,
-    })
+        _ => return RangeResult::Unsatisfiable,
+    };
+    content_range.map_or(RangeResult::Unsatisfiable, RangeResult::Satisfiable)
 }
 
 #[cfg(test)]
@@ -84,7 +146,7 @@ mod t_range {
         let etag = &"\"strong\"".to_string().parse::<ETag>().unwrap();
         let if_range = IfRange::etag(etag.clone());
         req.headers_mut().typed_insert(if_range);
-        assert!(!is_range_fresh(req, etag, last_modified));
+        assert!(!is_range_fresh(req, etag, Some(last_modified)));
     }
 
     #[test]
@@ -95,7 +157,7 @@ mod t_range {
         let last_modified = &LastModified::from(SystemTime::now());
         let etag = &"\"strong\"".to_string().parse::<ETag>().unwrap();
         // Always be fresh if there is no validators
-        assert!(is_range_fresh(req, etag, last_modified));
+        assert!(is_range_fresh(req, etag, Some(last_modified)));
     }
 
     #[test]
@@ -107,7 +169,7 @@ mod t_range {
         let etag = &"W/\"weak\"".to_string().parse::<ETag>().unwrap();
         let if_range = IfRange::etag(etag.clone());
         req.headers_mut().typed_insert(if_range);
-        assert!(!is_range_fresh(req, etag, last_modified));
+        assert!(!is_range_fresh(req, etag, Some(last_modified)));
     }
 
     #[test]
@@ -120,12 +182,15 @@ mod t_range {
 
         // Same date.
         req.headers_mut().typed_insert(IfRange::date(date));
-        assert!(is_range_fresh(req, etag, last_modified));
+        assert!(is_range_fresh(req, etag, Some(last_modified)));
+
+        // Without last modified time.
+        assert!(!is_range_fresh(req, etag, None));
 
         // Before 10 sec.
         let past = date - Duration::from_secs(10);
         req.headers_mut().typed_insert(IfRange::date(past));
-        assert!(!is_range_fresh(req, etag, last_modified));
+        assert!(!is_range_fresh(req, etag, Some(last_modified)));
 
         // After 10 sec.
         //
@@ -139,7 +204,7 @@ mod t_range {
         // [2]: https://github.com/hyperium/headers/blob/2e8c12b/src/common/if_range.rs#L66
         let future = date + Duration::from_secs(10);
         req.headers_mut().typed_insert(IfRange::date(future));
-        // assert!(!is_range_fresh(req, etag, last_modified));
+        // assert!(!is_range_fresh(req, etag, Some(last_modified)));
     }
 
     #[test]
@@ -151,7 +216,7 @@ mod t_range {
         let etag = &"\"strong\"".to_string().parse::<ETag>().unwrap();
         let if_range = IfRange::etag(etag.clone());
         req.headers_mut().typed_insert(if_range);
-        assert!(is_range_fresh(req, etag, last_modified));
+        assert!(is_range_fresh(req, etag, Some(last_modified)));
     }
 }
 
@@ -162,7 +227,7 @@ mod t_satisfiable {
     #[test]
     fn zero_byte_range() {
         let range = &Range::bytes(1..1).unwrap();
-        assert!(is_satisfiable_range(range, 10).is_none());
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Ignored);
     }
 
     #[test]
@@ -172,7 +237,7 @@ mod t_satisfiable {
         let content_range = is_satisfiable_range(range, complete_length);
         assert_eq!(
             content_range,
-            ContentRange::bytes(4..7, complete_length).ok()
+            RangeResult::Satisfiable(ContentRange::bytes(4..7, complete_length).unwrap())
         );
 
         // only first-byte-pos and retrieve to the end
@@ -181,7 +246,7 @@ mod t_satisfiable {
         let content_range = is_satisfiable_range(range, complete_length);
         assert_eq!(
             content_range,
-            ContentRange::bytes(3..10, complete_length).ok()
+            RangeResult::Satisfiable(ContentRange::bytes(3..10, complete_length).unwrap())
         );
 
         // last-byte-pos exceeds complete length
@@ -190,7 +255,7 @@ mod t_satisfiable {
         let content_range = is_satisfiable_range(range, complete_length);
         assert_eq!(
             content_range,
-            ContentRange::bytes(7..10, complete_length).ok()
+            RangeResult::Satisfiable(ContentRange::bytes(7..10, complete_length).unwrap())
         );
 
         // suffix-byte-range-spec
@@ -199,7 +264,7 @@ mod t_satisfiable {
         let content_range = is_satisfiable_range(range, complete_length);
         assert_eq!(
             content_range,
-            ContentRange::bytes(7..10, complete_length).ok()
+            RangeResult::Satisfiable(ContentRange::bytes(7..10, complete_length).unwrap())
         );
 
         // suffix-byte-range-spec greater than complete length
@@ -208,7 +273,7 @@ mod t_satisfiable {
         let content_range = is_satisfiable_range(range, complete_length);
         assert_eq!(
             content_range,
-            ContentRange::bytes(0..10, complete_length).ok()
+            RangeResult::Satisfiable(ContentRange::bytes(0..10, complete_length).unwrap())
         );
     }
 
@@ -216,11 +281,15 @@ mod t_satisfiable {
     fn one_unsatisfiable_byte_range() {
         // First-byte-pos is greater than complete length.
         let range = &Range::bytes(20..).unwrap();
-        assert!(is_satisfiable_range(range, 10).is_none());
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Unsatisfiable);
+        let range = &Range::bytes(20..30).unwrap();
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Unsatisfiable);
 
-        // Last-bypte-pos is less than first-byte-pos
-        let range = &Range::bytes(5..3).unwrap();
-        assert!(is_satisfiable_range(range, 10).is_none());
+        // Nothing is satisfiable for an empty representation.
+        let range = &Range::bytes(0..).unwrap();
+        assert_eq!(is_satisfiable_range(range, 0), RangeResult::Unsatisfiable);
+        let range = &Range::bytes(..=3).unwrap();
+        assert_eq!(is_satisfiable_range(range, 0), RangeResult::Unsatisfiable);
 
         // suffix-byte-range-spec must be non-zero
         let mut headers = headers::HeaderMap::new();
@@ -229,17 +298,75 @@ mod t_satisfiable {
             headers::HeaderValue::from_static("bytes=-0"),
         );
         let range = &headers.typed_get::<Range>().unwrap();
-        assert!(is_satisfiable_range(range, 10).is_none());
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Unsatisfiable);
     }
 
     #[test]
-    fn multiple_byte_ranges() {
+    fn invalid_byte_range() {
+        // Last-bypte-pos is less than first-byte-pos
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = &Range::bytes(5..3).unwrap();
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Ignored);
+    }
+
+    fn parse_range(value: &str) -> Range {
         let mut headers = headers::HeaderMap::new();
         headers.insert(
             hyper::header::RANGE,
-            headers::HeaderValue::from_static("bytes=0-1,30-40"),
+            headers::HeaderValue::from_str(value).unwrap(),
         );
-        let range = &headers.typed_get::<Range>().unwrap();
-        assert!(is_satisfiable_range(range, 10).is_none());
+        headers.typed_get::<Range>().unwrap()
+    }
+
+    #[test]
+    fn multiple_byte_ranges() {
+        let range = &parse_range("bytes=4-5,0-1,-2");
+        assert_eq!(
+            is_satisfiable_range(range, 10),
+            RangeResult::Multiple(vec![
+                ContentRange::bytes(0..2, 10).unwrap(),
+                ContentRange::bytes(4..6, 10).unwrap(),
+                ContentRange::bytes(8..10, 10).unwrap(),
+            ])
+        );
+
+        // Overlapping and adjacent byte-ranges are coalesced.
+        let range = &parse_range("bytes=0-1,4-,-2");
+        assert_eq!(
+            is_satisfiable_range(range, 10),
+            RangeResult::Multiple(vec![
+                ContentRange::bytes(0..2, 10).unwrap(),
+                ContentRange::bytes(4..10, 10).unwrap(),
+            ])
+        );
+        let range = &parse_range("bytes=0-3,4-5,2-6");
+        assert_eq!(
+            is_satisfiable_range(range, 10),
+            RangeResult::Satisfiable(ContentRange::bytes(0..7, 10).unwrap())
+        );
+        let many = vec!["0-"; MAX_RANGES].join(",");
+        let range = &parse_range(&format!("bytes={}", many));
+        assert_eq!(
+            is_satisfiable_range(range, 10),
+            RangeResult::Satisfiable(ContentRange::bytes(0..10, 10).unwrap())
+        );
+
+        // Unsatisfiable byte-ranges are dropped.
+        let range = &parse_range("bytes=0-1,30-40");
+        assert_eq!(
+            is_satisfiable_range(range, 10),
+            RangeResult::Satisfiable(ContentRange::bytes(0..2, 10).unwrap())
+        );
+        let range = &parse_range("bytes=20-,30-40");
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Unsatisfiable);
+
+        // Any invalid byte-range invalidates all.
+        let range = &parse_range("bytes=0-1,5-3");
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Ignored);
+
+        // Too many byte-ranges.
+        let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        let range = &parse_range(&format!("bytes={}", many));
+        assert_eq!(is_satisfiable_range(range, 10), RangeResult::Ignored);
     }
 }
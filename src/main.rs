@@ -19,20 +19,41 @@ mod server;
 #[cfg(test)]
 pub mod test_utils;
 
+use std::time::Duration;
+
 use crate::cli::{matches, Args};
-use crate::server::serve;
+use crate::server::{spawn, ShutdownTrigger};
 
 pub type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// Time for in-flight responses to finish after Ctrl-C before exiting anyway.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() {
-    Args::parse(matches())
-        .map(serve)
-        .unwrap_or_else(handle_err)
+    let (server, trigger) = Args::parse(matches())
+        .and_then(spawn)
+        .unwrap_or_else(handle_err);
+    tokio::spawn(shutdown_on_ctrl_c(trigger));
+    server
         .await
+        .map_err(From::from)
+        .and_then(|res| res.map_err(From::from))
         .unwrap_or_else(handle_err);
 }
 
+/// Shut down gracefully on Ctrl-C. Exit at once on a second Ctrl-C, or when
+/// draining takes longer than `DRAIN_TIMEOUT`.
+async fn shutdown_on_ctrl_c(trigger: ShutdownTrigger) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    trigger.shutdown();
+    eprintln!("Shutting down, press Ctrl-C again to force");
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, tokio::signal::ctrl_c()).await;
+    std::process::exit(130);
+}
+
 fn handle_err<T>(err: Box<dyn std::error::Error>) -> T {
     eprintln!("Server error: {}", err);
     std::process::exit(1);
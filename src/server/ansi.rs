@@ -0,0 +1,115 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Stream adapter stripping ANSI escape sequences.
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Position inside an escape sequence.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+enum State {
+    /// Plain text.
+    #[default]
+    Ground,
+    /// Right after `ESC`.
+    Escape,
+    /// Inside `ESC` followed by intermediate bytes, e.g. `ESC ( B`.
+    EscapeIntermediate,
+    /// Inside Control Sequence Introducer, e.g. `ESC [ 3 1 m`.
+    Csi,
+    /// Inside Operating System Command, e.g. `ESC ] 0 ; title BEL`.
+    Osc,
+    /// Right after `ESC` inside an OSC, which might be a String Terminator.
+    OscEscape,
+}
+
+/// Stateful stripper, so that sequences split across chunks are still
+/// recognized.
+#[derive(Debug, Default)]
+pub struct AnsiStripper {
+    state: State,
+}
+
+impl AnsiStripper {
+    /// Strip escape sequences from a chunk, remembering any incomplete
+    /// sequence at the end of the chunk.
+    pub fn strip(&mut self, input: &[u8]) -> Bytes {
+        let mut output = BytesMut::with_capacity(input.len());
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Ground, ESC) => State::Escape,
+                (State::Ground, _) => {
+                    output.extend_from_slice(&[byte]);
+                    State::Ground
+                }
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']') => State::Osc,
+                (State::Escape | State::EscapeIntermediate, 0x20..=0x2f) => {
+                    State::EscapeIntermediate
+                }
+                (State::Escape | State::EscapeIntermediate, _) => State::Ground,
+                (State::Csi, 0x40..=0x7e) => State::Ground,
+                (State::Csi, _) => State::Csi,
+                (State::Osc, BEL) => State::Ground,
+                (State::Osc, ESC) => State::OscEscape,
+                (State::Osc, _) => State::Osc,
+                (State::OscEscape, b'\\') => State::Ground,
+                (State::OscEscape, _) => State::Osc,
+            };
+        }
+        output.freeze()
+    }
+}
+
+/// Strip ANSI escape sequences from every chunk of a byte stream.
+pub fn strip_ansi<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    let mut stripper = AnsiStripper::default();
+    stream.map_ok(move |chunk| stripper.strip(&chunk))
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn strip_sequences() {
+        let mut stripper = AnsiStripper::default();
+        let input = b"\x1b[1;31mred\x1b[0m \x1b]0;title\x07plain \x1b(Bcharset\x1b]8;;\x1b\\";
+        assert_eq!(&stripper.strip(input)[..], b"red plain charset");
+        assert_eq!(stripper.state, State::Ground);
+    }
+
+    #[test]
+    fn strip_sequences_across_chunks() {
+        let mut stripper = AnsiStripper::default();
+        assert_eq!(&stripper.strip(b"a\x1b")[..], b"a");
+        assert_eq!(&stripper.strip(b"[3")[..], b"");
+        assert_eq!(&stripper.strip(b"2mb\x1b[0")[..], b"b");
+        assert_eq!(&stripper.strip(b"mc")[..], b"c");
+    }
+
+    #[tokio::test]
+    async fn strip_stream() {
+        let chunks = ["\x1b[3", "2mgreen\x1b", "[0m"]
+            .into_iter()
+            .map(|s| Ok::<_, std::io::Error>(Bytes::from(s)));
+        let stripped = strip_ansi(futures::stream::iter(chunks))
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(&stripped[..], b"green");
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Entity tags of files, either from metadata or content digests, and of
+//! generated content.
+
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use headers::{ETag, Header};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::extensions::SystemTimeExt;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Coarsest resolution of file timestamps among common filesystems, which
+/// is 2 seconds of FAT.
+const TIMESTAMP_RESOLUTION: Duration = Duration::from_secs(2);
+
+/// How ETags of files are computed.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum EtagAlgorithm {
+    /// Modified time and size, without reading the file.
+    #[default]
+    Mtime,
+    /// XXH3 digest of the content. Fast but non-cryptographic.
+    Xxhash,
+    /// SHA-256 digest of the content.
+    Sha256,
+}
+
+impl FromStr for EtagAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mtime" => Ok(Self::Mtime),
+            "xxhash" => Ok(Self::Xxhash),
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(format!("expect mtime, xxhash or sha256, got {}", s)),
+        }
+    }
+}
+
+impl EtagAlgorithm {
+    /// Whether the entire file is read to compute the ETag.
+    pub fn reads_content(&self) -> bool {
+        *self != Self::Mtime
+    }
+
+    /// Compute a strong ETag of the file.
+    ///
+    /// Content digests are stable across machines, whereas modified time
+    /// varies with copies of the same file.
+    pub fn file_etag(&self, path: &Path, mtime: SystemTime, size: u64) -> io::Result<ETag> {
+        let tag = match self {
+            Self::Mtime => format!("{}-{}", mtime.timestamp(), size),
+            Self::Xxhash => {
+                let mut hasher = Xxh3::new();
+                read_chunks(path, |chunk| hasher.update(chunk))?;
+                format!("xxh3-{:016x}", hasher.digest())
+            }
+            Self::Sha256 => {
+                let mut hasher = Sha256::new();
+                read_chunks(path, |chunk| hasher.update(chunk))?;
+                format!("sha256-{:x}", hasher.finalize())
+            }
+        };
+        Ok(strong_etag(&tag))
+    }
+}
+
+/// Metadata of a file which changes whenever its content does.
+///
+/// Modified time can be set back at will, so on unix the inode number and
+/// status change time are compared as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    mtime: SystemTime,
+    size: u64,
+    #[cfg(unix)]
+    inode: u64,
+    #[cfg(unix)]
+    ctime: (i64, i64),
+}
+
+impl FileStamp {
+    pub fn new(meta: &Metadata) -> io::Result<Self> {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Ok(Self {
+            mtime: meta.modified()?,
+            size: meta.len(),
+            #[cfg(unix)]
+            inode: meta.ino(),
+            #[cfg(unix)]
+            ctime: (meta.ctime(), meta.ctime_nsec()),
+        })
+    }
+
+    /// Whether the file changed too recently to tell from its timestamps
+    /// whether it changes again, as timestamps are only as precise as the
+    /// filesystem keeps them.
+    fn is_racy(&self, now: SystemTime) -> bool {
+        #[cfg(unix)]
+        let changed = SystemTime::UNIX_EPOCH + Duration::from_secs(self.ctime.0.max(0) as u64);
+        #[cfg(not(unix))]
+        let changed = self.mtime;
+        let recent = now
+            .checked_sub(TIMESTAMP_RESOLUTION)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        self.mtime.max(changed) >= recent
+    }
+}
+
+/// Content digest ETags of files, valid as long as the stamp of a file stays
+/// the same.
+///
+/// Each file keeps only its latest ETag, so the cache grows no larger than
+/// the number of files served.
+#[derive(Debug, Default)]
+pub struct EtagCache {
+    entries: Mutex<HashMap<PathBuf, (FileStamp, ETag)>>,
+}
+
+impl EtagCache {
+    /// Get the cached ETag of a file, unless it has changed since.
+    pub fn get(&self, path: &Path, stamp: &FileStamp) -> Option<ETag> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|entry| entry.0 == *stamp)
+            .map(|entry| entry.1.clone())
+    }
+
+    /// Cache the ETag of a file computed at given stamp.
+    ///
+    /// Files changed within the resolution of timestamps are left uncached,
+    /// since another change in the meantime would keep the same stamp.
+    pub fn insert(&self, path: &Path, stamp: FileStamp, etag: ETag) {
+        if stamp.is_racy(SystemTime::now()) {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(path.to_owned(), (stamp, etag));
+    }
+}
+
+/// ETag of static files, which are identical byte by byte as long as the
+/// tag matches.
+pub fn strong_etag(tag: &str) -> ETag {
+    format!(r#""{}""#, tag).parse().unwrap()
+}
+
+/// ETag of generated content, such as listings and archives, which is only
+/// semantically equivalent as long as the tag matches.
+pub fn weak_etag(tag: &str) -> ETag {
+    format!(r#"W/"{}""#, tag).parse().unwrap()
+}
+
+/// Weak ETag of a representation transformed from that of `etag`, such as a
+/// file with ANSI escapes stripped, told apart from it by `suffix`.
+pub fn derived_etag(etag: &ETag, suffix: &str) -> ETag {
+    let mut values = Vec::with_capacity(1);
+    etag.encode(&mut values);
+    let tag = values[0].to_str().unwrap_or_default();
+    let tag = tag.trim_start_matches("W/").trim_matches('"');
+    weak_etag(&format!("{}-{}", tag, suffix))
+}
+
+/// Feed the content of a file into `f` chunk by chunk.
+fn read_chunks(path: &Path, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => f(&buf[..len]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn etags(algorithm: EtagAlgorithm, contents: &[&[u8]]) -> Vec<ETag> {
+        let dir = tempfile::tempdir().unwrap();
+        contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| {
+                let path = dir.path().join(i.to_string());
+                std::fs::write(&path, content).unwrap();
+                let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(i as u64);
+                algorithm
+                    .file_etag(&path, mtime, content.len() as u64)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn derived() {
+        let etag = derived_etag(&strong_etag("1-2"), "ansi");
+        assert_eq!(etag, weak_etag("1-2-ansi"));
+        assert_eq!(derived_etag(&weak_etag("1-2"), "ansi"), etag);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("mtime".parse(), Ok(EtagAlgorithm::Mtime));
+        assert_eq!("xxhash".parse(), Ok(EtagAlgorithm::Xxhash));
+        assert_eq!("sha256".parse(), Ok(EtagAlgorithm::Sha256));
+        assert!("md5".parse::<EtagAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn mtime_etag() {
+        let etags = etags(EtagAlgorithm::Mtime, &[b"same", b"same"]);
+        assert_eq!(etags[0], r#""0-4""#.parse().unwrap());
+        assert_ne!(etags[0], etags[1]);
+    }
+
+    #[test]
+    fn content_etag() {
+        for algorithm in [EtagAlgorithm::Xxhash, EtagAlgorithm::Sha256] {
+            // Identical contents match regardless of modified time.
+            let etags = etags(algorithm, &[b"same", b"same", b"diff"]);
+            assert_eq!(etags[0], etags[1]);
+            assert_ne!(etags[0], etags[2]);
+        }
+        let etags = etags(EtagAlgorithm::Sha256, &[b""]);
+        let digest = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(etags[0], format!(r#""sha256-{}""#, digest).parse().unwrap());
+    }
+
+    #[test]
+    fn strength() {
+        let strong = strong_etag("tag");
+        let weak = weak_etag("tag");
+        assert_eq!(strong, r#""tag""#.parse().unwrap());
+        assert_eq!(weak, r#"W/"tag""#.parse().unwrap());
+        assert_ne!(strong, weak);
+    }
+
+    fn stamp(mtime: u64, size: u64, ctime: i64) -> FileStamp {
+        FileStamp {
+            mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(mtime),
+            size,
+            #[cfg(unix)]
+            inode: 1,
+            #[cfg(unix)]
+            ctime: (ctime, 0),
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let cache = EtagCache::default();
+        let path = Path::new("file");
+        let old = stamp(0, 4, 0);
+        assert!(cache.get(path, &old).is_none());
+        cache.insert(path, old, strong_etag("a"));
+        assert_eq!(cache.get(path, &old), Some(strong_etag("a")));
+        // Changes of modified time, size or status change time invalidate
+        // the entry.
+        assert!(cache.get(path, &stamp(0, 5, 0)).is_none());
+        assert!(cache.get(path, &stamp(1, 4, 0)).is_none());
+        #[cfg(unix)]
+        assert!(cache.get(path, &stamp(0, 4, 1)).is_none());
+        cache.insert(path, stamp(0, 5, 0), strong_etag("b"));
+        assert_eq!(cache.get(path, &stamp(0, 5, 0)), Some(strong_etag("b")));
+        assert!(cache.get(path, &old).is_none());
+
+        // Files changed just now are never cached.
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let racy = stamp(now, 4, now as i64);
+        cache.insert(path, racy, strong_etag("c"));
+        assert!(cache.get(path, &racy).is_none());
+    }
+
+    #[test]
+    fn xxhash_large_file() {
+        let content = (0..32 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let timer = Instant::now();
+        let etags = etags(EtagAlgorithm::Xxhash, &[&content]);
+        // Generous even for unoptimized builds.
+        assert!(timer.elapsed() < Duration::from_secs(10));
+        let digest = xxhash_rust::xxh3::xxh3_64(&content);
+        let expected = format!(r#""xxh3-{:016x}""#, digest);
+        assert_eq!(etags[0], expected.parse().unwrap());
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Glob patterns excluding paths from being served.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Exclude matcher built from glob patterns.
+///
+/// Unlike gitignore rules, patterns are plain globs matched against paths
+/// relative to the base path. A path is excluded if itself or any of its
+/// ancestors matches.
+#[derive(Debug, Clone)]
+pub struct Exclude {
+    base_path: PathBuf,
+    globs: GlobSet,
+}
+
+impl Exclude {
+    /// Build a matcher of paths under `base_path`.
+    pub fn new<P: AsRef<Path>>(base_path: P, patterns: &[String]) -> Result<Self, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern)?);
+        }
+        Ok(Self {
+            base_path: base_path.as_ref().to_owned(),
+            globs: builder.build()?,
+        })
+    }
+
+    /// Determine if the given path is excluded.
+    ///
+    /// Paths outside of the base path are never excluded.
+    pub fn is_excluded<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = match path.as_ref().strip_prefix(&self.base_path) {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+        path.ancestors()
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| self.globs.is_match(p))
+    }
+}
+
+/// Build a matcher of MIME types, such as `video/*`, against their essence.
+pub fn mime_globs(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// Read glob patterns from a file, one per line. Blank lines and comments
+/// are ignored.
+pub fn read_patterns<P: AsRef<Path>>(file: P) -> io::Result<Vec<String>> {
+    let patterns = fs::read_to_string(file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect();
+    Ok(patterns)
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn excluded() {
+        let patterns = ["*.log".to_owned(), "secret".to_owned()];
+        let exclude = Exclude::new("/base", &patterns).unwrap();
+        assert!(exclude.is_excluded("/base/app.log"));
+        assert!(exclude.is_excluded("/base/dir/app.log"));
+        assert!(exclude.is_excluded("/base/secret"));
+        assert!(exclude.is_excluded("/base/secret/file.txt"));
+        assert!(!exclude.is_excluded("/base/file.txt"));
+        assert!(!exclude.is_excluded("/base/dir/secret.txt"));
+        assert!(!exclude.is_excluded("/elsewhere/app.log"));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(Exclude::new("/base", &["a[".to_owned()]).is_err());
+    }
+}
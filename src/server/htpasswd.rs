@@ -0,0 +1,213 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Apache-style htpasswd file for Basic authentication.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+const APR1_MAGIC: &str = "$apr1$";
+
+/// Alphabet of crypt(3) flavored base64 encoding.
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Users and their password hashes loaded from an htpasswd file.
+///
+/// Supported hash formats are APR1-MD5 (`$apr1$`), and those recognized by
+/// crypt(3), including bcrypt (`$2y$`), MD5 (`$1$`), and DES.
+///
+/// Cloning is cheap, as entries are shared.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Htpasswd {
+    users: Arc<HashMap<String, String>>,
+    /// Hash of the first user, verified against for unknown users so that
+    /// they take as long as known ones.
+    dummy: Option<String>,
+}
+
+impl FromStr for Htpasswd {
+    type Err = String;
+
+    /// Parse `user:hash` lines. Blank lines and comments are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut users = HashMap::new();
+        let mut dummy = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((user, hash)) if !user.is_empty() && !hash.is_empty() => {
+                    dummy.get_or_insert_with(|| hash.to_owned());
+                    users.insert(user.to_owned(), hash.to_owned());
+                }
+                _ => return Err(format!("malformed entry at line {}", i + 1)),
+            }
+        }
+        Ok(Self {
+            users: Arc::new(users),
+            dummy,
+        })
+    }
+}
+
+impl Htpasswd {
+    /// Verify the password of a user against the stored hash.
+    ///
+    /// Hashing is slow by design, so call this off the async runtime.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match (self.users.get(user), &self.dummy) {
+            (Some(hash), _) => verify_hash(hash, password),
+            // Unknown users are rejected only after as much work.
+            (None, Some(dummy)) => {
+                std::hint::black_box(verify_hash(dummy, password));
+                false
+            }
+            (None, None) => false,
+        }
+    }
+}
+
+/// Verify a password against a hash in any supported format.
+fn verify_hash(hash: &str, password: &str) -> bool {
+    match hash.strip_prefix(APR1_MAGIC) {
+        Some(rest) => {
+            let salt = rest.split('$').next().unwrap_or_default();
+            constant_time_eq(hash, &apr1(password.as_bytes(), salt.as_bytes()))
+        }
+        None => pwhash::unix::verify(password, hash),
+    }
+}
+
+/// Compare secrets without leaking the position of the first difference,
+/// nor their lengths, by comparing digests of fixed size instead.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Compute an APR1-MD5 hash, which is MD5-crypt with Apache's own magic.
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let alternate = Md5::new()
+        .chain(password)
+        .chain(salt)
+        .chain(password)
+        .finalize();
+
+    let mut ctx = Md5::new().chain(password).chain(APR1_MAGIC).chain(salt);
+    for chunk in password.chunks(16) {
+        ctx.update(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            ctx.update([0]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut digest = ctx.finalize();
+
+    for round in 0..1000 {
+        let mut ctx = Md5::new();
+        if round & 1 == 1 {
+            ctx.update(password);
+        } else {
+            ctx.update(digest);
+        }
+        if round % 3 != 0 {
+            ctx.update(salt);
+        }
+        if round % 7 != 0 {
+            ctx.update(password);
+        }
+        if round & 1 == 1 {
+            ctx.update(digest);
+        } else {
+            ctx.update(password);
+        }
+        digest = ctx.finalize();
+    }
+
+    let mut hash = format!("{}{}$", APR1_MAGIC, String::from_utf8_lossy(salt));
+    let mut encode = |mut value: u32, len: usize| {
+        for _ in 0..len {
+            hash.push(ITOA64[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let value = (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32;
+        encode(value, 4);
+    }
+    encode(digest[11] as u32, 2);
+    hash
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    // Passwords are "secret" for alice and "password" for bob.
+    const FIXTURE: &str = "\
+# Generated by htpasswd
+alice:$apr1$r31..G..$3UUppPV2F0WcPRalJVo3i.
+
+bob:$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe
+";
+
+    #[test]
+    fn parse() {
+        let htpasswd = FIXTURE.parse::<Htpasswd>().unwrap();
+        assert_eq!(htpasswd.users.len(), 2);
+        assert!("alice".parse::<Htpasswd>().is_err());
+        assert!("alice:".parse::<Htpasswd>().is_err());
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert!(super::constant_time_eq("secret", "secret"));
+        assert!(!super::constant_time_eq("secret", "secreT"));
+        assert!(!super::constant_time_eq("secret", "secret!"));
+        assert!(!super::constant_time_eq("", "secret"));
+    }
+
+    #[test]
+    fn verify_apr1() {
+        let htpasswd = FIXTURE.parse::<Htpasswd>().unwrap();
+        assert!(htpasswd.verify("alice", "secret"));
+        assert!(!htpasswd.verify("alice", "wrong"));
+    }
+
+    #[test]
+    fn verify_bcrypt() {
+        let htpasswd = FIXTURE.parse::<Htpasswd>().unwrap();
+        assert!(htpasswd.verify("bob", "password"));
+        assert!(!htpasswd.verify("bob", "wrong"));
+    }
+
+    #[test]
+    fn verify_unknown_user() {
+        let htpasswd = FIXTURE.parse::<Htpasswd>().unwrap();
+        // Verified against the hash of alice, yet never accepted.
+        assert_eq!(
+            htpasswd.dummy.as_deref(),
+            htpasswd.users.get("alice").map(String::as_str)
+        );
+        assert!(!htpasswd.verify("carol", "secret"));
+        assert!(!Htpasswd::default().verify("carol", "secret"));
+    }
+}
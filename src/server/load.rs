@@ -0,0 +1,124 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Gauge estimating server load from request service time.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Weight of each new sample is `1 / SMOOTHING` of the moving average.
+const SMOOTHING: u64 = 8;
+
+/// Exponential moving average of request service time, shared across
+/// requests served concurrently.
+///
+/// Service time is the time spent handling a request and producing its
+/// body, including streaming compression, but not waiting for clients to
+/// receive it.
+#[derive(Debug)]
+pub struct LoadGauge {
+    /// Average service time in microseconds.
+    average: AtomicU64,
+    threshold: Duration,
+}
+
+impl LoadGauge {
+    /// Create a gauge considering the server overloaded once the average
+    /// service time exceeds `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            average: AtomicU64::new(0),
+            threshold,
+        }
+    }
+
+    /// Feed service time of a request into the moving average.
+    pub fn record(&self, elapsed: Duration) {
+        let sample = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let _ = self
+            .average
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(avg - avg / SMOOTHING + sample / SMOOTHING)
+            });
+    }
+
+    /// Current average service time.
+    pub fn average(&self) -> Duration {
+        Duration::from_micros(self.average.load(Ordering::Relaxed))
+    }
+
+    /// Whether the average service time exceeds the threshold.
+    pub fn is_overloaded(&self) -> bool {
+        self.average() > self.threshold
+    }
+}
+
+/// Service time of a request in progress, fed into the gauge once the
+/// response body is done with, i.e. dropped.
+#[derive(Debug)]
+pub struct LoadSample {
+    gauge: Arc<LoadGauge>,
+    elapsed: Duration,
+}
+
+impl LoadSample {
+    /// Start a sample with time already spent handling the request.
+    pub fn new(gauge: Arc<LoadGauge>, elapsed: Duration) -> Self {
+        Self { gauge, elapsed }
+    }
+
+    /// Add time spent producing the body.
+    pub fn add(&mut self, elapsed: Duration) {
+        self.elapsed += elapsed;
+    }
+}
+
+impl Drop for LoadSample {
+    fn drop(&mut self) {
+        self.gauge.record(self.elapsed);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn moving_average() {
+        let gauge = LoadGauge::new(Duration::from_millis(10));
+        assert_eq!(gauge.average(), Duration::ZERO);
+        gauge.record(Duration::from_millis(80));
+        assert_eq!(gauge.average(), Duration::from_millis(10));
+        gauge.record(Duration::from_millis(80));
+        assert_eq!(gauge.average(), Duration::from_micros(18750));
+    }
+
+    #[test]
+    fn overloaded() {
+        let gauge = LoadGauge::new(Duration::from_millis(10));
+        assert!(!gauge.is_overloaded());
+        gauge.record(Duration::from_millis(800));
+        assert!(gauge.is_overloaded());
+        // Load drops as fast requests come in.
+        for _ in 0..64 {
+            gauge.record(Duration::ZERO);
+        }
+        assert!(!gauge.is_overloaded());
+    }
+
+    #[test]
+    fn sample_on_drop() {
+        let gauge = Arc::new(LoadGauge::new(Duration::from_millis(10)));
+        let mut sample = LoadSample::new(gauge.clone(), Duration::from_millis(8));
+        sample.add(Duration::from_millis(72));
+        assert_eq!(gauge.average(), Duration::ZERO);
+        drop(sample);
+        assert_eq!(gauge.average(), Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,657 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Response body which writes access log lines while being sent.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use bytes::Bytes;
+use chrono::{FixedOffset, Local, Utc};
+use hyper::body::HttpBody;
+use hyper::header::HeaderName;
+use hyper::{Body, HeaderMap, Method, StatusCode, Uri, Version};
+
+use crate::server::load::LoadSample;
+use crate::server::Request;
+
+/// Destination of log lines, which is stdout by default.
+pub type LogWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// Log format of sfz since its early days.
+pub const DEFAULT_LOG_FORMAT: &str = r#"[$time] "$request_method $request_uri" - $status_text"#;
+
+/// Nginx combined log format, selected by name `combined`.
+const COMBINED_LOG_FORMAT: &str = r#"$remote_addr - - [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+/// Piece of a parsed access log format.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `$remote_addr`: client address.
+    RemoteAddr,
+    /// `$time`: time in the default format of sfz, with the offset only if
+    /// a timezone other than the local one is selected.
+    Time,
+    /// `$time_local`: local time in the common log format.
+    TimeLocal,
+    /// `$request`: full original request line.
+    Request,
+    /// `$request_method`: request method.
+    RequestMethod,
+    /// `$request_uri`: full original request URI.
+    RequestUri,
+    /// `$status`: response status code.
+    Status,
+    /// `$status_text`: response status code with its reason phrase.
+    StatusText,
+    /// `$body_bytes_sent`: number of bytes sent in the response body.
+    BodyBytesSent,
+    /// `$request_time`: seconds elapsed since the request was received, with
+    /// a milliseconds resolution.
+    RequestTime,
+    /// `$http_<name>`: arbitrary request header field, with dashes replaced
+    /// by underscores.
+    Header(HeaderName),
+}
+
+/// Timezone of timestamps in access log lines.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LogTimezone {
+    Utc,
+    /// Timezone of the machine.
+    #[default]
+    Local,
+    /// Fixed offset from UTC, such as `+08:00`.
+    Fixed(FixedOffset),
+}
+
+impl LogTimezone {
+    /// Format current time in this timezone.
+    fn now(&self, fmt: &str) -> String {
+        match self {
+            Self::Utc => Utc::now().format(fmt).to_string(),
+            Self::Local => Local::now().format(fmt).to_string(),
+            Self::Fixed(offset) => Utc::now().with_timezone(offset).format(fmt).to_string(),
+        }
+    }
+}
+
+impl FromStr for LogTimezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utc" => return Ok(Self::Utc),
+            "local" => return Ok(Self::Local),
+            _ => (),
+        }
+        let err = || format!("expect utc, local or +HH:MM, got {}", s);
+        let (sign, offset) = match s.split_at(s.len().min(1)) {
+            ("+", offset) => (1, offset),
+            ("-", offset) => (-1, offset),
+            _ => return Err(err()),
+        };
+        let (hours, minutes) = offset.split_once(':').ok_or_else(err)?;
+        if hours.len() != 2 || minutes.len() != 2 {
+            return Err(err());
+        }
+        let hours = hours.parse::<i32>().map_err(|_| err())?;
+        let minutes = minutes.parse::<i32>().map_err(|_| err())?;
+        if minutes >= 60 {
+            return Err(err());
+        }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Self::Fixed)
+            .ok_or_else(err)
+    }
+}
+
+/// Access log format with nginx-style variables, parsed once at startup.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogFormat {
+    segments: Vec<Segment>,
+    timezone: LogTimezone,
+}
+
+impl LogFormat {
+    /// Render `$time` and `$time_local` in the given timezone instead of the
+    /// local one.
+    pub fn with_timezone(mut self, timezone: LogTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Current time of lines logged outside of requests, in the same
+    /// timezone as `$time_local`.
+    fn event_time(&self) -> String {
+        self.timezone.now("%d/%b/%Y %H:%M:%S %z")
+    }
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        DEFAULT_LOG_FORMAT.parse().unwrap()
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s {
+            "combined" => COMBINED_LOG_FORMAT,
+            s => s,
+        };
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(pos) = rest.find('$') {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let (name, remaining) = rest.split_at(len);
+            rest = remaining;
+            if name.is_empty() {
+                // A lone dollar sign.
+                literal.push('$');
+                continue;
+            }
+            let segment = match name {
+                "remote_addr" => Segment::RemoteAddr,
+                "time" => Segment::Time,
+                "time_local" => Segment::TimeLocal,
+                "request" => Segment::Request,
+                "request_method" => Segment::RequestMethod,
+                "request_uri" => Segment::RequestUri,
+                "status" => Segment::Status,
+                "status_text" => Segment::StatusText,
+                "body_bytes_sent" => Segment::BodyBytesSent,
+                "request_time" => Segment::RequestTime,
+                _ => match name.strip_prefix("http_") {
+                    Some(header) => HeaderName::from_str(&header.replace('_', "-"))
+                        .map(Segment::Header)
+                        .map_err(|_| format!("invalid header variable ${}", name))?,
+                    None => return Err(format!("unknown variable ${}", name)),
+                },
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self {
+            segments,
+            timezone: LogTimezone::default(),
+        })
+    }
+}
+
+/// Information of a request and its response to be logged.
+#[derive(Debug)]
+pub struct LogEntry {
+    remote_addr: Option<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    status: StatusCode,
+    start: Instant,
+    bytes_sent: u64,
+}
+
+impl LogEntry {
+    /// Capture a request which started at `start`.
+    pub fn new(
+        req: &Request,
+        remote_addr: Option<SocketAddr>,
+        status: StatusCode,
+        start: Instant,
+    ) -> Self {
+        Self {
+            remote_addr,
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+            status,
+            start,
+            bytes_sent: 0,
+        }
+    }
+
+    fn request_line(&self) -> String {
+        format!("{} {} {:?}", self.method, self.uri, self.version)
+    }
+
+    fn time(timezone: &LogTimezone) -> String {
+        match timezone {
+            LogTimezone::Local => timezone.now("%d/%b/%Y %H:%M:%S"),
+            _ => timezone.now("%d/%b/%Y %H:%M:%S %z"),
+        }
+    }
+
+    fn time_local(timezone: &LogTimezone) -> String {
+        timezone.now("%d/%b/%Y:%H:%M:%S %z")
+    }
+
+    /// Render a log line. Unavailable values are rendered as `-`.
+    fn render(&self, format: &LogFormat) -> String {
+        let mut line = String::new();
+        for segment in &format.segments {
+            let _ = match segment {
+                Segment::Literal(s) => write!(line, "{}", s),
+                Segment::RemoteAddr => match self.remote_addr {
+                    Some(addr) => write!(line, "{}", addr.ip()),
+                    None => write!(line, "-"),
+                },
+                Segment::Time => write!(line, "{}", Self::time(&format.timezone)),
+                Segment::TimeLocal => write!(line, "{}", Self::time_local(&format.timezone)),
+                Segment::Request => write!(line, "{}", self.request_line()),
+                Segment::RequestMethod => write!(line, "{}", self.method),
+                Segment::RequestUri => write!(line, "{}", self.uri),
+                Segment::Status => write!(line, "{}", self.status.as_u16()),
+                Segment::StatusText => write!(line, "{}", self.status),
+                Segment::BodyBytesSent => write!(line, "{}", self.bytes_sent),
+                Segment::RequestTime => {
+                    write!(line, "{:.3}", self.start.elapsed().as_secs_f64())
+                }
+                Segment::Header(name) => {
+                    let value = self.headers.get(name).and_then(|v| v.to_str().ok());
+                    write!(line, "{}", value.unwrap_or("-"))
+                }
+            };
+        }
+        line
+    }
+}
+
+/// Lifecycle of a connection, logged when accepted and when closed.
+///
+/// The connection is considered closed once the last reference is dropped,
+/// that is, after its service and all response bodies are gone.
+pub struct ConnectionLog {
+    remote_addr: SocketAddr,
+    accepted: Instant,
+    bytes_sent: AtomicU64,
+    writer: LogWriter,
+    format: Arc<LogFormat>,
+}
+
+impl ConnectionLog {
+    /// Log an accepted connection, with timestamps in the timezone of
+    /// `format`.
+    pub fn accept(remote_addr: SocketAddr, writer: LogWriter, format: Arc<LogFormat>) -> Arc<Self> {
+        let conn = Self {
+            remote_addr,
+            accepted: Instant::now(),
+            bytes_sent: AtomicU64::new(0),
+            writer,
+            format,
+        };
+        conn.log("connection accepted");
+        Arc::new(conn)
+    }
+
+    /// Count bytes of response bodies sent over the connection.
+    fn add_bytes(&self, len: u64) {
+        self.bytes_sent.fetch_add(len, Ordering::Relaxed);
+    }
+
+    fn log(&self, event: &str) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writeln!(
+                writer,
+                "[{}] {} {}",
+                self.format.event_time(),
+                self.remote_addr,
+                event,
+            );
+        }
+    }
+}
+
+impl Drop for ConnectionLog {
+    fn drop(&mut self) {
+        self.log(&format!(
+            "connection closed after {:.3}s, {} bytes sent",
+            self.accepted.elapsed().as_secs_f64(),
+            self.bytes_sent.load(Ordering::Relaxed),
+        ));
+    }
+}
+
+/// Wrap a response body to log the request once the body is fully sent.
+///
+/// If a progress step is given, a progress line is also logged every time
+/// the number of bytes sent crosses a multiple of the step.
+pub struct LoggableBody {
+    inner: Body,
+    entry: LogEntry,
+    format: Arc<LogFormat>,
+    progress_step: Option<u64>,
+    writer: Option<LogWriter>,
+    connection: Option<Arc<ConnectionLog>>,
+    load: Option<LoadSample>,
+}
+
+impl LoggableBody {
+    /// Wrap a body. Nothing would be logged if `writer` is `None`.
+    pub fn new(
+        inner: Body,
+        entry: LogEntry,
+        format: Arc<LogFormat>,
+        writer: Option<LogWriter>,
+        progress_step: Option<u64>,
+    ) -> Self {
+        Self {
+            inner,
+            entry,
+            format,
+            progress_step: progress_step.filter(|step| *step > 0),
+            writer,
+            connection: None,
+            load: None,
+        }
+    }
+
+    /// Count bytes sent towards the connection, which is kept open in the
+    /// log until the body is dropped.
+    pub fn with_connection(mut self, connection: Option<Arc<ConnectionLog>>) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Add time spent producing the body, such as compressing it on the
+    /// fly, to the service time sampled once the body is dropped.
+    pub fn with_load(mut self, load: LoadSample) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    fn log(&self, line: &str) {
+        if let Some(writer) = &self.writer {
+            if let Ok(mut writer) = writer.lock() {
+                let _ = writeln!(writer, "{}", line);
+            }
+        }
+    }
+
+    /// Log a progress line if a threshold was crossed by the latest chunk.
+    ///
+    /// Progress lines are rendered in the access log format like the
+    /// completion line, followed by the number of bytes sent so far.
+    fn log_progress(&mut self, chunk_len: u64) {
+        if let Some(connection) = &self.connection {
+            connection.add_bytes(chunk_len);
+        }
+        let before = self.entry.bytes_sent;
+        self.entry.bytes_sent += chunk_len;
+        if let Some(step) = self.progress_step {
+            if self.entry.bytes_sent / step > before / step {
+                self.log(&format!(
+                    "{} (in progress, {} bytes sent)",
+                    self.entry.render(&self.format),
+                    self.entry.bytes_sent,
+                ));
+            }
+        }
+    }
+
+    /// Log the completion line. Logged only once.
+    fn log_completion(&mut self) {
+        if self.writer.is_some() {
+            self.log(&self.entry.render(&self.format));
+            self.writer = None;
+        }
+    }
+}
+
+impl HttpBody for LoggableBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        let started = Instant::now();
+        let poll = Pin::new(&mut this.inner).poll_data(cx);
+        if let Some(load) = &mut this.load {
+            load.add(started.elapsed());
+        }
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => this.log_progress(chunk.len() as u64),
+            Poll::Ready(None) => this.log_completion(),
+            _ => (),
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggableBody {
+    /// Empty bodies might never be polled, and clients might abort the
+    /// transfer. Make sure the request is logged anyway.
+    fn drop(&mut self) {
+        self.log_completion();
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn log_writer() -> (LogWriter, Arc<Mutex<Vec<u8>>>) {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        (buf.clone(), buf)
+    }
+
+    fn lines(buf: &Arc<Mutex<Vec<u8>>>) -> Vec<String> {
+        let buf = buf.lock().unwrap();
+        String::from_utf8_lossy(&buf)
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn entry(req: &Request) -> LogEntry {
+        let addr = "127.0.0.1:8080".parse().ok();
+        LogEntry::new(req, addr, StatusCode::OK, Instant::now())
+    }
+
+    #[test]
+    fn parse_format() {
+        let format = "$remote_addr $$ $http_x_forwarded_for$status $ end"
+            .parse::<LogFormat>()
+            .unwrap();
+        assert_eq!(
+            format.segments,
+            [
+                Segment::RemoteAddr,
+                Segment::Literal(" $$ ".to_owned()),
+                Segment::Header(HeaderName::from_static("x-forwarded-for")),
+                Segment::Status,
+                Segment::Literal(" $ end".to_owned()),
+            ]
+        );
+        assert!("$unknown".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn render_default_format() {
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::USER_AGENT, "curl/7.79.1")
+            .body(Body::empty())
+            .unwrap();
+        let mut entry = entry(&req);
+        entry.bytes_sent = 5;
+        let line = entry.render(&LogFormat::default());
+        assert!(line.starts_with('['));
+        assert!(line.ends_with(r#"] "GET /file.txt" - 200 OK"#));
+
+        let line = entry.render(&"combined".parse().unwrap());
+        assert!(line.starts_with("127.0.0.1 - - ["));
+        assert!(line.ends_with(r#"] "GET /file.txt HTTP/1.1" 200 5 "-" "curl/7.79.1""#));
+    }
+
+    #[test]
+    fn parse_timezone() {
+        assert_eq!("utc".parse(), Ok(LogTimezone::Utc));
+        assert_eq!("local".parse(), Ok(LogTimezone::Local));
+        let offset = |secs| LogTimezone::Fixed(FixedOffset::east_opt(secs).unwrap());
+        assert_eq!("+08:00".parse(), Ok(offset(8 * 3600)));
+        assert_eq!("-03:30".parse(), Ok(offset(-(3 * 3600 + 30 * 60))));
+        for invalid in ["", "UTC", "+8:00", "+08", "+08:60", "+24:00", "08:00"] {
+            assert!(invalid.parse::<LogTimezone>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn render_timezone() {
+        let req = hyper::Request::get("/").body(Body::empty()).unwrap();
+        let format = "[$time_local]".parse::<LogFormat>().unwrap();
+        let line = entry(&req).render(&format.clone().with_timezone(LogTimezone::Utc));
+        assert!(line.ends_with(" +0000]"), "{}", line);
+        let offset = "-03:30".parse().unwrap();
+        let line = entry(&req).render(&format.with_timezone(offset));
+        assert!(line.ends_with(" -0330]"), "{}", line);
+
+        // Offset of the default format is omitted only for local time.
+        let line = entry(&req).render(&LogFormat::default().with_timezone(LogTimezone::Utc));
+        assert!(line.ends_with(r#" +0000] "GET /" - 200 OK"#), "{}", line);
+        let line = entry(&req).render(&LogFormat::default());
+        let (time, _) = line.split_once(']').unwrap();
+        assert_eq!(time.len(), "[17/Oct/2026 12:00:00".len(), "{}", line);
+    }
+
+    #[test]
+    fn render_custom_format() {
+        let req = hyper::Request::post("/upload?x=1")
+            .header(hyper::header::REFERER, "http://example.com")
+            .body(Body::empty())
+            .unwrap();
+        let format = "$request_method $request_uri $status $http_referer $request_time"
+            .parse::<LogFormat>()
+            .unwrap();
+        let line = entry(&req).render(&format);
+        assert!(line.starts_with("POST /upload?x=1 200 http://example.com 0.0"));
+    }
+
+    #[tokio::test]
+    async fn log_completion_once() {
+        let (writer, buf) = log_writer();
+        let req = hyper::Request::get("/file.txt")
+            .body(Body::empty())
+            .unwrap();
+        let body = LoggableBody::new(
+            Body::from("hello"),
+            entry(&req),
+            Arc::new(LogFormat::default()),
+            Some(writer),
+            None,
+        );
+        let bytes = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(&bytes[..], b"hello");
+
+        let lines = lines(&buf);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(r#""GET /file.txt" - 200 OK"#));
+    }
+
+    #[tokio::test]
+    async fn log_progress() {
+        let (writer, buf) = log_writer();
+        let req = hyper::Request::get("/large").body(Body::empty()).unwrap();
+        let chunks = (0..10).map(|_| Ok::<_, std::io::Error>(vec![0; 1024]));
+        let body = LoggableBody::new(
+            Body::wrap_stream(futures::stream::iter(chunks)),
+            entry(&req),
+            Arc::new("$request $status $body_bytes_sent".parse().unwrap()),
+            Some(writer),
+            Some(4096),
+        );
+        let bytes = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(bytes.len(), 10 * 1024);
+
+        let lines = lines(&buf);
+        assert_eq!(lines.len(), 3);
+        // Progress lines share the format with the completion line.
+        assert_eq!(
+            lines[0],
+            "GET /large HTTP/1.1 200 4096 (in progress, 4096 bytes sent)"
+        );
+        assert_eq!(
+            lines[1],
+            "GET /large HTTP/1.1 200 8192 (in progress, 8192 bytes sent)"
+        );
+        assert_eq!(lines[2], "GET /large HTTP/1.1 200 10240");
+    }
+
+    #[tokio::test]
+    async fn log_connection() {
+        let (writer, buf) = log_writer();
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        let format = LogFormat::default().with_timezone(LogTimezone::Utc);
+        let connection = ConnectionLog::accept(addr, writer, Arc::new(format));
+        assert_eq!(lines(&buf).len(), 1);
+
+        // Two requests served over the connection without access logs.
+        for _ in 0..2 {
+            let req = hyper::Request::get("/file.txt")
+                .body(Body::empty())
+                .unwrap();
+            let body = LoggableBody::new(
+                Body::from("hello"),
+                entry(&req),
+                Arc::new(LogFormat::default()),
+                None,
+                None,
+            )
+            .with_connection(Some(connection.clone()));
+            hyper::body::to_bytes(body).await.unwrap();
+        }
+        assert_eq!(lines(&buf).len(), 1);
+        drop(connection);
+
+        let lines = lines(&buf);
+        assert_eq!(lines.len(), 2);
+        // Timestamps are in the timezone of access logs.
+        assert!(lines[0].ends_with(" +0000] 127.0.0.1:8080 connection accepted"));
+        assert!(lines[1].contains("] 127.0.0.1:8080 connection closed after 0.0"));
+        assert!(lines[1].ends_with("s, 10 bytes sent"));
+    }
+}
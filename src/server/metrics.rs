@@ -0,0 +1,66 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Request counters exposed in Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hyper::StatusCode;
+
+/// Status classes counted separately, from `1xx` to `5xx`.
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters shared across requests served concurrently.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    responses: [AtomicU64; 5],
+}
+
+impl Metrics {
+    /// Count a response by its status class.
+    pub fn record(&self, status: StatusCode) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render counters along with the average service time.
+    pub fn render(&self, average: Duration) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP sfz_responses_total Responses sent by status class.\n");
+        out.push_str("# TYPE sfz_responses_total counter\n");
+        for (class, count) in STATUS_CLASSES.iter().zip(&self.responses) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "sfz_responses_total{{class=\"{class}\"}} {count}");
+        }
+        out.push_str("# HELP sfz_service_time_seconds Moving average of service time.\n");
+        out.push_str("# TYPE sfz_service_time_seconds gauge\n");
+        let _ = writeln!(out, "sfz_service_time_seconds {}", average.as_secs_f64());
+        out
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let metrics = Metrics::default();
+        metrics.record(StatusCode::OK);
+        metrics.record(StatusCode::NOT_MODIFIED);
+        metrics.record(StatusCode::NOT_FOUND);
+        metrics.record(StatusCode::NOT_FOUND);
+        let text = metrics.render(Duration::from_millis(5));
+        assert!(text.contains("sfz_responses_total{class=\"2xx\"} 1\n"));
+        assert!(text.contains("sfz_responses_total{class=\"4xx\"} 2\n"));
+        assert!(text.contains("sfz_responses_total{class=\"5xx\"} 0\n"));
+        assert!(text.contains("sfz_service_time_seconds 0.005\n"));
+    }
+}
@@ -6,11 +6,26 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+mod ansi;
+mod etag;
+mod exclude;
+mod htpasswd;
+mod load;
+mod loggable;
+mod metrics;
 mod res;
 mod send;
 mod serve;
+mod throttle;
+mod timeout;
+mod tls;
+mod watch;
 
 pub type Request = hyper::Request<hyper::Body>;
 pub type Response = hyper::Response<hyper::Body>;
 
-pub use self::serve::{serve, PathType};
+pub use self::etag::EtagAlgorithm;
+pub use self::exclude::{mime_globs, read_patterns, Exclude};
+pub use self::htpasswd::Htpasswd;
+pub use self::loggable::{LogFormat, LogTimezone, DEFAULT_LOG_FORMAT};
+pub use self::serve::{spawn, PathType, ShutdownTrigger};
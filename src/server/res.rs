@@ -9,17 +9,61 @@
 //! Response factory functions.
 //!
 
-use headers::{ContentLength, HeaderMapExt};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bytes::Bytes;
+use headers::{ContentLength, ContentRange, ContentType, HeaderMapExt};
+use hyper::header::HeaderValue;
 use hyper::StatusCode;
 
 use crate::server::Response;
 
+/// Generate 204 NoContent response.
+pub fn no_content(mut res: Response) -> Response {
+    *res.status_mut() = StatusCode::NO_CONTENT;
+    res
+}
+
+/// Generate 301 MovedPermanently response redirecting to `location`.
+pub fn moved_permanently(mut res: Response, location: &str) -> Response {
+    match HeaderValue::from_str(location) {
+        Ok(location) => {
+            res.headers_mut().insert(hyper::header::LOCATION, location);
+            prepare_response(res, StatusCode::MOVED_PERMANENTLY, "301 Moved Permanently")
+        }
+        Err(_) => internal_server_error(res),
+    }
+}
+
+/// Generate 302 Found response redirecting to `location`.
+pub fn found(mut res: Response, location: &str) -> Response {
+    match HeaderValue::from_str(location) {
+        Ok(location) => {
+            res.headers_mut().insert(hyper::header::LOCATION, location);
+            prepare_response(res, StatusCode::FOUND, "302 Found")
+        }
+        Err(_) => internal_server_error(res),
+    }
+}
+
 /// Generate 304 NotModified response.
 pub fn not_modified(mut res: Response) -> Response {
     *res.status_mut() = StatusCode::NOT_MODIFIED;
     res
 }
 
+/// Generate 401 Unauthorized response, challenging for Basic authentication.
+pub fn unauthorized(mut res: Response) -> Response {
+    res.headers_mut().insert(
+        hyper::header::WWW_AUTHENTICATE,
+        HeaderValue::from_static(r#"Basic realm="sfz""#),
+    );
+    prepare_response(res, StatusCode::UNAUTHORIZED, "401 Unauthorized")
+}
+
 /// Generate 403 Forbidden response.
 pub fn forbidden(res: Response) -> Response {
     prepare_response(res, StatusCode::FORBIDDEN, "403 Forbidden")
@@ -39,6 +83,32 @@ pub fn precondition_failed(res: Response) -> Response {
     )
 }
 
+/// Generate 414 URITooLong response.
+pub fn uri_too_long(res: Response) -> Response {
+    prepare_response(res, StatusCode::URI_TOO_LONG, "414 URI Too Long")
+}
+
+/// Generate 416 RangeNotSatisfiable response, telling the complete length
+/// of the representation via `Content-Range: bytes */<length>`.
+pub fn range_not_satisfiable(mut res: Response, complete_length: u64) -> Response {
+    res.headers_mut()
+        .typed_insert(ContentRange::unsatisfied_bytes(complete_length));
+    prepare_response(
+        res,
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        "416 Range Not Satisfiable",
+    )
+}
+
+/// Generate 421 MisdirectedRequest response.
+pub fn misdirected_request(res: Response) -> Response {
+    prepare_response(
+        res,
+        StatusCode::MISDIRECTED_REQUEST,
+        "421 Misdirected Request",
+    )
+}
+
 /// Generate 500 InternalServerError response.
 pub fn internal_server_error(res: Response) -> Response {
     prepare_response(
@@ -48,6 +118,49 @@ pub fn internal_server_error(res: Response) -> Response {
     )
 }
 
+/// Custom pages used as bodies of error responses.
+#[derive(Debug, Default)]
+pub struct ErrorPages(HashMap<StatusCode, Bytes>);
+
+impl ErrorPages {
+    /// Load pages named after status codes, such as `404.html`, from a
+    /// directory. Files not named after an error status are ignored.
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut pages = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let status = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".html"))
+                .and_then(|code| code.parse::<StatusCode>().ok())
+                .filter(|status| status.is_client_error() || status.is_server_error());
+            if let Some(status) = status {
+                pages.insert(status, fs::read(&path)?.into());
+            }
+        }
+        Ok(Self(pages))
+    }
+
+    /// Use the page at `path` for responses of `status`, replacing the
+    /// loaded one if any.
+    pub fn insert<P: AsRef<Path>>(&mut self, status: StatusCode, path: P) -> io::Result<()> {
+        self.0.insert(status, fs::read(path)?.into());
+        Ok(())
+    }
+
+    /// Replace the body with the custom page of the response status, if any.
+    pub fn apply(&self, mut res: Response) -> Response {
+        if let Some(page) = self.0.get(&res.status()) {
+            res.headers_mut().typed_insert(ContentType::html());
+            res.headers_mut()
+                .typed_insert(ContentLength(page.len() as u64));
+            *res.body_mut() = page.clone().into();
+        }
+        res
+    }
+}
+
 fn prepare_response(mut res: Response, code: StatusCode, body: &'static str) -> Response {
     *res.status_mut() = code;
     *res.body_mut() = body.into();
@@ -60,12 +173,42 @@ fn prepare_response(mut res: Response, code: StatusCode, body: &'static str) ->
 mod t {
     use super::*;
 
+    #[test]
+    fn response_204() {
+        let res = no_content(Response::default());
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[test]
+    fn response_301() {
+        let res = moved_permanently(Response::default(), "dir/");
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[hyper::header::LOCATION], "dir/");
+    }
+
+    #[test]
+    fn response_302() {
+        let res = found(Response::default(), "/app/");
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()[hyper::header::LOCATION], "/app/");
+    }
+
     #[test]
     fn response_304() {
         let res = not_modified(Response::default());
         assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
     }
 
+    #[test]
+    fn response_401() {
+        let res = unauthorized(Response::default());
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers()[hyper::header::WWW_AUTHENTICATE],
+            r#"Basic realm="sfz""#
+        );
+    }
+
     #[test]
     fn response_403() {
         let res = forbidden(Response::default());
@@ -84,9 +227,46 @@ mod t {
         assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
     }
 
+    #[test]
+    fn response_414() {
+        let res = uri_too_long(Response::default());
+        assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+    }
+
+    #[test]
+    fn response_416() {
+        let res = range_not_satisfiable(Response::default(), 10);
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[hyper::header::CONTENT_RANGE], "bytes */10");
+    }
+
     #[test]
     fn response_500() {
         let res = internal_server_error(Response::default());
         assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
+
+    #[tokio::test]
+    async fn error_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("403.html"), "<h1>Forbidden</h1>").unwrap();
+        std::fs::write(dir.path().join("200.html"), "<h1>OK</h1>").unwrap();
+        std::fs::write(dir.path().join("index.html"), "<h1>Index</h1>").unwrap();
+        let pages = ErrorPages::load(dir.path()).unwrap();
+        assert_eq!(pages.0.len(), 1);
+
+        let res = pages.apply(forbidden(Response::default()));
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            res.headers().typed_get::<ContentType>(),
+            Some(ContentType::html())
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"<h1>Forbidden</h1>");
+
+        // Fall back to plain text.
+        let res = pages.apply(internal_server_error(Response::default()));
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"500 Internal Server Error");
+    }
 }
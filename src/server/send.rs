@@ -6,24 +6,58 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cmp::Ordering;
+use std::collections::hash_map::{DefaultHasher, RandomState};
 use std::convert::AsRef;
 use std::fs::File;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::io::{self, BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Mutex;
 use std::task::Poll;
+use std::time::SystemTime;
 
-use bytes::BytesMut;
-use futures::Stream;
-use ignore::WalkBuilder;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use headers::ETag;
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use qstring::QString;
 use serde::Serialize;
 use tera::{Context, Tera};
 use zip::ZipWriter;
 
-use crate::extensions::PathExt;
+use crate::extensions::{PathExt, SystemTimeExt};
+use crate::server::etag::weak_etag;
+use crate::server::exclude::Exclude;
 use crate::server::PathType;
 
+/// File name of the index page of a directory.
+pub const INDEX_NAME: &str = "index.html";
+
+/// Size of each chunk read from files.
+const CHUNK_SIZE: usize = 4_096;
+
+/// Size of a pooled buffer chunks are split off from.
+const BUFFER_POOL_SIZE: usize = 64 * 1024;
+
+/// Number of JSON Lines walked ahead of the client.
+const NDJSON_BUFFER: usize = 64;
+
+/// File names rendered as README of a directory, in order of precedence.
+const README_NAMES: [&str; 2] = ["README.md", "README.markdown"];
+
+/// Characters percent-encoded in a path segment of generated URLs. All but
+/// unreserved ones per RFC 3986.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 /// Serializable `Item` that would be passed to Tera for template rendering.
 /// The order of struct fields is deremined to ensure sorting precedence.
 #[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
@@ -31,6 +65,114 @@ struct Item {
     path_type: PathType,
     name: String,
     path: String,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+/// Column of directory listing to sort by.
+#[derive(Debug, Clone, Copy, Default, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+/// Direction of sorting.
+#[derive(Debug, Clone, Copy, Default, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Representation of directory listing, negotiated via `Accept` header.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ListingFormat {
+    #[default]
+    Html,
+    Json,
+    Plain,
+    /// JSON Lines streamed entry by entry via [`send_dir_ndjson`].
+    Ndjson,
+}
+
+/// Sorting criteria of directory listing, parsed from `?sort=` and `&order=`
+/// query parameters.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SortBy {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+impl SortBy {
+    /// Parse sorting criteria from query string. Unrecognized values fall
+    /// back to default, which is ascending by name.
+    pub fn from_query(query: &QString) -> Self {
+        let key = match query.get("sort") {
+            Some("size") => SortKey::Size,
+            Some("modified") => SortKey::Modified,
+            _ => SortKey::Name,
+        };
+        let order = match query.get("order") {
+            Some("desc") => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        };
+        Self { key, order }
+    }
+
+    /// Compare two items. Directories always come first.
+    fn compare(&self, a: &Item, b: &Item) -> Ordering {
+        let is_file =
+            |item: &Item| matches!(item.path_type, PathType::File | PathType::SymlinkFile);
+        let ordering = match self.key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.mtime.cmp(&b.mtime),
+        }
+        .then_with(|| a.cmp(b));
+        let ordering = match self.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        is_file(a).cmp(&is_file(b)).then(ordering)
+    }
+}
+
+/// Clickable column header of directory listing.
+#[derive(Debug, Serialize)]
+struct Column {
+    title: &'static str,
+    sort: SortKey,
+    /// Order to apply when clicking on the header.
+    order: SortOrder,
+    active: bool,
+}
+
+/// Create column headers. Clicking on the active column toggles the order.
+fn create_columns(sort_by: SortBy) -> Vec<Column> {
+    [
+        ("Name", SortKey::Name),
+        ("Size", SortKey::Size),
+        ("Modified", SortKey::Modified),
+    ]
+    .into_iter()
+    .map(|(title, sort)| {
+        let active = sort_by.key == sort;
+        let order = match (active, sort_by.order) {
+            (true, SortOrder::Asc) => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        };
+        Column {
+            title,
+            sort,
+            order,
+            active,
+        }
+    })
+    .collect()
 }
 
 /// Breadcrumb represents a directory name and a path.
@@ -40,19 +182,105 @@ struct Breadcrumb<'a> {
     path: String,
 }
 
-/// Walking inside a directory recursively
-fn get_dir_contents<P: AsRef<Path>>(
+/// Prepare a walker respecting gitignore files and hidden entries on demand.
+///
+/// Unless `allow_git` is true, `.git` directories are skipped even if hidden
+/// entries are shown. Case is ignored as on case-insensitive filesystems.
+fn walk_builder<P: AsRef<Path>>(
     dir_path: P,
     with_ignore: bool,
     show_all: bool,
+    allow_git: bool,
     depth: Option<usize>,
-) -> ignore::Walk {
-    WalkBuilder::new(dir_path)
+) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir_path);
+    builder
         .standard_filters(false) // Disable all standard filters.
         .git_ignore(with_ignore)
         .hidden(!show_all) // Filter out hidden entries on demand.
-        .max_depth(depth) // Do not traverse subpaths.
-        .build()
+        .max_depth(depth); // Do not traverse subpaths.
+    if !allow_git {
+        builder.filter_entry(|entry| {
+            !entry
+                .file_name()
+                .as_encoded_bytes()
+                .eq_ignore_ascii_case(b".git")
+        });
+    }
+    builder
+}
+
+/// Walking inside a directory recursively
+///
+/// With more than one thread, entries are walked in parallel, collected and
+/// yielded sorted by path, since threads finish in arbitrary order. A
+/// sequential walk is lazy, so taking a few entries walks no further.
+fn get_dir_contents<'a>(
+    mut builder: WalkBuilder,
+    exclude: Option<&'a Exclude>,
+    threads: usize,
+) -> Box<dyn Iterator<Item = DirEntry> + 'a> {
+    let is_excluded = move |entry: &DirEntry| exclude.is_some_and(|e| e.is_excluded(entry.path()));
+    if threads <= 1 {
+        let entries = builder
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(move |entry| !is_excluded(entry));
+        return Box::new(entries);
+    }
+
+    let entries = Mutex::new(Vec::new());
+    builder.threads(threads).build_parallel().run(|| {
+        Box::new(|entry| {
+            if let Some(entry) = entry.ok().filter(|entry| !is_excluded(entry)) {
+                entries.lock().unwrap().push(entry);
+            }
+            WalkState::Continue
+        })
+    });
+    let mut entries = entries.into_inner().unwrap();
+    entries.sort_unstable_by(|a, b| a.path().cmp(b.path()));
+    Box::new(entries.into_iter())
+}
+
+/// Options of directory listing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListOptions<'a> {
+    /// Whether to show hidden and 'dot' files.
+    pub show_all: bool,
+    /// Whether to list `.git` directories along with other hidden files.
+    pub allow_git: bool,
+    /// Whether to respet gitignore files.
+    pub with_ignore: bool,
+    /// Glob patterns of paths to be excluded from the listing.
+    pub exclude: Option<&'a Exclude>,
+    /// The url path prefix optionally defined.
+    pub path_prefix: Option<&'a str>,
+    /// Sorting criteria of listed files.
+    pub sort_by: SortBy,
+    /// Representation of the listing.
+    pub format: ListingFormat,
+    /// Whether to render README file below the HTML listing.
+    pub readme: bool,
+    /// Whether to link to the parent directory in the HTML listing.
+    pub parent_link: bool,
+    /// Whether to omit the index page from the listing.
+    pub hide_index: bool,
+    /// Whether to generate links relative to the directory URL, which must
+    /// end with a slash, instead of absolute ones with path prefix.
+    pub relative_links: bool,
+    /// Number of threads walking the directory. Sequential if not above one.
+    pub walk_threads: usize,
+    /// Whether to indent the JSON listing for human readers.
+    pub json_pretty: bool,
+    /// Maximum number of entries to be listed. Entries are taken in the
+    /// order they are walked, which is by path for parallel walks, and then
+    /// sorted.
+    pub max_entries: Option<usize>,
+    /// Footer at the bottom of the HTML listing.
+    pub footer: Option<&'a str>,
+    /// Whether the footer is trusted as HTML instead of escaped as text.
+    pub footer_raw: bool,
 }
 
 /// Send a HTML page of all files under the path.
@@ -61,130 +289,477 @@ fn get_dir_contents<P: AsRef<Path>>(
 ///
 /// * `dir_path` - Directory to be listed files.
 /// * `base_path` - The base path resolving all filepaths under `dir_path`.
-/// * `show_all` - Whether to show hidden and 'dot' files.
-/// * `with_ignore` - Whether to respet gitignore files.
-/// * `path_prefix` - The url path prefix optionally defined
+/// * `options` - Options controlling what and how files are listed.
 pub fn send_dir<P1: AsRef<Path>, P2: AsRef<Path>>(
     dir_path: P1,
     base_path: P2,
-    show_all: bool,
-    with_ignore: bool,
-    path_prefix: Option<&str>,
+    options: &ListOptions,
 ) -> io::Result<(Vec<u8>, usize)> {
     let base_path = base_path.as_ref();
     let dir_path = dir_path.as_ref();
+    let ListOptions {
+        show_all,
+        allow_git,
+        with_ignore,
+        exclude,
+        sort_by,
+        format,
+        readme,
+        parent_link,
+        hide_index,
+        relative_links,
+        ..
+    } = *options;
     // Prepare dirname of current dir relative to base path.
-    let prefix = path_prefix.unwrap_or("");
+    let prefix = options.path_prefix.unwrap_or("");
 
     // Breadcrumbs for navigation.
-    let breadcrumbs = create_breadcrumbs(dir_path, base_path, prefix);
+    let mut breadcrumbs = create_breadcrumbs(dir_path, base_path, prefix);
+    if relative_links {
+        let depth = breadcrumbs.len() - 1;
+        for (i, breadcrumb) in breadcrumbs.iter_mut().enumerate() {
+            breadcrumb.path = relative_link(depth - i, "");
+        }
+    }
 
     // Collect filename and there links.
-    let files_iter = get_dir_contents(dir_path, with_ignore, show_all, Some(1))
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| dir_path != entry.path()) // Exclude `.`
-        .map(|entry| {
-            let abs_path = entry.path();
-            // Get relative path.
-            let rel_path = abs_path.strip_prefix(base_path).unwrap();
-            let rel_path_ref = rel_path.to_str().unwrap_or_default();
+    let max_entries = options.max_entries.unwrap_or(usize::MAX);
+    let mut entries = get_dir_contents(
+        walk_builder(dir_path, with_ignore, show_all, allow_git, Some(1)),
+        exclude,
+        options.walk_threads,
+    )
+    .filter(|entry| dir_path != entry.path()) // Exclude `.`
+    .filter(|entry| !(hide_index && entry.file_name() == INDEX_NAME && entry.path().is_file()));
+    let mut files = entries
+        .by_ref()
+        .take(max_entries)
+        .map(|entry| to_item(&entry, base_path, prefix, relative_links))
+        .collect::<Vec<_>>();
+    // Entries beyond the cap are only counted, and only for the notice of
+    // the HTML listing.
+    let rest = match format {
+        ListingFormat::Html => entries.count(),
+        _ => 0,
+    };
+    let truncated = (rest > 0).then_some((files.len(), files.len() + rest));
+    // Sort files (dir-first and then by requested column).
+    files.sort_unstable_by(|a, b| sort_by.compare(a, b));
 
-            Item {
-                path_type: abs_path.type_(),
-                name: rel_path.filename_str().to_owned(),
-                path: format!(
-                    "{}/{}",
-                    prefix,
-                    if cfg!(windows) {
-                        rel_path_ref.replace("\\", "/")
-                    } else {
-                        rel_path_ref.to_string()
-                    }
-                ),
-            }
-        });
+    // JSON listing is consumed by programs. No parent entry is needed.
+    if format == ListingFormat::Json {
+        let content = if options.json_pretty {
+            serde_json::to_vec_pretty(&files)
+        } else {
+            serde_json::to_vec(&files)
+        };
+        let content = content.map_err(io::Error::other)?;
+        let size = content.len();
+        return Ok((content, size));
+    }
 
-    let mut files = if base_path == dir_path {
-        // CWD == base dir
-        files_iter.collect::<Vec<_>>()
-    } else {
+    // Plain listing is read in terminals. One path per line, and directories
+    // end with a slash.
+    if format == ListingFormat::Plain {
+        let content = files
+            .iter()
+            .map(|item| match item.path_type {
+                PathType::Dir | PathType::SymlinkDir if !item.path.ends_with('/') => {
+                    format!("{}/\n", item.path)
+                }
+                _ => format!("{}\n", item.path),
+            })
+            .collect::<String>()
+            .into_bytes();
+        let size = content.len();
+        return Ok((content, size));
+    }
+
+    if parent_link && base_path != dir_path {
         // CWD == sub dir of base dir
-        // Append an item for popping back to parent directory.
+        // Prepend an item for popping back to parent directory.
 
-        let path = format!(
-            "{}/{}",
-            prefix,
-            dir_path
-                .parent()
-                .unwrap()
-                .strip_prefix(base_path)
-                .unwrap()
-                .to_str()
-                .unwrap()
+        let path = if relative_links {
+            relative_link(1, "")
+        } else {
+            format!(
+                "{}/{}",
+                prefix,
+                dir_path
+                    .parent()
+                    .unwrap()
+                    .strip_prefix(base_path)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            )
+        };
+
+        files.insert(
+            0,
+            Item {
+                name: "..".to_owned(),
+                path,
+                path_type: PathType::Dir,
+                size: None,
+                mtime: None,
+            },
         );
+    }
 
-        vec![Item {
-            name: "..".to_owned(),
-            path,
-            path_type: PathType::Dir,
-        }]
-        .into_iter()
-        .chain(files_iter)
-        .collect::<Vec<_>>()
-    };
-    // Sort files (dir-first and lexicographic ordering).
-    files.sort_unstable();
+    // Render README from listed files only, so that hidden and ignored
+    // ones are never leaked.
+    let readme = readme
+        .then(|| find_readme(dir_path, &files))
+        .flatten()
+        .map(|path| std::fs::read_to_string(path).map(|md| render_markdown(&md)))
+        .transpose()?;
+    let footer = options.footer.map(|footer| match options.footer_raw {
+        true => footer.to_owned(),
+        false => tera::escape_html(footer),
+    });
 
-    let content = render(dir_path.filename_str(), &files, &breadcrumbs).into_bytes();
+    let columns = create_columns(sort_by);
+    let content = render(
+        dir_path.filename_str(),
+        &files,
+        &breadcrumbs,
+        &columns,
+        readme.as_deref(),
+        truncated,
+        footer.as_deref(),
+    )
+    .into_bytes();
     let size = content.len();
     Ok((content, size))
 }
 
+/// Stream entries directly under a directory as JSON Lines, one object per
+/// entry.
+///
+/// Unlike [`send_dir`], entries are neither sorted nor buffered but emitted
+/// as they are walked, so that huge directories are listed in constant
+/// memory. The walk runs off the async runtime, ahead of the client by at
+/// most [`NDJSON_BUFFER`] entries, and stops once the stream is dropped.
+pub fn send_dir_ndjson<P1: AsRef<Path>, P2: AsRef<Path>>(
+    dir_path: P1,
+    base_path: P2,
+    options: &ListOptions,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    let dir_path = dir_path.as_ref().to_owned();
+    let base_path = base_path.as_ref().to_owned();
+    let prefix = options.path_prefix.unwrap_or("").to_owned();
+    let exclude = options.exclude.cloned();
+    let ListOptions {
+        show_all,
+        allow_git,
+        with_ignore,
+        hide_index,
+        relative_links,
+        max_entries,
+        ..
+    } = *options;
+    let (tx, mut rx) = tokio::sync::mpsc::channel(NDJSON_BUFFER);
+    tokio::task::spawn_blocking(move || {
+        let lines = walk_builder(&dir_path, with_ignore, show_all, allow_git, Some(1))
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| dir_path != entry.path()) // Exclude `.`
+            .filter(|entry| {
+                !exclude
+                    .as_ref()
+                    .is_some_and(|e| e.is_excluded(entry.path()))
+            })
+            .filter(|entry| {
+                !(hide_index && entry.file_name() == INDEX_NAME && entry.path().is_file())
+            })
+            .take(max_entries.unwrap_or(usize::MAX))
+            .map(|entry| {
+                let item = to_item(&entry, &base_path, &prefix, relative_links);
+                let mut line = serde_json::to_vec(&item).map_err(io::Error::other)?;
+                line.push(b'\n');
+                Ok(Bytes::from(line))
+            });
+        for line in lines {
+            // The receiver is gone along with the client.
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    futures::stream::poll_fn(move |cx| rx.poll_recv(cx))
+}
+
+/// Describe a walked entry as an item of the listing.
+fn to_item(entry: &DirEntry, base_path: &Path, prefix: &str, relative_links: bool) -> Item {
+    let abs_path = entry.path();
+    // Get relative path.
+    let rel_path = abs_path.strip_prefix(base_path).unwrap();
+    let rel_path_ref = rel_path.to_str().unwrap_or_default();
+    let meta = abs_path.metadata().ok();
+    let path_type = abs_path.type_();
+    let name = rel_path.filename_str().to_owned();
+    let path = if relative_links {
+        match path_type {
+            PathType::Dir | PathType::SymlinkDir => format!("{}/", name),
+            PathType::File | PathType::SymlinkFile => name.clone(),
+        }
+    } else {
+        format!(
+            "{}/{}",
+            prefix,
+            if cfg!(windows) {
+                rel_path_ref.replace("\\", "/")
+            } else {
+                rel_path_ref.to_string()
+            }
+        )
+    };
+
+    Item {
+        size: meta.as_ref().filter(|m| m.is_file()).map(|m| m.len()),
+        mtime: meta
+            .and_then(|m| m.modified().ok())
+            .map(|mtime| mtime.timestamp()),
+        path_type,
+        name,
+        path,
+    }
+}
+
 #[derive(Debug)]
 pub struct FileStream<T> {
     reader: Mutex<T>,
+    /// Buffer chunks are split off from, if pooling is on.
+    pool: Option<Mutex<BytesMut>>,
+    /// Length declared to clients, and bytes read so far. Reaching EOF
+    /// early means the file was truncated while being sent.
+    len: u64,
+    sent: u64,
 }
 
-impl<T: Read> Stream for FileStream<T> {
+impl<T> FileStream<T> {
+    fn new(reader: T, len: u64) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            pool: None,
+            len,
+            sent: 0,
+        }
+    }
+
+    /// Split chunks off a shared buffer instead of allocating one per read.
+    ///
+    /// Chunks are handed over to hyper, so the buffer is reclaimed only once
+    /// all chunks split off from it are dropped. Otherwise a new buffer is
+    /// allocated.
+    pub fn buffer_pool(mut self, enabled: bool) -> Self {
+        self.pool = enabled.then(|| Mutex::new(BytesMut::new()));
+        self
+    }
+}
+
+impl<T: Read + Unpin> Stream for FileStream<T> {
     type Item = io::Result<hyper::body::Bytes>;
 
     fn poll_next(self: Pin<&mut Self>, _: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut r = match self.reader.lock() {
+        let this = self.get_mut();
+        let mut r = match this.reader.lock() {
             Ok(r) => r,
             Err(e) => {
                 eprintln!("{e:?}");
-                let e = io::Error::new(io::ErrorKind::Other, "Failed to read file");
+                let e = io::Error::other("Failed to read file");
                 return Poll::Ready(Some(Err(e)));
             }
         };
-        let mut buf = BytesMut::zeroed(4_096);
-        match r.read(&mut buf[..]) {
-            Ok(bytes) => {
-                if bytes == 0 {
-                    Poll::Ready(None)
-                } else {
-                    buf.truncate(bytes);
-                    Poll::Ready(Some(Ok(buf.freeze())))
+        let chunk = match &this.pool {
+            Some(pool) => {
+                let mut pool = match pool.lock() {
+                    Ok(pool) => pool,
+                    Err(_) => {
+                        return Poll::Ready(Some(Err(io::Error::other("Failed to read file"))))
+                    }
+                };
+                if pool.capacity() < CHUNK_SIZE {
+                    pool.reserve(BUFFER_POOL_SIZE);
                 }
+                read_chunk(&mut *r, &mut pool)
             }
+            None => read_chunk(&mut *r, &mut BytesMut::new()),
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                this.sent += chunk.len() as u64;
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            // Abort rather than silently ending short of the declared
+            // length, so that clients see an incomplete transfer.
+            Ok(None) if this.sent < this.len => {
+                eprintln!(
+                    "warning: file truncated while being sent, {} of {} bytes sent",
+                    this.sent, this.len
+                );
+                this.len = this.sent;
+                let e = io::Error::new(io::ErrorKind::UnexpectedEof, "file truncated");
+                Poll::Ready(Some(Err(e)))
+            }
+            Ok(None) => Poll::Ready(None),
             Err(e) => Poll::Ready(Some(Err(e))),
         }
     }
 }
 
+/// Read a chunk at the front of `buf`, and split it off. `None` at EOF.
+fn read_chunk<R: Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut BytesMut,
+) -> io::Result<Option<hyper::body::Bytes>> {
+    buf.resize(CHUNK_SIZE, 0);
+    let bytes = reader.read(&mut buf[..])?;
+    buf.truncate(bytes);
+    Ok((bytes > 0).then(|| buf.split().freeze()))
+}
+
 /// Send a stream of file to client.
 pub fn send_file<P: AsRef<Path>>(file_path: P) -> io::Result<(FileStream<BufReader<File>>, u64)> {
     let file = File::open(file_path)?;
     let size = file.metadata()?.len();
-    let reader = Mutex::new(BufReader::new(file));
-    Ok((FileStream { reader }, size))
+    let reader = BufReader::new(file);
+    Ok((FileStream::new(reader, size), size))
+}
+
+/// Compute validators of a directory download from entries to be archived.
+///
+/// Returns the newest modified time among entries, and a weak ETag
+/// fingerprinting the set of entries along with their sizes and modified
+/// times. Adding, removing or touching any entry changes the ETag.
+pub fn dir_validators<P: AsRef<Path>>(
+    dir_path: P,
+    show_all: bool,
+    allow_git: bool,
+    with_ignore: bool,
+    exclude: Option<&Exclude>,
+    walk_threads: usize,
+) -> (SystemTime, ETag) {
+    let dir_path = dir_path.as_ref();
+    let mut entries = get_dir_contents(
+        walk_builder(dir_path, with_ignore, show_all, allow_git, None),
+        exclude,
+        walk_threads,
+    )
+    .filter_map(|entry| {
+        let meta = entry.metadata().ok()?;
+        let mtime = meta.modified().ok()?;
+        Some((entry.into_path(), meta.len(), mtime))
+    })
+    .collect::<Vec<_>>();
+    entries.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    let newest = entries
+        .iter()
+        .map(|(_, _, mtime)| *mtime)
+        .max()
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let etag = weak_etag(&format!("{}-{:x}", newest.timestamp(), hasher.finish()));
+    (newest, etag)
+}
+
+/// Check if a directory has no entries to be archived.
+pub fn dir_is_empty<P: AsRef<Path>>(
+    dir_path: P,
+    show_all: bool,
+    allow_git: bool,
+    with_ignore: bool,
+    exclude: Option<&Exclude>,
+) -> bool {
+    let dir_path = dir_path.as_ref();
+    get_dir_contents(
+        walk_builder(dir_path, with_ignore, show_all, allow_git, Some(1)),
+        exclude,
+        1,
+    )
+    .all(|entry| entry.path() == dir_path)
+}
+
+/// Percent-encode each segment of a relative path, joined with slashes.
+///
+/// Returns `None` if the path is not valid UTF-8.
+pub fn encode_path(rel_path: &Path) -> Option<String> {
+    let segments = rel_path
+        .components()
+        .map(|c| Some(utf8_percent_encode(c.as_os_str().to_str()?, SEGMENT).to_string()))
+        .collect::<Option<Vec<String>>>()?;
+    Some(segments.join("/"))
+}
+
+/// Generate a sitemap listing all HTML files under the base path.
+///
+/// Each location is `base_url` joined with the percent-encoded path of the
+/// file relative to the base path.
+pub fn send_sitemap<P: AsRef<Path>>(
+    base_path: P,
+    base_url: &str,
+    show_all: bool,
+    allow_git: bool,
+    with_ignore: bool,
+    exclude: Option<&Exclude>,
+) -> String {
+    let base_path = base_path.as_ref();
+    let base_url = base_url.trim_end_matches('/');
+    let mut pages = get_dir_contents(
+        walk_builder(base_path, with_ignore, show_all, allow_git, None),
+        exclude,
+        1,
+    )
+    .filter(|entry| entry.path().is_file())
+    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "html"))
+    .filter_map(|entry| {
+        let rel_path = entry.path().strip_prefix(base_path).ok()?;
+        Some((encode_path(rel_path)?, entry.path().mtime()))
+    })
+    .collect::<Vec<_>>();
+    pages.sort_unstable();
+
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (path, mtime) in pages {
+        let lastmod = DateTime::<Utc>::from(mtime).format("%Y-%m-%d");
+        sitemap.push_str(&format!(
+            "  <url><loc>{}/{}</loc><lastmod>{}</lastmod></url>\n",
+            xml_escape(base_url),
+            path,
+            lastmod,
+        ));
+    }
+    sitemap.push_str("</urlset>\n");
+    sitemap
+}
+
+/// Escape characters with special meaning in XML.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
 }
 
 /// Sending a directory as zip buffer
+///
+/// If `root_name` is given, all entries are nested under a top-level folder
+/// of that name, so that extracting the archive won't scatter files around.
 pub fn send_dir_as_zip<P: AsRef<Path>>(
     dir_path: P,
     show_all: bool,
+    allow_git: bool,
     with_ignore: bool,
+    exclude: Option<&Exclude>,
+    root_name: Option<&str>,
+    walk_threads: usize,
 ) -> io::Result<(FileStream<BufReader<File>>, u64)> {
     let dir_path = dir_path.as_ref();
 
@@ -196,22 +771,35 @@ pub fn send_dir_as_zip<P: AsRef<Path>>(
         .compression_method(zip::CompressionMethod::Stored)
         .unix_permissions(0o755);
 
+    if let Some(root_name) = root_name {
+        zip_writer
+            .add_directory(root_name, zip_options)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+
     // Recursively finding files and directories
-    let files_iter = get_dir_contents(dir_path, with_ignore, show_all, None)
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.path() != dir_path);
+    let files_iter = get_dir_contents(
+        walk_builder(dir_path, with_ignore, show_all, allow_git, None),
+        exclude,
+        walk_threads,
+    )
+    .filter(|entry| entry.path() != dir_path);
 
     for dir_entry in files_iter {
         let file_path = dir_entry.path();
         let name = file_path.strip_prefix(dir_path).unwrap().to_str().unwrap();
+        let name = match root_name {
+            Some(root_name) => format!("{}/{}", root_name, name),
+            None => name.to_owned(),
+        };
 
         if file_path.is_dir() {
             zip_writer
-                .add_directory(name, zip_options)
+                .add_directory(&name, zip_options)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         } else {
             zip_writer
-                .start_file(name, zip_options)
+                .start_file(&name, zip_options)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
             let mut file = File::open(file_path)?;
 
@@ -226,8 +814,8 @@ pub fn send_dir_as_zip<P: AsRef<Path>>(
     zip.seek(SeekFrom::Start(0))?;
 
     let size = zip.metadata()?.len();
-    let reader = Mutex::new(BufReader::new(zip));
-    Ok((FileStream { reader }, size))
+    let reader = BufReader::new(zip);
+    Ok((FileStream::new(reader, size), size))
 }
 
 /// Send a stream with specific range.
@@ -240,7 +828,7 @@ pub fn send_file_with_range<P: AsRef<Path>>(
     file_path: P,
     range: (u64, u64),
 ) -> io::Result<(FileStream<std::io::Take<BufReader<File>>>, u64)> {
-    let (start, end) = range; // TODO: should return HTTP 416
+    let (start, end) = range;
     if end < start {
         return Err(io::Error::from(io::ErrorKind::InvalidInput));
     }
@@ -249,13 +837,88 @@ pub fn send_file_with_range<P: AsRef<Path>>(
     let max_end = f.metadata()?.len() - 1;
     f.seek(SeekFrom::Start(start))?;
 
-    let reader = Mutex::new(BufReader::new(f).take(end - start + 1));
+    let reader = BufReader::new(f).take(end - start + 1);
     let size = if start > max_end {
         0
     } else {
         std::cmp::min(end, max_end) - start + 1
     };
-    Ok((FileStream { reader }, size))
+    Ok((FileStream::new(reader, size), size))
+}
+
+/// Generator of boundaries delimiting parts of multipart bodies.
+///
+/// Boundaries are derived from a seed, which is random by default. A fixed
+/// seed makes the sequence of boundaries, and so whole bodies, predictable.
+pub struct BoundaryGenerator {
+    seed: u64,
+    counter: AtomicU64,
+}
+
+impl BoundaryGenerator {
+    /// Create a generator yielding the same boundaries for the same `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Generate the next boundary.
+    pub fn next_boundary(&self) -> String {
+        let count = self.counter.fetch_add(1, AtomicOrdering::Relaxed);
+        // SplitMix64, which is good enough to never collide with contents.
+        let mut z = self
+            .seed
+            .wrapping_add(count.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        format!("{:016x}", z ^ (z >> 31))
+    }
+}
+
+impl Default for BoundaryGenerator {
+    /// Create a generator of a random seed.
+    fn default() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+}
+
+/// Send multiple ranges of a file as a `multipart/byteranges` body.
+///
+/// Each part carries its own `Content-Type` and `Content-Range`, and parts
+/// are delimited by `boundary`. Returns the stream along with its exact
+/// length.
+///
+/// # Parameters
+///
+/// * `file_path` - Path to the file that is going to send.
+/// * `ranges` - Tuples of `(start, end)` range (inclusive) within the file.
+/// * `content_type` - Media type of the file.
+/// * `boundary` - Delimiter of parts, which must not occur in the file.
+pub fn send_file_with_ranges(
+    file_path: &Path,
+    ranges: &[(u64, u64)],
+    content_type: &str,
+    boundary: &str,
+) -> io::Result<(impl Stream<Item = io::Result<Bytes>> + Send, u64)> {
+    let complete_length = file_path.metadata()?.len();
+    let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut size = 0;
+    for &range in ranges {
+        let header = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, range.0, range.1, complete_length,
+        );
+        let (stream, len) = send_file_with_range(file_path, range)?;
+        size += header.len() as u64 + len;
+        parts.push(futures::stream::once(futures::future::ok(Bytes::from(header))).boxed());
+        parts.push(stream.boxed());
+    }
+    let trailer = format!("\r\n--{}--\r\n", boundary);
+    size += trailer.len() as u64;
+    parts.push(futures::stream::once(futures::future::ok(Bytes::from(trailer))).boxed());
+    Ok((futures::stream::iter(parts).flatten(), size))
 }
 
 /// Create breadcrumbs for navigation.
@@ -288,12 +951,55 @@ fn create_breadcrumbs<'a>(
         .collect::<Vec<_>>()
 }
 
+/// Relative link to `tail` under the directory `ups` levels above the
+/// current one.
+fn relative_link(ups: usize, tail: &str) -> String {
+    match "../".repeat(ups) + tail {
+        link if link.is_empty() => "./".to_owned(),
+        link => link,
+    }
+}
+
+/// Find a README file among listed files of a directory, in the order of
+/// `README_NAMES`.
+fn find_readme(dir_path: &Path, files: &[Item]) -> Option<PathBuf> {
+    README_NAMES.iter().find_map(|readme| {
+        files
+            .iter()
+            .find(|file| file.path_type == PathType::File && file.name == *readme)
+            .map(|file| dir_path.join(&file.name))
+    })
+}
+
+/// Render markdown into HTML.
+fn render_markdown(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all());
+    let mut output = String::new();
+    pulldown_cmark::html::push_html(&mut output, parser);
+    output
+}
+
 /// Render page with Tera template engine.
-fn render(dir_name: &str, files: &[Item], breadcrumbs: &[Breadcrumb]) -> String {
+fn render(
+    dir_name: &str,
+    files: &[Item],
+    breadcrumbs: &[Breadcrumb],
+    columns: &[Column],
+    readme: Option<&str>,
+    truncated: Option<(usize, usize)>,
+    footer: Option<&str>,
+) -> String {
     let mut ctx = Context::new();
+    ctx.insert("readme", &readme);
+    ctx.insert("footer", &footer);
+    if let Some((shown, total)) = truncated {
+        ctx.insert("shown", &shown);
+        ctx.insert("total", &total);
+    }
     ctx.insert("dir_name", dir_name);
     ctx.insert("files", files);
     ctx.insert("breadcrumbs", breadcrumbs);
+    ctx.insert("columns", columns);
     ctx.insert("style", include_str!("style.css"));
     Tera::one_off(include_str!("index.html"), &ctx, true)
         .unwrap_or_else(|e| format!("500 Internal server error: {}", e))
@@ -305,9 +1011,99 @@ mod t {
 
     #[test]
     fn render_successfully() {
-        let page = render("", &vec![], &vec![]);
+        let page = render("", &[], &[], &[], None, None, None);
         assert!(page.starts_with("<!DOCTYPE html>"))
     }
+
+    #[test]
+    fn render_sort_links() {
+        let breadcrumbs = create_breadcrumbs(Path::new("/a/b"), Path::new("/a"), "/prefix");
+        let sort_by = SortBy {
+            key: SortKey::Size,
+            order: SortOrder::Asc,
+        };
+        let page = render(
+            "b",
+            &[],
+            &breadcrumbs,
+            &create_columns(sort_by),
+            None,
+            None,
+            None,
+        );
+        // Slashes are escaped as HTML entities by Tera.
+        let href = |query| format!(r#"href="&#x2F;prefix&#x2F;b?{}""#, query);
+        assert!(page.contains(&href("sort=name&amp;order=asc")));
+        assert!(page.contains(&href("sort=size&amp;order=desc")));
+        assert!(page.contains(&href("sort=modified&amp;order=asc")));
+    }
+
+    #[test]
+    fn render_size_and_mtime() {
+        let item = |name: &str, size, mtime| Item {
+            path_type: PathType::File,
+            name: name.to_owned(),
+            path: format!("/{}", name),
+            size,
+            mtime,
+        };
+        let files = [
+            item("..", None, None),
+            item("a", Some(1_234_567), Some(0)),
+            item("b", Some(0), None),
+        ];
+        let page = render("", &files, &[], &[], None, None, None);
+        assert!(page.contains("1.18 MB"));
+        assert!(page.contains("1970-01-01 00:00"));
+        assert!(page.contains("0 B"));
+        assert_eq!(page.matches("<small>").count(), 2);
+    }
+
+    #[test]
+    fn sort_by_from_query() {
+        let sort_by = SortBy::from_query(&QString::from("sort=size&order=desc"));
+        assert_eq!(sort_by.key, SortKey::Size);
+        assert_eq!(sort_by.order, SortOrder::Desc);
+
+        let sort_by = SortBy::from_query(&QString::from("sort=unknown"));
+        assert_eq!(sort_by, SortBy::default());
+    }
+
+    #[test]
+    fn sort_by_compare() {
+        let item = |path_type, name: &str, size| Item {
+            path_type,
+            name: name.to_owned(),
+            path: format!("/{}", name),
+            size,
+            mtime: None,
+        };
+        let mut files = vec![
+            item(PathType::File, "a", Some(3)),
+            item(PathType::File, "b", Some(1)),
+            item(PathType::Dir, "c", None),
+            item(PathType::File, "d", Some(2)),
+        ];
+        let names = |files: &[Item]| files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+
+        let sort_by = SortBy::default();
+        files.sort_unstable_by(|a, b| sort_by.compare(a, b));
+        assert_eq!(names(&files), ["c", "a", "b", "d"]);
+
+        let sort_by = SortBy {
+            key: SortKey::Size,
+            order: SortOrder::Asc,
+        };
+        files.sort_unstable_by(|a, b| sort_by.compare(a, b));
+        assert_eq!(names(&files), ["c", "b", "d", "a"]);
+
+        let sort_by = SortBy {
+            key: SortKey::Size,
+            order: SortOrder::Desc,
+        };
+        files.sort_unstable_by(|a, b| sort_by.compare(a, b));
+        assert_eq!(names(&files), ["c", "a", "d", "b"]);
+    }
     #[test]
     fn breadcrumbs() {
         // Only one level
@@ -412,6 +1208,260 @@ mod t_send {
     #[test]
     fn t_send_dir() {}
 
+    #[test]
+    fn t_send_dir_with_readme() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("README.md"), "# Hello\n\n*world*").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let options = ListOptions {
+            readme: true,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains("<h1>Hello</h1>"));
+        assert!(page.contains("<em>world</em>"));
+        // File list is still available.
+        assert!(page.contains(r#"title="file.txt""#));
+
+        let options = ListOptions::default();
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(!page.contains("<h1>Hello</h1>"));
+    }
+
+    #[test]
+    fn t_send_dir_parent_link() {
+        let base_path = dir_with_sub_dir_path();
+        let dir_path = base_path.join("sub_dir");
+        let parent_link = r#"title="..""#;
+
+        let options = ListOptions {
+            parent_link: true,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(&dir_path, &base_path, &options).unwrap();
+        assert!(String::from_utf8(content).unwrap().contains(parent_link));
+
+        let options = ListOptions::default();
+        let (content, _) = send_dir(&dir_path, &base_path, &options).unwrap();
+        assert!(!String::from_utf8(content).unwrap().contains(parent_link));
+    }
+
+    #[test]
+    fn t_send_dir_relative_links() {
+        let base_path = dir_with_sub_dir_path();
+        let dir_path = base_path.join("sub_dir");
+        let options = ListOptions {
+            path_prefix: Some("/prefix"),
+            parent_link: true,
+            relative_links: true,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(&base_path, &base_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        // Slashes are escaped as HTML entities by Tera.
+        assert!(page.contains(r#"href="sub_dir&#x2F;""#));
+        assert!(page.contains(r#"href="file.txt""#));
+        assert!(page.contains(r#"href=".&#x2F;?sort=size&amp;order=asc""#));
+        assert!(!page.contains("prefix"));
+
+        let (content, _) = send_dir(&dir_path, &base_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains(r#"href="..&#x2F;" title="..""#));
+        assert!(page.contains(r#"href="..&#x2F;"><b>"#));
+        assert!(page.contains(r#"href="file.txt""#));
+
+        // Absolute and prefixed by default.
+        let options = ListOptions {
+            path_prefix: Some("/prefix"),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(&dir_path, &base_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains(r#"href="&#x2F;prefix&#x2F;sub_dir&#x2F;file.txt""#));
+    }
+
+    #[test]
+    fn t_send_dir_hide_index() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(INDEX_NAME), "<h1>index</h1>").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let options = ListOptions {
+            format: ListingFormat::Json,
+            hide_index: true,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains("file.txt"));
+        assert!(!page.contains(INDEX_NAME));
+
+        let options = ListOptions {
+            format: ListingFormat::Json,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        assert!(String::from_utf8(content).unwrap().contains(INDEX_NAME));
+    }
+
+    #[test]
+    fn t_send_dir_json_pretty() {
+        let dir_path = dir_with_sub_dir_path();
+        let base_path = dir_path.parent().unwrap();
+        let options = ListOptions {
+            format: ListingFormat::Json,
+            ..Default::default()
+        };
+        let (compact, _) = send_dir(&dir_path, base_path, &options).unwrap();
+        let options = ListOptions {
+            json_pretty: true,
+            ..options
+        };
+        let (pretty, size) = send_dir(&dir_path, base_path, &options).unwrap();
+        assert_eq!(size, pretty.len());
+        assert!(!compact.contains(&b'\n'));
+        assert!(String::from_utf8(pretty.clone())
+            .unwrap()
+            .contains("\n  {\n    "));
+        let compact: serde_json::Value = serde_json::from_slice(&compact).unwrap();
+        let pretty: serde_json::Value = serde_json::from_slice(&pretty).unwrap();
+        assert_eq!(compact, pretty);
+    }
+
+    #[tokio::test]
+    async fn t_send_dir_ndjson() {
+        use futures::TryStreamExt;
+
+        let dir_path = dir_with_sub_dir_path();
+        let base_path = dir_path.parent().unwrap();
+        let options = ListOptions {
+            format: ListingFormat::Ndjson,
+            ..Default::default()
+        };
+        let chunks = send_dir_ndjson(&dir_path, base_path, &options)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        // Each chunk is a line of exactly one entry.
+        let mut names = chunks
+            .iter()
+            .map(|chunk| {
+                assert_eq!(chunk.iter().filter(|b| **b == b'\n').count(), 1);
+                let item: serde_json::Value = serde_json::from_slice(chunk).unwrap();
+                item["name"].as_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["file.txt", "sub_dir"]);
+
+        let options = ListOptions {
+            max_entries: Some(1),
+            ..options
+        };
+        let chunks = send_dir_ndjson(&dir_path, base_path, &options)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 1);
+    }
+
+    #[test]
+    fn t_send_dir_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..5 {
+            std::fs::write(dir.path().join(format!("{}.txt", i)), "").unwrap();
+        }
+        let options = ListOptions {
+            max_entries: Some(2),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert_eq!(page.matches(".txt</a>").count(), 2);
+        assert!(page.contains("Showing 2 of 5 entries"));
+
+        // Parallel walks cap the same entries on every request.
+        for _ in 0..4 {
+            let options = ListOptions {
+                max_entries: Some(2),
+                walk_threads: 4,
+                ..Default::default()
+            };
+            let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+            let page = String::from_utf8(content).unwrap();
+            assert_eq!(page.matches(".txt</a>").count(), 2);
+            assert!(page.contains(">0.txt</a>") && page.contains(">1.txt</a>"));
+            assert!(page.contains("Showing 2 of 5 entries"));
+        }
+
+        let options = ListOptions {
+            max_entries: Some(0),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains("Showing 0 of 5 entries"));
+
+        let options = ListOptions {
+            max_entries: Some(5),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert_eq!(page.matches(".txt</a>").count(), 5);
+        assert!(!page.contains("Showing"));
+
+        let options = ListOptions {
+            format: ListingFormat::Plain,
+            max_entries: Some(3),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(dir.path(), dir.path(), &options).unwrap();
+        assert_eq!(String::from_utf8(content).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn t_send_dir_footer() {
+        let dir_path = dir_with_sub_dir_path();
+        let footer = "<b>&copy; 2018 Weihang Lo</b>";
+        let options = ListOptions {
+            footer: Some(footer),
+            ..Default::default()
+        };
+        let (content, _) = send_dir(&dir_path, &dir_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(
+            page.contains("<footer>&lt;b&gt;&amp;copy; 2018 Weihang Lo&lt;&#x2F;b&gt;</footer>")
+        );
+
+        let options = ListOptions {
+            footer: Some(footer),
+            footer_raw: true,
+            ..Default::default()
+        };
+        let (content, _) = send_dir(&dir_path, &dir_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert!(page.contains(&format!("<footer>{}</footer>", footer)));
+    }
+
+    #[test]
+    fn t_send_dir_plain() {
+        let dir_path = dir_with_sub_dir_path();
+        let base_path = dir_path.parent().unwrap();
+        let options = ListOptions {
+            format: ListingFormat::Plain,
+            ..Default::default()
+        };
+        let (content, size) = send_dir(&dir_path, base_path, &options).unwrap();
+        let page = String::from_utf8(content).unwrap();
+        assert_eq!(size, page.len());
+        assert_eq!(
+            page,
+            "/dir_with_sub_dirs/sub_dir/\n/dir_with_sub_dirs/file.txt\n"
+        );
+    }
+
     async fn stream_to_vec<T: Read + std::marker::Unpin>(mut s: FileStream<T>) -> Vec<u8> {
         let mut buf = vec![];
         while let Some(r) = s.next().await {
@@ -431,6 +1481,49 @@ mod t_send {
         assert_eq!(&buf, b"01234567");
     }
 
+    #[tokio::test]
+    async fn t_send_file_buffer_pool() {
+        let data: Vec<u8> = (0..BUFFER_POOL_SIZE * 3 + 17).map(|i| i as u8).collect();
+        let stream = || {
+            let reader = std::io::Cursor::new(data.clone());
+            FileStream::new(reader, data.len() as u64).buffer_pool(true)
+        };
+        // Chunks dropped right away let the buffer be reclaimed.
+        assert_eq!(stream_to_vec(stream()).await, data);
+        // Chunks held on to must not be overwritten by later reads.
+        let chunks: Vec<_> = stream().collect().await;
+        let buf: Vec<u8> = chunks.into_iter().flat_map(|c| c.unwrap()).collect();
+        assert_eq!(buf, data);
+    }
+
+    #[tokio::test]
+    async fn t_send_file_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.log");
+        std::fs::write(&path, vec![0; CHUNK_SIZE * 8]).unwrap();
+        let (mut s, size) = send_file(&path).unwrap();
+        assert_eq!(size, CHUNK_SIZE as u64 * 8);
+        assert!(s.next().await.unwrap().is_ok());
+
+        // Rotated while being sent.
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let mut sent = CHUNK_SIZE;
+        let err = loop {
+            match s.next().await.unwrap() {
+                Ok(chunk) => sent += chunk.len(),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(sent < CHUNK_SIZE * 8);
+        assert!(s.next().await.is_none());
+    }
+
     #[test]
     fn t_send_file_not_found() {
         let buf = send_file(missing_file_path());
@@ -471,6 +1564,34 @@ mod t_send {
         assert_eq!(size, 0);
     }
 
+    #[tokio::test]
+    async fn t_send_file_with_ranges() {
+        use futures::TryStreamExt;
+
+        let path = file_txt_path();
+        let (s, size) = send_file_with_ranges(&path, &[(0, 1), (6, 7)], "text/plain", "b").unwrap();
+        let buf = s.map_ok(|b| b.to_vec()).try_concat().await.unwrap();
+        assert_eq!(buf.len() as u64, size);
+        assert_eq!(
+            buf,
+            concat!(
+                "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/8\r\n\r\n01",
+                "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 6-7/8\r\n\r\n67",
+                "\r\n--b--\r\n",
+            )
+            .as_bytes()
+        );
+    }
+
+    #[test]
+    fn boundary_generator() {
+        let seeded = BoundaryGenerator::with_seed(0);
+        assert_eq!(seeded.next_boundary(), "e220a8397b1dcdaf");
+        assert_eq!(seeded.next_boundary(), "6e789e6aa1b965f4");
+        let random = BoundaryGenerator::default();
+        assert_ne!(random.next_boundary(), random.next_boundary());
+    }
+
     #[test]
     fn t_send_file_with_range_not_found() {
         let buf = send_file_with_range(missing_file_path(), (0, 0));
@@ -486,16 +1607,104 @@ mod t_send {
 
     #[tokio::test]
     async fn t_send_dir_as_zip() {
-        let s = send_dir_as_zip(dir_with_sub_dir_path(), true, false);
+        let s = send_dir_as_zip(dir_with_sub_dir_path(), true, false, false, None, None, 1);
         assert!(s.is_ok());
 
         let (s, size) = s.unwrap();
         assert!(size > 0);
 
         let v = stream_to_vec(s).await;
-        assert!(v.len() > 0);
+        assert!(!v.is_empty());
 
         // https://users.cs.jmu.edu/buchhofp/forensics/formats/pkzip.html#localheader
         assert_eq!(&v[0..4], &[0x50, 0x4b, 0x03, 0x04]);
     }
+
+    #[test]
+    fn t_dir_validators() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let (mtime, etag) = dir_validators(dir.path(), false, false, false, None, 1);
+        assert!(mtime > SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            etag,
+            dir_validators(dir.path(), false, false, false, None, 1).1
+        );
+
+        // Any change in the entry set results in a different ETag.
+        std::fs::write(dir.path().join("b.txt"), "b").unwrap();
+        assert_ne!(
+            etag,
+            dir_validators(dir.path(), false, false, false, None, 1).1
+        );
+    }
+
+    #[test]
+    fn t_get_dir_contents_parallel() {
+        let dir_path = dir_with_sub_dir_path();
+        let paths = |threads| {
+            get_dir_contents(
+                walk_builder(&dir_path, false, true, false, None),
+                None,
+                threads,
+            )
+            .map(DirEntry::into_path)
+            .collect::<Vec<_>>()
+        };
+        let mut sequential = paths(1);
+        sequential.sort_unstable();
+        assert!(sequential.len() > 2);
+        assert_eq!(paths(4), sequential);
+    }
+
+    #[test]
+    fn t_send_dir_parallel() {
+        let dir_path = dir_with_sub_dir_path();
+        let base_path = dir_path.parent().unwrap();
+        let listing = |walk_threads| {
+            let options = ListOptions {
+                format: ListingFormat::Json,
+                walk_threads,
+                ..Default::default()
+            };
+            send_dir(&dir_path, base_path, &options).unwrap().0
+        };
+        assert_eq!(listing(4), listing(1));
+    }
+
+    #[test]
+    fn t_dir_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(dir_is_empty(dir.path(), false, false, false, None));
+        std::fs::write(dir.path().join(".hidden"), "").unwrap();
+        assert!(dir_is_empty(dir.path(), false, false, false, None));
+        assert!(!dir_is_empty(dir.path(), true, false, false, None));
+    }
+
+    #[tokio::test]
+    async fn t_send_dir_as_zip_with_root_name() {
+        let (s, _) = send_dir_as_zip(
+            dir_with_sub_dir_path(),
+            true,
+            false,
+            false,
+            None,
+            Some("root"),
+            1,
+        )
+        .unwrap();
+        let v = stream_to_vec(s).await;
+        let zip = zip::ZipArchive::new(io::Cursor::new(v)).unwrap();
+        let mut names = zip.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "root/",
+                "root/file.txt",
+                "root/sub_dir/",
+                "root/sub_dir/file.txt"
+            ]
+        );
+    }
 }
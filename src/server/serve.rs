@@ -7,41 +7,100 @@
 // except according to those terms.
 
 use std::convert::{AsRef, Infallible};
-use std::io;
+use std::fmt;
+use std::fs::{File, Metadata};
+use std::future::Future;
+use std::io::{self, Read};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::Utf8Error;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use chrono::Local;
-use futures::TryStreamExt as _;
+use futures::future::Either;
+use futures::{StreamExt as _, TryStreamExt as _};
+use globset::GlobSet;
+use headers::authorization::{Authorization, Basic};
 use headers::{
     AcceptRanges, AccessControlAllowHeaders, AccessControlAllowOrigin, CacheControl, ContentLength,
-    ContentType, ETag, HeaderMapExt, LastModified, Range, Server,
+    ContentRange, ContentType, ETag, HeaderMapExt, Host, LastModified, Range, Server,
 };
 // Can not use headers::ContentDisposition. Because of https://github.com/hyperium/headers/issues/8
-use hyper::header::{HeaderValue, CONTENT_DISPOSITION};
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, StatusCode};
+use hyper::{Body, Method, StatusCode};
 use ignore::gitignore::Gitignore;
 use mime_guess::mime;
+use notify::RecommendedWatcher;
 use percent_encoding::percent_decode;
 use qstring::QString;
+use regex::Regex;
 use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use xxhash_rust::xxh3::xxh3_64;
 
 use crate::cli::Args;
-use crate::extensions::{MimeExt, PathExt, SystemTimeExt};
+use crate::extensions::{MimeExt, PathExt};
 use crate::http::conditional_requests::{is_fresh, is_precondition_failed};
-use crate::http::content_encoding::{compress_stream, get_prior_encoding, should_compress};
-use crate::http::range_requests::{is_range_fresh, is_satisfiable_range};
+use crate::http::content_encoding::{
+    compress_stream, decompress_stream, get_encoding_quality, get_prior_encoding, should_compress,
+    Level, BR, GZIP, ZSTD,
+};
+use crate::http::range_requests::{is_range_fresh, is_satisfiable_range, RangeResult};
 
-use crate::server::send::{send_dir, send_dir_as_zip, send_file, send_file_with_range};
-use crate::server::{res, Request, Response};
+use crate::server::ansi::strip_ansi;
+use crate::server::etag::{derived_etag, weak_etag, EtagCache, FileStamp};
+use crate::server::exclude::{mime_globs, Exclude};
+use crate::server::htpasswd::constant_time_eq;
+use crate::server::load::{LoadGauge, LoadSample};
+use crate::server::loggable::{ConnectionLog, LogEntry, LogFormat, LogWriter, LoggableBody};
+use crate::server::metrics::Metrics;
+use crate::server::res::{self, ErrorPages};
+use crate::server::send::{
+    dir_is_empty, dir_validators, encode_path, send_dir, send_dir_as_zip, send_dir_ndjson,
+    send_file, send_file_with_range, send_file_with_ranges, send_sitemap, BoundaryGenerator,
+    ListOptions, ListingFormat, SortBy, INDEX_NAME,
+};
+use crate::server::throttle::{Throttled, Throttles};
+use crate::server::timeout::IdleTimeout;
+use crate::server::tls::{load_server_config, self_signed_server_config, tls_incoming};
+use crate::server::watch::{watch_rules, Reloadable};
+use crate::server::{Request, Response};
 use crate::BoxResult;
 
 const SERVER_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// Path of the endpoint exposing version information.
+const VERSION_ENDPOINT: &str = "/__sfz/version";
+/// Path of robots.txt, always at the root as crawlers expect.
+const ROBOTS_PATH: &str = "/robots.txt";
+/// Path of the generated sitemap, after path prefix.
+const SITEMAP_PATH: &str = "/sitemap.xml";
+/// Paths of endpoints served by the admin server.
+const HEALTH_ENDPOINT: &str = "/healthz";
+const METRICS_ENDPOINT: &str = "/metrics";
+const ADMIN_VERSION_ENDPOINT: &str = "/version";
 const CROSS_ORIGIN_EMBEDDER_POLICY: &str = "Cross-Origin-Embedder-Policy";
 const CROSS_ORIGIN_OPENER_POLICY: &str = "Cross-Origin-Opener-Policy";
+const SERVER_TIMING: &str = "Server-Timing";
+const X_GUESSED_MIME: &str = "X-Guessed-Mime";
+const SAVE_DATA: &str = "Save-Data";
+const X_ROBOTS_TAG: &str = "X-Robots-Tag";
+const X_CONTENT_TYPE_OPTIONS: &str = "X-Content-Type-Options";
+const X_ACCEL_REDIRECT: &str = "X-Accel-Redirect";
+/// Media type of JSON Lines directory listings.
+const NDJSON: &str = "application/x-ndjson";
+/// Cache-Control of responses matching `immutable_pattern` arg.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Number of leading bytes read for MIME sniffing.
+const SNIFF_LEN: usize = 8192;
+/// Max size of responses compressed in memory when `compress_if_smaller` arg
+/// is on. Larger ones are always compressed on the fly.
+const COMPRESS_IF_SMALLER_LIMIT: u64 = 1024 * 1024;
 
 /// Indicate that a path is a normal file/dir or a symlink to another path/dir.
 ///
@@ -55,65 +114,488 @@ pub enum PathType {
     SymlinkFile,
 }
 
-/// Run the server.
-pub async fn serve(args: Args) -> BoxResult<()> {
+/// Handle to stop a server started by [`spawn`].
+#[derive(Debug)]
+pub struct ShutdownTrigger(oneshot::Sender<()>);
+
+impl ShutdownTrigger {
+    /// Stop accepting new connections and gracefully shut the server down.
+    pub fn shutdown(self) {
+        // The server might have already stopped. Nothing to do then.
+        let _ = self.0.send(());
+    }
+}
+
+/// Start the server in background.
+///
+/// Return a handle to await the server and a trigger to stop it.
+pub fn spawn(args: Args) -> BoxResult<(JoinHandle<hyper::Result<()>>, ShutdownTrigger)> {
+    let (tx, rx) = oneshot::channel();
+    let server = serve_with_shutdown(args, async {
+        rx.await.ok();
+    })?;
+    Ok((tokio::spawn(server), ShutdownTrigger(tx)))
+}
+
+/// Bind the server, which runs until `shutdown` future completes.
+pub fn serve_with_shutdown<F>(
+    mut args: Args,
+    shutdown: F,
+) -> BoxResult<impl Future<Output = hyper::Result<()>>>
+where
+    F: Future<Output = ()>,
+{
     let address = args.address()?;
     let path_prefix = args.path_prefix.clone().unwrap_or_default();
+    let listen_fd = args.listen_fd;
+    let admin_bind = args.admin_bind;
+    // Given certificates take priority over self-signed ones.
+    let self_signed = args.tls_self_signed && args.tls_cert.is_none();
+    let tls_config = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(load_server_config(cert, key)?),
+        _ if self_signed => Some(self_signed_server_config()?),
+        _ => None,
+    };
+
+    let listener = match listen_fd {
+        Some(fd) => inherited_listener(fd)?,
+        None => bind(&address)?,
+    };
+    let incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+    let address = incoming.local_addr();
+    // Sitemap locations default to where the server is reachable, which is
+    // unknown if listening on all interfaces.
+    if args.sitemap && args.sitemap_base_url.is_none() {
+        if address.ip().is_unspecified() {
+            bail!("error: --sitemap-base-url is required to serve a sitemap on all interfaces");
+        }
+        args.sitemap_base_url = Some(origin(&args, address));
+    }
+    let inner = Arc::new(InnerService::new(args)?);
+    // The admin server stops along with the main one.
+    let (admin_tx, admin_rx) = oneshot::channel::<()>();
+    let admin = match admin_bind {
+        Some(address) => Some(serve_admin(&address, inner.clone(), async {
+            admin_rx.await.ok();
+        })?),
+        None => None,
+    };
+    let shutdown = async move {
+        shutdown.await;
+        let _ = admin_tx.send(());
+    };
+    let server = match tls_config {
+        Some(config) => {
+            eprintln!("Files served on https://{address}{path_prefix}");
+            if self_signed {
+                eprintln!("warning: the certificate is self-signed, which clients won't trust");
+            }
+            Either::Left(serve_incoming(
+                tls_incoming(incoming, config),
+                inner,
+                shutdown,
+            ))
+        }
+        None => {
+            eprintln!("Files served on http://{address}{path_prefix}");
+            Either::Right(serve_incoming(incoming, inner, shutdown))
+        }
+    };
+    Ok(async move {
+        match admin {
+            Some(admin) => {
+                let (res, admin_res) = tokio::join!(server, admin);
+                res.and(admin_res)
+            }
+            None => server.await,
+        }
+    })
+}
+
+/// Connections knowing the address of their clients.
+trait RemoteAddr {
+    fn remote_addr(&self) -> SocketAddr;
+}
+
+impl RemoteAddr for AddrStream {
+    fn remote_addr(&self) -> SocketAddr {
+        AddrStream::remote_addr(self)
+    }
+}
+
+impl RemoteAddr for TlsStream<AddrStream> {
+    fn remote_addr(&self) -> SocketAddr {
+        self.get_ref().0.remote_addr()
+    }
+}
+
+/// Serve connections accepted by `incoming` until `shutdown` completes.
+fn serve_incoming<I, F>(
+    incoming: I,
+    inner: Arc<InnerService>,
+    shutdown: F,
+) -> impl Future<Output = hyper::Result<()>>
+where
+    I: Accept,
+    I::Conn: RemoteAddr + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    F: Future<Output = ()>,
+{
+    let make_svc = make_service_fn(move |conn: &I::Conn| {
+        let inner = inner.clone();
+        let remote_addr = conn.remote_addr();
+        // Logged as closed once the service and all bodies are dropped.
+        let connection = inner.args.connection_log.then(|| {
+            ConnectionLog::accept(
+                remote_addr,
+                inner.log_writer.clone(),
+                inner.log_format.clone(),
+            )
+        });
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let inner = inner.clone();
+                inner.call(req, Some(remote_addr), connection.clone())
+            }))
+        }
+    });
+    hyper::Server::builder(incoming)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown)
+}
+
+/// Origin of the server listening on `address`, followed by path prefix.
+fn origin(args: &Args, address: SocketAddr) -> String {
+    let scheme = if args.tls_cert.is_some() || args.tls_self_signed {
+        "https"
+    } else {
+        "http"
+    };
+    let prefix = args.path_prefix.as_deref().unwrap_or_default();
+    format!("{}://{}{}", scheme, address, prefix)
+}
+
+/// Run `f`, adding the time it takes to `elapsed`.
+fn timed<T>(elapsed: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let timer = Instant::now();
+    let output = f();
+    *elapsed += timer.elapsed();
+    output
+}
 
-    let inner = Arc::new(InnerService::new(args));
-    let make_svc = make_service_fn(move |_| {
+/// Bind the admin server, which serves only health, metrics and version
+/// endpoints with counters shared with the main server.
+fn serve_admin<F>(
+    address: &SocketAddr,
+    inner: Arc<InnerService>,
+    shutdown: F,
+) -> BoxResult<impl Future<Output = hyper::Result<()>>>
+where
+    F: Future<Output = ()>,
+{
+    let make_svc = make_service_fn(move |_: &AddrStream| {
         let inner = inner.clone();
-        async {
+        async move {
             Ok::<_, Infallible>(service_fn(move |req| {
                 let inner = inner.clone();
-                inner.call(req)
+                async move { Ok::<_, Infallible>(inner.handle_admin_request(&req)) }
             }))
         }
     });
-    let server = hyper::Server::try_bind(&address)?.serve(make_svc);
-    let address = server.local_addr();
-    eprintln!("Files served on http://{address}{path_prefix}");
-    server.await?;
+    let server = hyper::Server::from_tcp(bind(address)?)?.serve(make_svc);
+    eprintln!("Admin endpoints served on http://{}", server.local_addr());
+    Ok(server.with_graceful_shutdown(shutdown))
+}
+
+/// Bind a listening socket, explaining common failures.
+fn bind(address: &SocketAddr) -> BoxResult<std::net::TcpListener> {
+    let listener = match std::net::TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => bail!("{}", bind_error(address, &err)),
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Describe a bind failure with how to resolve it.
+fn bind_error(address: &SocketAddr, err: &io::Error) -> String {
+    let port = address.port();
+    match err.kind() {
+        io::ErrorKind::AddrInUse => format!(
+            "error: address {} is already in use. Stop the process using it or choose another port with --port",
+            address
+        ),
+        io::ErrorKind::PermissionDenied if port < 1024 => format!(
+            "error: port {} requires elevated privileges or CAP_NET_BIND_SERVICE. Choose a port above 1023 with --port",
+            port
+        ),
+        io::ErrorKind::AddrNotAvailable => format!(
+            "error: address {} is not available on this machine. Choose another one with --bind",
+            address.ip()
+        ),
+        _ => format!("error: failed to bind {}: {}", address, err),
+    }
+}
+
+/// Take over a listening socket inherited from the parent process, such as
+/// the one passed by systemd socket activation.
+#[cfg(unix)]
+fn inherited_listener(fd: i32) -> BoxResult<std::net::TcpListener> {
+    use std::os::unix::io::FromRawFd;
+    // SAFETY: The descriptor is handed over to sfz exclusively. Nothing else
+    // in this process would ever touch it.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    if let Err(err) = listener.local_addr() {
+        bail!("error: fd {} is not a listening socket: {}", fd, err);
+    }
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn inherited_listener(fd: i32) -> BoxResult<std::net::TcpListener> {
+    bail!("error: listening on fd {} is only supported on Unix", fd);
+}
+
+/// Collapse consecutive slashes and remove leading ones, so that the path
+/// never replaces the base path while joining. A single trailing slash is
+/// preserved for directory detection.
+fn collapse_slashes(path: &str) -> String {
+    let mut collapsed = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.ends_with('/') && !collapsed.is_empty() {
+        collapsed.push('/');
+    }
+    collapsed
+}
+
+/// Check if any segment of a slash-separated path is treated specially by
+/// Windows, i.e. a reserved device name like `CON` or `nul.txt`, or a name
+/// with trailing dots or spaces, which Windows silently strips.
+fn has_windows_unsafe_segment(path: &str) -> bool {
+    const RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+    path.split('/')
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .any(|segment| {
+            if segment.ends_with('.') || segment.ends_with(' ') {
+                return true;
+            }
+            let stem = segment.split('.').next().unwrap_or_default().trim_end();
+            // Compare bytes, since the stem may have multibyte characters.
+            let numbered = |prefix: &str| match stem.as_bytes() {
+                [head @ .., digit] if head.len() == 3 => {
+                    head.eq_ignore_ascii_case(prefix.as_bytes()) && matches!(digit, b'1'..=b'9')
+                }
+                _ => false,
+            };
+            RESERVED.iter().any(|name| stem.eq_ignore_ascii_case(name))
+                || numbered("COM")
+                || numbered("LPT")
+        })
+}
+
+/// Check if leading bytes of a file are valid UTF-8. A multibyte character
+/// cut off at the end of a `truncated` sample is tolerated.
+fn is_utf8_sample(sample: &[u8], truncated: bool) -> bool {
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(err) => truncated && err.error_len().is_none(),
+    }
+}
+
+/// Read up to `len` leading bytes of a file off the async runtime.
+async fn read_head(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let mut head = Vec::with_capacity(len);
+        File::open(path)
+            .and_then(|file| file.take(len as u64).read_to_end(&mut head))
+            .map(|_| head)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Whether a file looks binary from its leading bytes, i.e. containing NUL
+/// bytes or not being UTF-8. Unreadable files are not.
+async fn is_binary_file(path: &Path) -> bool {
+    match read_head(path, SNIFF_LEN).await {
+        Ok(head) => head.contains(&0) || !is_utf8_sample(&head, head.len() == SNIFF_LEN),
+        Err(_) => false,
+    }
+}
+
+/// Errors while constructing file path from request path.
+#[derive(Debug)]
+enum PathError {
+    /// Not a valid UTF-8 string after percent decoding.
+    Utf8(Utf8Error),
+    /// Longer than `max_path_length` arg after percent decoding.
+    TooLong,
+}
 
-    Ok(())
+impl From<Utf8Error> for PathError {
+    fn from(err: Utf8Error) -> Self {
+        Self::Utf8(err)
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Utf8(err) => err.fmt(f),
+            Self::TooLong => f.write_str("request path too long"),
+        }
+    }
 }
 
+impl std::error::Error for PathError {}
+
 /// File and folder actions
+#[derive(Clone, Copy)]
 enum Action {
     DownloadZip,
-    ListDir,
+    ListDir(ListingFormat),
     DownloadFile,
 }
 
 struct InnerService {
     args: Args,
-    gitignore: Gitignore,
+    gitignore: Arc<Reloadable<Gitignore>>,
+    exclude: Arc<Reloadable<Option<Exclude>>>,
+    // Keeps rules reloading while alive.
+    _watcher: Option<RecommendedWatcher>,
+    error_pages: ErrorPages,
+    log_writer: LogWriter,
+    log_format: Arc<LogFormat>,
+    cors_origin_regex: Option<Regex>,
+    immutable_regex: Option<Regex>,
+    no_ranges_for: Option<GlobSet>,
+    throttles: Option<Throttles>,
+    compression_permits: Option<Arc<Semaphore>>,
+    etag_cache: EtagCache,
+    boundaries: BoundaryGenerator,
+    load: Arc<LoadGauge>,
+    metrics: Metrics,
 }
 
 impl InnerService {
-    pub fn new(args: Args) -> Self {
+    /// Prepare the service, failing if any custom error page is unreadable.
+    pub fn new(args: Args) -> BoxResult<Self> {
         let gitignore = Gitignore::new(args.path.join(".gitignore")).0;
-        Self { args, gitignore }
+        let exclude = (!args.exclude.is_empty()).then(|| {
+            Exclude::new(&args.path, &args.exclude)
+                .expect("exclude patterns are validated while parsing args")
+        });
+        let gitignore = Arc::new(Reloadable::new(gitignore));
+        let exclude = Arc::new(Reloadable::new(exclude));
+        // Serve with rules loaded at startup if watching fails.
+        let watcher = args
+            .watch_ignore
+            .then(|| {
+                watch_rules(
+                    &args.path,
+                    args.exclude_from.as_deref(),
+                    gitignore.clone(),
+                    exclude.clone(),
+                )
+                .ok()
+            })
+            .flatten();
+        let mut error_pages = match &args.error_page_dir {
+            Some(dir) => match ErrorPages::load(dir) {
+                Ok(pages) => pages,
+                Err(err) => bail!(
+                    "error: failed to load error pages from \"{}\": {}",
+                    dir.display(),
+                    err
+                ),
+            },
+            None => ErrorPages::default(),
+        };
+        for (status, page) in [
+            (StatusCode::NOT_FOUND, &args.not_found_page),
+            (StatusCode::FORBIDDEN, &args.forbidden_page),
+            (StatusCode::INTERNAL_SERVER_ERROR, &args.internal_error_page),
+        ] {
+            if let Some(page) = page {
+                if let Err(err) = error_pages.insert(status, page) {
+                    bail!("error: failed to read \"{}\": {}", page.display(), err);
+                }
+            }
+        }
+        let log_writer = Arc::new(Mutex::new(io::stdout()));
+        let log_format = args
+            .access_log_format
+            .parse::<LogFormat>()
+            .expect("log format is validated while parsing args")
+            .with_timezone(args.log_timezone);
+        let cors_origin_regex = args
+            .cors_origin_regex
+            .as_deref()
+            .map(|regex| Regex::new(regex).expect("regex is validated while parsing args"));
+        let immutable_regex = args
+            .immutable_pattern
+            .as_deref()
+            .map(|regex| Regex::new(regex).expect("regex is validated while parsing args"));
+        let no_ranges_for = (!args.no_ranges_for.is_empty()).then(|| {
+            mime_globs(&args.no_ranges_for).expect("globs are validated while parsing args")
+        });
+        let throttles = args.download_rate_per_ip.map(Throttles::new);
+        let load = LoadGauge::new(Duration::from_millis(args.compress_adaptive_threshold));
+        let compression_permits = args
+            .compression_threads
+            .map(|n| Arc::new(Semaphore::new(n)));
+        Ok(Self {
+            args,
+            gitignore,
+            exclude,
+            _watcher: watcher,
+            error_pages,
+            log_writer,
+            log_format: Arc::new(log_format),
+            load: Arc::new(load),
+            metrics: Metrics::default(),
+            cors_origin_regex,
+            immutable_regex,
+            no_ranges_for,
+            throttles,
+            compression_permits,
+            etag_cache: EtagCache::default(),
+            boundaries: BoundaryGenerator::default(),
+        })
     }
 
-    pub async fn call(self: Arc<Self>, req: Request) -> Result<Response, hyper::Error> {
+    pub async fn call(
+        self: Arc<Self>,
+        req: Request,
+        remote_addr: Option<SocketAddr>,
+        connection: Option<Arc<ConnectionLog>>,
+    ) -> Result<hyper::Response<LoggableBody>, hyper::Error> {
+        let timer = Instant::now();
         let res = self
-            .handle_request(&req)
+            .handle_request(&req, remote_addr)
             .await
             .unwrap_or_else(|_| res::internal_server_error(Response::default()));
-        // Logging
+        let res = self.error_pages.apply(res);
+        let load = LoadSample::new(self.load.clone(), timer.elapsed());
+        self.metrics.record(res.status());
+        // Logging happens when the body is fully sent.
         // TODO: use proper logging crate
-        if self.args.log {
-            println!(
-                r#"[{}] "{} {}" - {}"#,
-                Local::now().format("%d/%b/%Y %H:%M:%S"),
-                req.method(),
-                req.uri(),
-                res.status(),
-            );
-        }
+        let status = res.status();
+        let writer = self.args.log.then(|| self.log_writer.clone());
+        let progress_step = self.args.log_progress;
         // Returning response
-        Ok(res)
+        let entry = LogEntry::new(&req, remote_addr, status, timer);
+        let format = self.log_format.clone();
+        Ok(res.map(|body| {
+            LoggableBody::new(body, entry, format, writer, progress_step)
+                .with_connection(connection)
+                .with_load(load)
+        }))
     }
 
     /// Construct file path from request path.
@@ -121,40 +603,285 @@ impl InnerService {
     /// 1. Remove leading slash.
     /// 2. Strip path prefix if defined
     /// 3. URI percent decode.
-    /// 4. If on windows, switch slashes
-    /// 5. Concatenate base path and requested path.
-    fn file_path_from_path(&self, path: &str) -> Result<Option<PathBuf>, Utf8Error> {
-        let decoded = percent_decode(path[1..].as_bytes()).decode_utf8()?;
+    /// 4. Reject paths longer than `max_path_length` arg.
+    /// 5. Collapse consecutive slashes.
+    /// 6. If on windows, refuse reserved names and trailing dots or spaces,
+    ///    and switch slashes
+    /// 7. Concatenate base path and requested path.
+    /// 8. Append `index.html` to directories if `render_index` is on.
+    /// 9. Probe extensions in `probe_exts` arg for extensionless paths.
+    fn file_path_from_path(
+        &self,
+        path: &str,
+        render_index: bool,
+    ) -> Result<Option<PathBuf>, PathError> {
+        let decoded = percent_decode(&path.as_bytes()[1..]).decode_utf8()?;
+        if decoded.len() > self.args.max_path_length {
+            return Err(PathError::TooLong);
+        }
+        let collapsed = collapse_slashes(&decoded);
+        if cfg!(windows) && has_windows_unsafe_segment(&collapsed) {
+            return Ok(None);
+        }
         let slashes_switched = if cfg!(windows) {
-            decoded.replace("/", "\\")
+            collapsed.replace('/', "\\")
         } else {
-            decoded.into_owned()
+            collapsed
         };
         let stripped_path = match self.strip_path_prefix(&slashes_switched) {
             Some(path) => path,
             None => return Ok(None),
         };
+        // The bare prefix strips to an empty path, i.e. the base path.
         let mut path = self.args.path.join(stripped_path);
-        if self.args.render_index && path.is_dir() {
-            path.push("index.html")
+        if render_index && path.is_dir() {
+            path.push(INDEX_NAME)
+        }
+        if !decoded.ends_with('/') {
+            if let Some(probed) = self.probe_extensions(&path) {
+                path = probed;
+            }
         }
 
         Ok(Some(path))
     }
 
+    /// Find the first existing file of extensionless `path` with extensions
+    /// in `probe_exts` arg, in order.
+    fn probe_extensions(&self, path: &Path) -> Option<PathBuf> {
+        if path.extension().is_some() || path.exists() {
+            return None;
+        }
+        self.args
+            .probe_exts
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .find(|path| path.is_file())
+    }
+
+    /// Find `index.html` at the root to serve in place of a nonexistent path,
+    /// when `spa` arg is on. Paths with extensions are assets rather than
+    /// routes, which are never fallen back.
+    fn spa_index_path(&self, req: &Request, path: &Path) -> Option<PathBuf> {
+        if !self.args.spa
+            || !matches!(*req.method(), Method::GET | Method::HEAD)
+            || path.extension().is_some()
+            || path.exists()
+            || self.transparent_gz_path(path).is_some()
+        {
+            return None;
+        }
+        Some(self.args.path.join("index.html")).filter(|index| index.is_file())
+    }
+
+    /// Find `<path>.gz` to serve decompressed in place of a nonexistent path.
+    /// Only when `transparent_gz` arg is on.
+    fn transparent_gz_path<P: AsRef<Path>>(&self, path: P) -> Option<PathBuf> {
+        let path = path.as_ref();
+        if !self.args.transparent_gz || path.exists() {
+            return None;
+        }
+        let mut gz_path = path.as_os_str().to_owned();
+        gz_path.push(".gz");
+        Some(PathBuf::from(gz_path)).filter(|gz_path| gz_path.is_file())
+    }
+
+    /// Find a sidecar file pre-compressed in an encoding the client accepts,
+    /// such as `app.js.br` for `app.js`, along with the encoding.
+    ///
+    /// Sidecars are looked up only when `compress` arg is on. Byte offsets
+    /// of range requests then refer to the sidecar, which is the selected
+    /// representation. Sidecars pass the same checks as requested paths, so
+    /// hidden, ignored, excluded or disallowed symlinked ones are skipped.
+    fn precompressed_path(&self, req: &Request, path: &Path) -> Option<(PathBuf, &'static str)> {
+        if !self.args.compress {
+            return None;
+        }
+        let accept_encoding = req.headers().get(hyper::header::ACCEPT_ENCODING)?;
+        if !path.is_file() {
+            return None;
+        }
+        // Ties in quality are broken in the order of br, gzip and zstd.
+        let mut best: Option<(PathBuf, &'static str, u32)> = None;
+        for (encoding, ext) in [(BR, "br"), (GZIP, "gz"), (ZSTD, "zst")] {
+            let quality = get_encoding_quality(accept_encoding, encoding);
+            if quality == 0 || best.as_ref().is_some_and(|best| best.2 >= quality) {
+                continue;
+            }
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(".");
+            sidecar.push(ext);
+            let sidecar = PathBuf::from(sidecar);
+            if sidecar.is_file()
+                && self.path_exists(&sidecar)
+                && !self.path_is_symlink(&sidecar)
+                && (self.args.follow_links || self.path_is_under_basepath(&sidecar))
+            {
+                best = Some((sidecar, encoding, quality));
+            }
+        }
+        best.map(|(sidecar, encoding, _)| (sidecar, encoding))
+    }
+
     /// Enable HTTP cache control (current always enable with max-age=0)
-    fn enable_cache_control(&self, res: &mut Response) {
+    ///
+    /// Request paths matching `immutable_pattern` arg are cached for a year
+    /// as immutable instead, since their content never changes under the
+    /// same name.
+    fn enable_cache_control(&self, req: &Request, res: &mut Response) {
+        if let Some(regex) = &self.immutable_regex {
+            if regex.is_match(req.uri().path()) {
+                res.headers_mut().insert(
+                    CACHE_CONTROL,
+                    HeaderValue::from_static(IMMUTABLE_CACHE_CONTROL),
+                );
+                return;
+            }
+        }
         let header = CacheControl::new()
             .with_public()
             .with_max_age(Duration::from_secs(self.args.cache));
         res.headers_mut().typed_insert(header);
     }
 
+    /// Modified time used as a date validator, unless `no_last_modified` arg
+    /// is on, in which case only ETags validate conditional requests.
+    fn date_validator(&self, mtime: SystemTime) -> Option<SystemTime> {
+        (!self.args.no_last_modified).then_some(mtime)
+    }
+
+    /// Acquire a permit to compress a response if `compression_threads` arg
+    /// is set. Fails immediately if none is left.
+    fn try_acquire_compression(&self) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+        match &self.compression_permits {
+            Some(permits) => permits.clone().try_acquire_owned().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether range requests are supported for a file of the MIME type.
+    ///
+    /// Types matching `no_ranges_for` arg never advertise ranges, and their
+    /// `Range` headers are ignored.
+    fn accepts_ranges(&self, mime: &mime::Mime) -> bool {
+        !self
+            .no_ranges_for
+            .as_ref()
+            .is_some_and(|globs| globs.is_match(mime.essence_str()))
+    }
+
+    /// Compute ETag of a file per `etag` arg.
+    ///
+    /// Content digests are computed off the async runtime, and cached until
+    /// the stamp of the file changes.
+    async fn file_etag(&self, path: &Path, meta: &Metadata) -> io::Result<ETag> {
+        let algorithm = self.args.etag;
+        let (mtime, size) = (meta.modified()?, meta.len());
+        if !algorithm.reads_content() {
+            return algorithm.file_etag(path, mtime, size);
+        }
+        let stamp = FileStamp::new(meta)?;
+        if let Some(etag) = self.etag_cache.get(path, &stamp) {
+            return Ok(etag);
+        }
+        let owned_path = path.to_owned();
+        let etag =
+            tokio::task::spawn_blocking(move || algorithm.file_etag(&owned_path, mtime, size))
+                .await
+                .map_err(io::Error::other)??;
+        self.etag_cache.insert(path, stamp, etag.clone());
+        Ok(etag)
+    }
+
+    /// Internal location of a file for the proxy in front to serve, if
+    /// `x_accel` arg is set and the file is at least `x_accel_min_size` arg
+    /// bytes.
+    fn x_accel_location(&self, path: &Path, size: u64) -> Option<HeaderValue> {
+        let prefix = self.args.x_accel.as_deref()?;
+        if size < self.args.x_accel_min_size {
+            return None;
+        }
+        let rel_path = encode_path(path.strip_prefix(&self.args.path).ok()?)?;
+        let location = format!("{}/{}", prefix.trim_end_matches('/'), rel_path);
+        HeaderValue::from_str(&location).ok()
+    }
+
+    /// Treat a file not found as `None` rather than an error.
+    ///
+    /// Files might be removed after being checked for existence, such as
+    /// build outputs being regenerated. Such a race responds 404 instead of
+    /// 500.
+    fn not_found_as_none<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Insert validators of the representation.
+    fn insert_validators(res: &mut Response, last_modified: Option<LastModified>, etag: ETag) {
+        if let Some(last_modified) = last_modified {
+            res.headers_mut().typed_insert(last_modified);
+        }
+        res.headers_mut().typed_insert(etag);
+    }
+
+    /// Download files with extensions in `attachment_ext` arg as attachments
+    /// rather than displaying them inline.
+    fn enable_attachment(&self, res: &mut Response, path: &Path) {
+        let is_attachment = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                self.args
+                    .attachment_ext
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(ext))
+            });
+        if !is_attachment {
+            return;
+        }
+        let filename = path
+            .filename_str()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        let value = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+            .unwrap_or_else(|_| HeaderValue::from_static("attachment"));
+        res.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+
     /// Enable cross-origin resource sharing for given response.
-    fn enable_cors(&self, res: &mut Response) {
-        if self.args.cors {
+    ///
+    /// With `cors_origin_regex` arg, only a request `Origin` matching the
+    /// regex is allowed and echoed back.
+    fn enable_cors(&self, req: &Request, res: &mut Response) {
+        let allowed = if self.args.cors {
             res.headers_mut()
                 .typed_insert(AccessControlAllowOrigin::ANY);
+            true
+        } else if let Some(regex) = &self.cors_origin_regex {
+            // Allowed origin varies with requests.
+            res.headers_mut().append(
+                hyper::header::VARY,
+                HeaderValue::from_name(hyper::header::ORIGIN),
+            );
+            let origin = req
+                .headers()
+                .get(hyper::header::ORIGIN)
+                .filter(|origin| origin.to_str().is_ok_and(|o| regex.is_match(o)));
+            match origin {
+                Some(origin) => {
+                    res.headers_mut()
+                        .insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+                    true
+                }
+                None => false,
+            }
+        } else {
+            false
+        };
+        if allowed {
             res.headers_mut().typed_insert(
                 vec![
                     hyper::header::RANGE,
@@ -169,8 +896,15 @@ impl InnerService {
     }
 
     /// Enable cross-origin isolation for given response.
-    fn enable_coi(&self, res: &mut Response) {
-        if self.args.coi {
+    ///
+    /// If `coi_html_only` arg is true, only HTML documents are isolated, so
+    /// that embedded cross-origin assets won't be blocked by COEP. The type
+    /// is unknown to responses sent before the file is resolved, in which
+    /// case `mime` is `None`.
+    fn enable_coi(&self, res: &mut Response, mime: Option<&mime::Mime>) {
+        let is_html =
+            mime.is_some_and(|mime| mime.type_() == mime::TEXT && mime.subtype() == mime::HTML);
+        if self.args.coi && (!self.args.coi_html_only || is_html) {
             res.headers_mut().insert(
                 CROSS_ORIGIN_EMBEDDER_POLICY,
                 HeaderValue::from_str("require-corp").unwrap(),
@@ -182,6 +916,121 @@ impl InnerService {
         }
     }
 
+    /// Add headers given by `extra_headers` arg, replacing those of the same
+    /// names.
+    fn enable_extra_headers(&self, res: &mut Response) {
+        for (name, _) in &self.args.extra_headers {
+            res.headers_mut().remove(name);
+        }
+        for (name, value) in &self.args.extra_headers {
+            res.headers_mut().append(name, value.clone());
+        }
+    }
+
+    /// Report durations of each processing phase via `Server-Timing` header.
+    ///
+    /// See [Server Timing][1] for the header format.
+    ///
+    /// [1]: https://www.w3.org/TR/server-timing/
+    fn enable_server_timing(&self, res: &mut Response, timings: &[(&str, Duration)]) {
+        if self.args.server_timing && !timings.is_empty() {
+            let value = timings
+                .iter()
+                .map(|(name, dur)| format!("{};dur={:.3}", name, dur.as_secs_f64() * 1000.0))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                res.headers_mut().insert(SERVER_TIMING, value);
+            }
+        }
+    }
+
+    /// Report the raw MIME type guessed from path extension, before any
+    /// charset handling. `unknown` means a fallback type is used.
+    fn enable_debug_mime<P: AsRef<Path>>(&self, res: &mut Response, path: P) {
+        if self.args.debug_mime {
+            let guess = path
+                .as_ref()
+                .mime()
+                .map(|mime| mime.to_string())
+                .unwrap_or_else(|| "unknown".to_owned());
+            if let Ok(value) = HeaderValue::from_str(&guess) {
+                res.headers_mut().insert(X_GUESSED_MIME, value);
+            }
+        }
+    }
+
+    /// Check Basic authentication credentials if authentication is required.
+    ///
+    /// Credentials are compared in constant time, so response times never
+    /// reveal how much of them is correct. Hashes of htpasswd entries are
+    /// verified off the async runtime.
+    async fn is_authorized(&self, req: &Request) -> bool {
+        if self.args.auth.is_none() && self.args.htpasswd.is_none() {
+            return true;
+        }
+        let auth = match req.headers().typed_get::<Authorization<Basic>>() {
+            Some(auth) => auth,
+            None => return false,
+        };
+        if let Some(htpasswd) = self.args.htpasswd.clone() {
+            return tokio::task::spawn_blocking(move || {
+                htpasswd.verify(auth.username(), auth.password())
+            })
+            .await
+            .unwrap_or(false);
+        }
+        self.args.auth.as_ref().is_some_and(|(user, password)| {
+            // Both are always compared, not to reveal whether the user
+            // exists.
+            constant_time_eq(auth.username(), user) & constant_time_eq(auth.password(), password)
+        })
+    }
+
+    /// Check `Host` header against `allowed_hosts` arg, if any. Hosts
+    /// without a port match any port.
+    fn is_allowed_host(&self, req: &Request) -> bool {
+        if self.args.allowed_hosts.is_empty() {
+            return true;
+        }
+        let host = match req.headers().typed_get::<Host>() {
+            Some(host) => host,
+            None => return false,
+        };
+        let with_port = host
+            .port()
+            .map(|port| format!("{}:{}", host.hostname(), port));
+        self.args.allowed_hosts.iter().any(|allowed| {
+            allowed.eq_ignore_ascii_case(host.hostname())
+                || with_port
+                    .as_ref()
+                    .is_some_and(|with_port| allowed.eq_ignore_ascii_case(with_port))
+        })
+    }
+
+    /// Keep search engines from indexing and following served contents.
+    fn enable_no_index_robots(&self, res: &mut Response) {
+        if self.args.no_index_robots {
+            res.headers_mut()
+                .insert(X_ROBOTS_TAG, HeaderValue::from_static("noindex, nofollow"));
+        }
+    }
+
+    /// Tell browsers to reach the server only via HTTPS for `hsts` arg
+    /// seconds, and for its subdomains if `hsts_include_subdomains` arg is on.
+    fn enable_hsts(&self, res: &mut Response) {
+        if let Some(max_age) = self.args.hsts {
+            let mut hsts = format!("max-age={}", max_age);
+            if self.args.hsts_include_subdomains {
+                hsts.push_str("; includeSubDomains");
+            }
+            res.headers_mut().insert(
+                hyper::header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&hsts).unwrap(),
+            );
+        }
+    }
+
     /// Determine if payload should be compressed.
     ///
     /// Enable compression when all criteria are met:
@@ -189,13 +1038,39 @@ impl InnerService {
     /// - `compress` arg is true
     /// - is not partial responses
     /// - is not media contents
+    /// - server is not overloaded if `compress_adaptive` arg is true
     ///
     /// # Parameters
     ///
     /// * `status` - Current status code prepared to respond.
     /// * `mime` - MIME type of the payload.
     fn can_compress(&self, status: StatusCode, mime: &mime::Mime) -> bool {
-        self.args.compress && status != StatusCode::PARTIAL_CONTENT && !mime.is_compressed_format()
+        self.args.compress
+            && status != StatusCode::PARTIAL_CONTENT
+            && !mime.is_compressed_format()
+            && !(self.args.compress_adaptive && self.load.is_overloaded())
+    }
+
+    /// Whether a response of `content_length` is too small to be worth
+    /// compressing per `compress_threshold` arg. Responses of unknown length
+    /// are always worth it.
+    fn below_compress_threshold(&self, content_length: Option<u64>) -> bool {
+        content_length.is_some_and(|len| len < self.args.compress_threshold)
+    }
+
+    /// Strip ANSI escape sequences when all criteria are met:
+    ///
+    /// - `strip_ansi` arg is true
+    /// - is a file rather than generated content
+    /// - is `text/plain`
+    ///
+    /// Stripped files are sent whole, as byte ranges of the file on disk
+    /// don't apply to them.
+    fn can_strip_ansi(&self, action: Action, mime: &mime::Mime) -> bool {
+        self.args.strip_ansi
+            && matches!(action, Action::DownloadFile)
+            && mime.type_() == mime::TEXT
+            && mime.subtype() == mime::PLAIN
     }
 
     /// Determine critera if given path exists or not.
@@ -205,9 +1080,22 @@ impl InnerService {
     /// 1. exists
     /// 2. is not hidden
     /// 3. is not ignored
+    /// 4. is not excluded
     fn path_exists<P: AsRef<Path>>(&self, path: P) -> bool {
         let path = path.as_ref();
-        path.exists() && !self.path_is_hidden(path) && !self.path_is_ignored(path)
+        path.exists()
+            && !self.path_is_hidden(path)
+            && !self.path_is_ignored(path)
+            && !self.path_is_excluded(path)
+    }
+
+    /// Generate response for hidden or ignored paths according to
+    /// `hidden_status` arg.
+    fn hidden_response(&self, res: Response) -> Response {
+        match self.args.hidden_status {
+            403 => res::forbidden(res),
+            _ => res::not_found(res),
+        }
     }
 
     /// Determine if given path is hidden.
@@ -216,8 +1104,27 @@ impl InnerService {
     ///
     /// 1. `all` arg is false
     /// 2. any component of the path is hidden (prefixed with dot `.`)
+    ///
+    /// Regardless of `all` arg, paths inside `.git` directories are hidden
+    /// unless `allow_git` arg is true.
     fn path_is_hidden<P: AsRef<Path>>(&self, path: P) -> bool {
-        !self.args.all && path.as_ref().is_relatively_hidden()
+        let path = path.as_ref();
+        (!self.args.all && path.is_relatively_hidden()) || self.path_is_git(path)
+    }
+
+    /// Determine if any component of given path under the base path is
+    /// `.git`, while `allow_git` arg is false.
+    ///
+    /// Case is ignored, as `.GIT` is the same directory on case-insensitive
+    /// filesystems.
+    fn path_is_git(&self, path: &Path) -> bool {
+        let path = path.strip_prefix(&self.args.path).unwrap_or(path);
+        !self.args.allow_git
+            && path.components().any(|c| {
+                c.as_os_str()
+                    .as_encoded_bytes()
+                    .eq_ignore_ascii_case(b".git")
+            })
     }
 
     /// Determine if given path is ignored.
@@ -228,21 +1135,56 @@ impl InnerService {
     /// 2. matches any rules in .gitignore
     fn path_is_ignored<P: AsRef<Path>>(&self, path: P) -> bool {
         let path = path.as_ref();
-        self.args.ignore && self.gitignore.matched(path, path.is_dir()).is_ignore()
+        self.args.ignore
+            && self
+                .gitignore
+                .load()
+                .matched(path, path.is_dir())
+                .is_ignore()
+    }
+
+    /// Determine if given path matches any exclude patterns.
+    fn path_is_excluded<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.exclude
+            .load()
+            .as_ref()
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_excluded(path))
     }
 
     /// Check if requested resource is under directory of basepath.
     ///
     /// The given path must be resolved (canonicalized) to eliminate
-    /// incorrect path reported by symlink path.
+    /// incorrect path reported by symlink path. With `no_canonicalize` arg,
+    /// it is normalized lexically instead, so symlinks pointing outside are
+    /// not caught.
     fn path_is_under_basepath<P: AsRef<Path>>(&self, path: P) -> bool {
         let path = path.as_ref();
+        if self.args.no_canonicalize {
+            return path.lexically_normalize().starts_with(&self.args.path);
+        }
         match path.canonicalize() {
             Ok(path) => path.starts_with(&self.args.path),
             Err(_) => false,
         }
     }
 
+    /// Determine if given path or any of its ancestors under basepath is a
+    /// symlink.
+    ///
+    /// A path is refused as a symlink if matches all rules below:
+    ///
+    /// 1. `disable_symlinks` arg is true
+    /// 2. the path itself or any intermediate directory is a symlink
+    fn path_is_symlink<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.args.disable_symlinks
+            && path
+                .as_ref()
+                .ancestors()
+                .take_while(|p| *p != self.args.path)
+                .any(|p| matches!(p.type_(), PathType::SymlinkDir | PathType::SymlinkFile))
+    }
+
     /// Strip the path prefix of the request path.
     ///
     /// If there is a path prefix defined and `strip_prefix` returns `None`,
@@ -267,10 +1209,7 @@ impl InnerService {
         if !self.can_compress(status, mime_type) {
             return None;
         }
-        let encoding = match accept_encoding {
-            Some(e) => e,
-            None => return None,
-        };
+        let encoding = accept_encoding?;
         let content_encoding = get_prior_encoding(encoding);
         if !should_compress(content_encoding) {
             return None;
@@ -278,54 +1217,155 @@ impl InnerService {
         Some(content_encoding)
     }
 
+    /// Determine compression level. Clients in data-saver mode sending
+    /// `Save-Data: on` get the most aggressive compression, while others
+    /// get `compression_level` arg, or the default level of each encoding.
+    fn compression_level(&self, req: &Request) -> Option<Level> {
+        let save_data = req
+            .headers()
+            .get(SAVE_DATA)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("on"));
+        if save_data {
+            return Some(Level::Best);
+        }
+        self.args
+            .compression_level
+            .map(|level| Level::Precise(level.into()))
+    }
+
     /// Request handler for `MyService`.
-    async fn handle_request(&self, req: &Request) -> BoxResult<Response> {
+    async fn handle_request(
+        &self,
+        req: &Request,
+        remote_addr: Option<SocketAddr>,
+    ) -> BoxResult<Response> {
         // Construct response.
         let mut res = Response::default();
         res.headers_mut()
             .typed_insert(Server::from_static(SERVER_VERSION));
+        self.enable_no_index_robots(&mut res);
+        self.enable_hsts(&mut res);
+        // Content types are always declared, so browsers needn't guess them.
+        res.headers_mut()
+            .insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
 
-        let path = match self.file_path_from_path(req.uri().path())? {
-            Some(path) => path,
-            None => return Ok(res::not_found(res)),
+        // Requests for foreign hosts may come from DNS rebinding.
+        if !self.is_allowed_host(req) {
+            return Ok(res::misdirected_request(res));
+        }
+
+        // Authenticate before touching anything.
+        if !self.is_authorized(req).await {
+            return Ok(res::unauthorized(res));
+        }
+
+        // Version endpoint bypasses the filesystem.
+        if self.args.expose_version && req.uri().path() == VERSION_ENDPOINT {
+            return Ok(Self::send_version(res));
+        }
+
+        // Configured robots.txt takes precedence over files on disk.
+        if let Some(robots) = &self.args.robots {
+            if req.uri().path() == ROBOTS_PATH {
+                return Ok(Self::send_robots(res, robots));
+            }
+        }
+
+        // Root redirects to a subpath, under the path prefix.
+        if let Some(target) = &self.args.root_redirect {
+            if self.is_root_path(req.uri().path()) {
+                let prefix = self.args.path_prefix.as_deref().unwrap_or_default();
+                return Ok(res::found(res, &format!("{}{}", prefix, target)));
+            }
+        }
+
+        // Root serves a landing page, whereas other directories are intact.
+        if let Some(page) = &self.args.root_page {
+            if self.is_root_path(req.uri().path()) {
+                return self.send_root_page(req, res, page).await;
+            }
+        }
+
+        // Sitemap is generated from the whole tree instead of a file.
+        if self.args.sitemap && self.is_sitemap_path(req.uri().path()) {
+            return self.send_sitemap(res).await;
+        }
+
+        // Directory listing is negotiated via `Accept` header. A JSON listing
+        // is always the listing itself and never falls back to `index.html`,
+        // whereas HTML honors the `render_index` arg.
+        let format = Self::listing_format(req);
+        let render_index = self.args.render_index && format == ListingFormat::Html;
+        let mut path = match self.file_path_from_path(req.uri().path(), render_index) {
+            Ok(Some(path)) => path,
+            Ok(None) => return Ok(res::not_found(res)),
+            Err(PathError::TooLong) => return Ok(res::uri_too_long(res)),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Unknown routes are left to single page applications.
+        if let Some(index) = self.spa_index_path(req, &path) {
+            path = index;
+        }
+
+        // Path of the requested representation, which differs from the path
+        // to read when a gzipped file is served transparently.
+        let logical_path = path.clone();
+        let gunzip = match self.transparent_gz_path(&path) {
+            Some(gz_path) => {
+                path = gz_path;
+                true
+            }
+            None => false,
         };
 
         let default_action = if path.is_dir() {
-            Action::ListDir
+            Action::ListDir(format)
         } else {
             Action::DownloadFile
         };
 
-        let action = match req.uri().query() {
-            Some(query) => {
-                let query = QString::from(query);
-
-                match query.get("action") {
-                    Some(action_str) => match action_str {
-                        "zip" => {
-                            if path.is_dir() {
-                                Action::DownloadZip
-                            } else {
-                                bail!("error: invalid action");
-                            }
-                        }
-                        _ => bail!("error: invalid action"),
-                    },
-                    None => default_action,
+        let query = req.uri().query().map(QString::from).unwrap_or_default();
+        let action = match query.get("action") {
+            Some(action_str) => match action_str {
+                "zip" => {
+                    if path.is_dir() {
+                        Action::DownloadZip
+                    } else {
+                        bail!("error: invalid action");
+                    }
                 }
-            }
+                _ => bail!("error: invalid action"),
+            },
             None => default_action,
         };
+        // Bypass caches for this request only, to debug without clearing
+        // browser caches.
+        let nocache = query.get("nocache") == Some("1");
 
         // CORS headers
-        self.enable_cors(&mut res);
+        self.enable_cors(req, &mut res);
 
-        // COOP and COEP headers
-        self.enable_coi(&mut res);
+        // COOP and COEP headers, for HTML documents only once the type is
+        // known below per `coi_html_only` arg.
+        self.enable_coi(&mut res, None);
 
         // Check critera if the path should be ignore (404 NotFound).
+        // Hidden and ignored paths respond with `hidden_status` arg instead.
+        // Repositories are never revealed to exist, regardless of
+        // `hidden_status` arg.
         if !self.path_exists(&path) {
-            return Ok(res::not_found(res));
+            if !path.exists() || self.path_is_git(&path) {
+                return Ok(res::not_found(res));
+            }
+            return Ok(self.hidden_response(res));
+        }
+
+        // Symlinks are forbidden entirely when `disable_symlinks` arg is on.
+        if self.path_is_symlink(&path) {
+            return Ok(res::forbidden(res));
         }
 
         // Unless `follow_links` arg is on, any resource laid outside
@@ -334,121 +1374,385 @@ impl InnerService {
             return Ok(res::forbidden(res));
         }
 
-        // Prepare response body.
-        // Being mutable for further modifications.
-        let mut body = Body::empty();
-        let mut content_length = None;
+        let mime_type = match self.sniff_path_mime(&logical_path, action).await {
+            Some(mime_type) => mime_type,
+            None => InnerService::guess_path_mime(&logical_path, action).await,
+        };
 
-        // Extra process for serving files.
-        match action {
-            Action::ListDir => {
-                let (content, size) = send_dir(
-                    &path,
-                    &self.args.path,
-                    self.args.all,
-                    self.args.ignore,
-                    self.args.path_prefix.as_deref(),
-                )?;
-                body = Body::from(content);
-                content_length = Some(size as u64);
+        // Serve a pre-compressed sidecar as is in place of the file, with the
+        // type of the file.
+        let precompressed = match action {
+            Action::DownloadFile if !gunzip => self.precompressed_path(req, &path),
+            _ => None,
+        }
+        .map(|(sidecar, encoding)| {
+            path = sidecar;
+            encoding
+        });
+
+        // Stripping changes the length, so stripped files are not seekable.
+        let strip = precompressed.is_none() && self.can_strip_ansi(action, &mime_type);
+
+        // Generated listings and archives are not seekable. Only files on
+        // disk, including sidecars, support range requests.
+        let accept_ranges = matches!(action, Action::DownloadFile)
+            && !gunzip
+            && !strip
+            && self.accepts_ranges(&mime_type);
+
+        if nocache {
+            res.headers_mut()
+                .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        }
+
+        // Prepare response body.
+        // Being mutable for further modifications.
+        let mut body = Body::empty();
+        let mut content_length = None;
+        // Boundary of a `multipart/byteranges` body of multiple ranges.
+        let mut multipart_boundary = None;
+        // Metrics reported via `Server-Timing` header.
+        let mut timings = Vec::new();
+
+        // Extra process for serving files.
+        match action {
+            Action::ListDir(format) => {
+                // Relative links resolve against the directory URL only if
+                // it ends with a slash.
+                let uri_path = req.uri().path();
+                if self.args.strip_prefix_from_listing && !uri_path.ends_with('/') {
+                    let name = uri_path.rsplit('/').next().unwrap_or_default();
+                    let location = match req.uri().query() {
+                        Some(query) => format!("{}/?{}", name, query),
+                        None => format!("{}/", name),
+                    };
+                    return Ok(res::moved_permanently(res, &location));
+                }
+                let exclude = self.exclude.load();
+                let options = ListOptions {
+                    show_all: self.args.all,
+                    allow_git: self.args.allow_git,
+                    with_ignore: self.args.ignore,
+                    exclude: exclude.as_ref().as_ref(),
+                    path_prefix: self.args.path_prefix.as_deref(),
+                    sort_by: SortBy::from_query(&query),
+                    format,
+                    readme: self.args.readme,
+                    parent_link: self.args.parent_link,
+                    hide_index: self.args.hide_index_in_listing,
+                    relative_links: self.args.strip_prefix_from_listing,
+                    walk_threads: self.args.walk_threads,
+                    json_pretty: self.args.list_json_pretty,
+                    max_entries: self.args.max_listing_entries,
+                    footer: self.args.listing_footer.as_deref(),
+                    footer_raw: self.args.listing_footer_raw,
+                };
+                if format == ListingFormat::Ndjson {
+                    body = Body::wrap_stream(send_dir_ndjson(&path, &self.args.path, &options));
+                } else {
+                    let timer = Instant::now();
+                    let (content, size) = send_dir(&path, &self.args.path, &options)?;
+                    timings.push(("render", timer.elapsed()));
+                    // Listings carry modified times of entries, so touching
+                    // any entry changes the ETag.
+                    let etag = weak_etag(&format!("{:016x}", xxh3_64(&content)));
+                    if !nocache && is_fresh(req, &etag, None) {
+                        Self::insert_validators(&mut res, None, etag);
+                        return Ok(res::not_modified(res));
+                    }
+                    Self::insert_validators(&mut res, None, etag);
+                    body = Body::from(content);
+                    content_length = Some(size as u64);
+                }
             }
             Action::DownloadFile => {
                 // Cache-Control.
-                self.enable_cache_control(&mut res);
+                if !nocache {
+                    self.enable_cache_control(req, &mut res);
+                }
+                self.enable_attachment(&mut res, &logical_path);
+
+                // Time spent on reading metadata and opening the file.
+                let mut open_time = Duration::ZERO;
 
                 // Last-Modified-Time from file metadata _mtime_.
-                let (mtime, size) = (path.mtime(), path.size());
-                let last_modified = LastModified::from(mtime);
-                // Concatenate _modified time_ and _file size_ to
+                let meta = match Self::not_found_as_none(timed(&mut open_time, || path.metadata()))?
+                {
+                    Some(meta) => meta,
+                    None => return Ok(res::not_found(res)),
+                };
+                let (mtime, size) = (meta.modified()?, meta.len());
+                let date_mtime = self.date_validator(mtime);
+                let last_modified = date_mtime.map(LastModified::from);
+                // By default, concatenate _modified time_ and _file size_ to
                 // form a (nearly) strong validator.
-                let etag = format!(r#""{}-{}""#, mtime.timestamp(), size)
-                    .parse::<ETag>()
-                    .unwrap();
+                let etag = match Self::not_found_as_none(self.file_etag(&path, &meta).await)? {
+                    Some(etag) => etag,
+                    None => return Ok(res::not_found(res)),
+                };
+                // Stripped contents differ from the file byte by byte.
+                let etag = if strip {
+                    derived_etag(&etag, "ansi")
+                } else {
+                    etag
+                };
 
                 // Validate preconditions of conditional requests.
-                if is_precondition_failed(req, &etag, mtime) {
+                if !nocache && is_precondition_failed(req, &etag, date_mtime) {
                     return Ok(res::precondition_failed(res));
                 }
 
                 // Validate cache freshness.
-                if is_fresh(req, &etag, mtime) {
-                    res.headers_mut().typed_insert(last_modified);
-                    res.headers_mut().typed_insert(etag);
+                if !nocache && is_fresh(req, &etag, date_mtime) {
+                    Self::insert_validators(&mut res, last_modified, etag);
                     return Ok(res::not_modified(res));
                 }
 
+                // Let the proxy in front stream large files by itself.
+                // Decompressed or stripped contents exist only in memory,
+                // and sidecars need their encoding declared, so they are
+                // always sent directly.
+                if let Some(location) = self
+                    .x_accel_location(&path, size)
+                    .filter(|_| !gunzip && !strip && precompressed.is_none())
+                {
+                    Self::insert_validators(&mut res, last_modified, etag);
+                    res.headers_mut().insert(X_ACCEL_REDIRECT, location);
+                    res.headers_mut().typed_insert(ContentType::from(mime_type));
+                    return Ok(res);
+                }
+
                 // Range Request support.
-                if let Some(range) = req.headers().typed_get::<Range>() {
-                    #[allow(clippy::single_match)]
+                // Only `bytes` range unit is supported. Reject others clearly
+                // rather than falling through to the entire entity.
+                if accept_ranges
+                    && req.headers().contains_key(hyper::header::RANGE)
+                    && req.headers().typed_get::<Range>().is_none()
+                {
+                    return Ok(res::range_not_satisfiable(res, size));
+                }
+
+                // Ranges of decompressed contents are unknown in advance.
+                if let Some(range) = req.headers().typed_get::<Range>().filter(|_| accept_ranges) {
                     match (
-                        is_range_fresh(req, &etag, &last_modified),
-                        is_satisfiable_range(&range, size as u64),
+                        is_range_fresh(req, &etag, last_modified.as_ref()),
+                        is_satisfiable_range(&range, size),
                     ) {
-                        (true, Some(content_range)) => {
+                        (true, RangeResult::Satisfiable(content_range)) => {
                             // 206 Partial Content.
                             if let Some(range) = content_range.bytes_range() {
-                                let (stream, size) = send_file_with_range(&path, range)?;
+                                let opened =
+                                    timed(&mut open_time, || send_file_with_range(&path, range));
+                                let (stream, size) = match Self::not_found_as_none(opened)? {
+                                    Some(opened) => opened,
+                                    None => return Ok(res::not_found(res)),
+                                };
+                                let stream = stream.buffer_pool(self.args.buffer_pool);
                                 body = Body::wrap_stream(stream);
                                 content_length = Some(size);
                             }
                             res.headers_mut().typed_insert(content_range);
                             *res.status_mut() = StatusCode::PARTIAL_CONTENT;
                         }
-                        // Respond entire entity if Range header contains
-                        // unsatisfiable range.
+                        (true, RangeResult::Multiple(content_ranges)) => {
+                            // 206 Partial Content with parts of each range.
+                            let ranges = content_ranges
+                                .iter()
+                                .filter_map(ContentRange::bytes_range)
+                                .collect::<Vec<_>>();
+                            let part_type =
+                                self.detect_charset(&logical_path, mime_type.clone()).await;
+                            let boundary = self.boundaries.next_boundary();
+                            let opened = timed(&mut open_time, || {
+                                send_file_with_ranges(&path, &ranges, part_type.as_ref(), &boundary)
+                            });
+                            let (stream, size) = match Self::not_found_as_none(opened)? {
+                                Some(opened) => opened,
+                                None => return Ok(res::not_found(res)),
+                            };
+                            body = Body::wrap_stream(stream);
+                            content_length = Some(size);
+                            multipart_boundary = Some(boundary);
+                            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                        }
+                        (true, RangeResult::Unsatisfiable) => {
+                            return Ok(res::range_not_satisfiable(res, size));
+                        }
+                        // Respond entire entity if Range header is ignored,
+                        // or stale per `If-Range`.
                         _ => (),
                     }
                 }
 
                 if res.status() != StatusCode::PARTIAL_CONTENT {
-                    let (stream, size) = send_file(&path)?;
-                    body = Body::wrap_stream(stream);
-                    content_length = Some(size);
+                    let opened = timed(&mut open_time, || send_file(&path));
+                    let (stream, size) = match Self::not_found_as_none(opened)? {
+                        Some(opened) => opened,
+                        None => return Ok(res::not_found(res)),
+                    };
+                    let stream = stream.buffer_pool(self.args.buffer_pool);
+                    if gunzip {
+                        body = decompress_stream(stream, GZIP)?;
+                    } else {
+                        body = Body::wrap_stream(stream);
+                        content_length = Some(size);
+                    }
                 }
-                res.headers_mut().typed_insert(last_modified);
-                res.headers_mut().typed_insert(etag);
+                Self::insert_validators(&mut res, last_modified, etag);
+                timings.push(("open", open_time));
             }
             Action::DownloadZip => {
-                let (stream, size) = send_dir_as_zip(&path, self.args.all, self.args.ignore)?;
+                // Archives might exclude dotfiles while the listing shows them.
+                let show_all = self.args.all && !self.args.deny_dotfiles_in_archives;
+                let allow_git = self.args.allow_git;
+                let exclude = self.exclude.load();
+                let exclude = exclude.as_ref().as_ref();
+
+                // Nothing to archive. Respond per `empty_zip_status` arg.
+                if self.args.empty_zip_status == 204
+                    && dir_is_empty(&path, show_all, allow_git, self.args.ignore, exclude)
+                {
+                    return Ok(res::no_content(res));
+                }
+
+                // Validators derived from archived entries.
+                let (mtime, etag) = dir_validators(
+                    &path,
+                    show_all,
+                    allow_git,
+                    self.args.ignore,
+                    exclude,
+                    self.args.walk_threads,
+                );
+                let date_mtime = self.date_validator(mtime);
+                let last_modified = date_mtime.map(LastModified::from);
+                if !nocache && is_precondition_failed(req, &etag, date_mtime) {
+                    return Ok(res::precondition_failed(res));
+                }
+                if !nocache && is_fresh(req, &etag, date_mtime) {
+                    Self::insert_validators(&mut res, last_modified, etag);
+                    return Ok(res::not_modified(res));
+                }
+                Self::insert_validators(&mut res, last_modified, etag);
+                // Nest entries under a folder named after the directory.
+                let dir_name = path.file_name().unwrap().to_str().unwrap();
+                let (stream, size) = send_dir_as_zip(
+                    &path,
+                    show_all,
+                    allow_git,
+                    self.args.ignore,
+                    exclude,
+                    Some(dir_name),
+                    self.args.walk_threads,
+                )?;
                 body = Body::wrap_stream(stream);
                 content_length = Some(size);
 
                 // Changing the filename
                 res.headers_mut().insert(
                     CONTENT_DISPOSITION,
-                    HeaderValue::from_str(&format!(
-                        "attachment; filename=\"{}.zip\"",
-                        path.file_name().unwrap().to_str().unwrap()
-                    ))
-                    .unwrap(),
+                    HeaderValue::from_str(&format!("attachment; filename=\"{}.zip\"", dir_name))
+                        .unwrap(),
                 );
             }
         }
 
         let accept_encoding = req.headers().get(hyper::header::ACCEPT_ENCODING);
-        let mime_type = InnerService::guess_path_mime(&path, action);
-        if let Some(content_encoding) =
-            self.get_content_encoding(accept_encoding, res.status(), &mime_type)
-        {
-            body = compress_stream(
-                body.map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
-                content_encoding.as_ref(),
-            )?;
+        // Gzipped files are served as is, which cannot be sampled as text.
+        let mime_type = if matches!(action, Action::DownloadFile) && !gunzip {
+            self.detect_charset(&logical_path, mime_type).await
+        } else {
+            mime_type
+        };
+        self.enable_debug_mime(&mut res, &logical_path);
+
+        // COOP and COEP headers of HTML documents
+        self.enable_coi(&mut res, Some(&mime_type));
+
+        if strip {
+            body = Body::wrap_stream(strip_ansi(body));
             content_length = None;
+        }
+        if let Some(content_encoding) = precompressed {
             res.headers_mut().insert(
                 hyper::header::CONTENT_ENCODING,
-                hyper::header::HeaderValue::from_static(content_encoding),
+                HeaderValue::from_static(content_encoding),
             );
+            res.headers_mut().append(
+                hyper::header::VARY,
+                HeaderValue::from_name(hyper::header::ACCEPT_ENCODING),
+            );
+        } else if res.status() == StatusCode::PARTIAL_CONTENT {
+            // Partial contents, single or multipart, are never compressed,
+            // though the entire entity they are part of might be.
+            if self.args.compress && !mime_type.is_compressed_format() {
+                res.headers_mut().append(
+                    hyper::header::VARY,
+                    HeaderValue::from_name(hyper::header::ACCEPT_ENCODING),
+                );
+            }
+        } else if let Some(content_encoding) = self
+            .get_content_encoding(accept_encoding, res.status(), &mime_type)
+            .filter(|_| !self.below_compress_threshold(content_length))
+        {
+            let level = self.compression_level(req);
+            let in_memory = self.args.compress_if_smaller
+                && content_length.is_some_and(|len| len <= COMPRESS_IF_SMALLER_LIMIT);
+            // Responses are left uncompressed rather than queued when all
+            // compressors are busy.
+            let permit = self.try_acquire_compression();
+            let compressed = if permit.is_err() {
+                false
+            } else if in_memory {
+                // Compress in memory, and keep whichever representation is
+                // smaller.
+                let original = hyper::body::to_bytes(body).await?;
+                let input = futures::stream::once(futures::future::ok(original.clone()));
+                let timer = Instant::now();
+                let output =
+                    hyper::body::to_bytes(compress_stream(input, content_encoding, level)?).await?;
+                timings.push(("compress", timer.elapsed()));
+                let smaller = output.len() < original.len();
+                let chosen = if smaller { output } else { original };
+                content_length = Some(chosen.len() as u64);
+                body = Body::from(chosen);
+                smaller
+            } else {
+                // The permit is released once the body is dropped.
+                let input = body.map_err(io::Error::other).inspect(move |_| {
+                    let _permit = &permit;
+                });
+                body = compress_stream(input, content_encoding, level)?;
+                content_length = None;
+                true
+            };
+            if compressed {
+                res.headers_mut().insert(
+                    hyper::header::CONTENT_ENCODING,
+                    hyper::header::HeaderValue::from_static(content_encoding),
+                );
+            }
             // Representation varies, so responds with a `Vary` header.
-            res.headers_mut().insert(
+            res.headers_mut().append(
                 hyper::header::VARY,
                 hyper::header::HeaderValue::from_name(hyper::header::ACCEPT_ENCODING),
             );
         }
 
         // Common headers
-        res.headers_mut().typed_insert(AcceptRanges::bytes());
-        res.headers_mut().typed_insert(ContentType::from(mime_type));
+        if accept_ranges {
+            res.headers_mut().typed_insert(AcceptRanges::bytes());
+        }
+        match multipart_boundary {
+            Some(boundary) => {
+                let multipart = format!("multipart/byteranges; boundary={}", boundary);
+                res.headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_str(&multipart).unwrap());
+            }
+            None => res.headers_mut().typed_insert(ContentType::from(mime_type)),
+        }
+        self.enable_server_timing(&mut res, &timings);
+        self.enable_extra_headers(&mut res);
 
         // Set Content-Length only when body is not compressed,
         // otherwise the client will get confused
@@ -458,43 +1762,253 @@ impl InnerService {
                 .typed_insert(ContentLength(content_length));
         }
 
+        // HEAD shares all headers with GET, including those of range
+        // requests, but never carries a body.
+        if req.method() == Method::HEAD {
+            body = Body::empty();
+        }
+
+        // Stalled transfers shouldn't hold resources forever.
+        if let Some(secs) = self.args.timeout_body {
+            let timeout = Duration::from_secs(secs);
+            body = Body::wrap_stream(IdleTimeout::new(body.map_err(io::Error::other), timeout));
+        }
+
+        // Clients share a bandwidth cap however many connections they open.
+        if let (Some(throttles), Some(addr)) = (&self.throttles, remote_addr) {
+            let bucket = throttles.bucket(addr.ip());
+            body = Body::wrap_stream(Throttled::new(body.map_err(io::Error::other), bucket));
+        }
+
         *res.body_mut() = body;
         Ok(res)
     }
 
-    fn guess_path_mime<P: AsRef<Path>>(path: P, action: Action) -> mime::Mime {
+    /// Handle requests to the admin server.
+    ///
+    /// Only health, metrics and version endpoints are served. Files never
+    /// are.
+    fn handle_admin_request(&self, req: &Request) -> Response {
+        let mut res = Response::default();
+        match req.uri().path() {
+            HEALTH_ENDPOINT => {
+                *res.body_mut() = "ok\n".into();
+                res
+            }
+            METRICS_ENDPOINT => {
+                let body = self.metrics.render(self.load.average());
+                res.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; version=0.0.4"),
+                );
+                *res.body_mut() = body.into();
+                res
+            }
+            ADMIN_VERSION_ENDPOINT => Self::send_version(res),
+            _ => res::not_found(res),
+        }
+    }
+
+    /// Determine if the request path points to the generated sitemap.
+    fn is_sitemap_path(&self, path: &str) -> bool {
+        let prefix = self.args.path_prefix.as_deref().unwrap_or_default();
+        path.strip_prefix(prefix) == Some(SITEMAP_PATH)
+    }
+
+    /// Respond a sitemap of all HTML files under the base path.
+    ///
+    /// Locations are based on `sitemap_base_url` arg, or the configured
+    /// address with path prefix if absent. Request headers never take part,
+    /// so clients cannot forge locations. The tree is walked off the async
+    /// runtime.
+    async fn send_sitemap(&self, mut res: Response) -> BoxResult<Response> {
+        let base_url = match &self.args.sitemap_base_url {
+            Some(url) => url.clone(),
+            None => origin(&self.args, self.args.address()?),
+        };
+        let base_path = self.args.path.clone();
+        let (show_all, allow_git) = (self.args.all, self.args.allow_git);
+        let with_ignore = self.args.ignore;
+        let exclude = self.exclude.load();
+        let body = tokio::task::spawn_blocking(move || {
+            send_sitemap(
+                &base_path,
+                &base_url,
+                show_all,
+                allow_git,
+                with_ignore,
+                exclude.as_ref().as_ref(),
+            )
+        })
+        .await?;
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        res.headers_mut()
+            .typed_insert(ContentLength(body.len() as u64));
+        *res.body_mut() = body.into();
+        Ok(res)
+    }
+
+    /// Respond version information as JSON.
+    fn send_version(mut res: Response) -> Response {
+        let info = serde_json::json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "server": SERVER_VERSION,
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "debug": cfg!(debug_assertions),
+        });
+        let body = info.to_string();
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(body.len() as u64));
+        *res.body_mut() = body.into();
+        res
+    }
+
+    /// Whether the request path is the root, under the path prefix.
+    fn is_root_path(&self, path: &str) -> bool {
+        let prefix = self.args.path_prefix.as_deref().unwrap_or_default();
+        path.strip_prefix(prefix)
+            .is_some_and(|p| p.is_empty() || p == "/")
+    }
+
+    /// Respond the file of `root_page` arg.
+    async fn send_root_page(
+        &self,
+        req: &Request,
+        mut res: Response,
+        page: &Path,
+    ) -> BoxResult<Response> {
+        let (stream, size) = match Self::not_found_as_none(send_file(page))? {
+            Some(opened) => opened,
+            None => return Ok(res::not_found(res)),
+        };
+        let mime_type = Self::guess_path_mime(page, Action::DownloadFile).await;
+        res.headers_mut().typed_insert(ContentType::from(mime_type));
+        res.headers_mut().typed_insert(ContentLength(size));
+        if req.method() != Method::HEAD {
+            *res.body_mut() = Body::wrap_stream(stream);
+        }
+        Ok(res)
+    }
+
+    /// Respond robots.txt with given content.
+    fn send_robots(mut res: Response, robots: &str) -> Response {
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime::TEXT_PLAIN_UTF_8));
+        res.headers_mut()
+            .typed_insert(ContentLength(robots.len() as u64));
+        *res.body_mut() = robots.to_owned().into();
+        res
+    }
+
+    /// Determine the format of directory listing from `Accept` header.
+    ///
+    /// The first media type recognized wins. Defaults to HTML.
+    fn listing_format(req: &Request) -> ListingFormat {
+        req.headers()
+            .get_all(hyper::header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|media| media.split(';').next())
+            .find_map(|media| match media.trim() {
+                "text/html" => Some(ListingFormat::Html),
+                "application/json" => Some(ListingFormat::Json),
+                "text/plain" => Some(ListingFormat::Plain),
+                NDJSON => Some(ListingFormat::Ndjson),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Detect MIME type from magic bytes of extensionless files, which
+    /// otherwise fall back to `text/plain`. Only when `sniff` arg is on.
+    ///
+    /// Reading happens off the async runtime.
+    async fn sniff_path_mime<P: AsRef<Path>>(&self, path: P, action: Action) -> Option<mime::Mime> {
+        let path = path.as_ref();
+        if !self.args.sniff || !matches!(action, Action::DownloadFile) {
+            return None;
+        }
+        if path.extension().is_some() {
+            return None;
+        }
+        let head = read_head(path, SNIFF_LEN).await.ok()?;
+        infer::get(&head)?.mime_type().parse().ok()
+    }
+
+    /// Verify a file labeled as UTF-8 actually is by sampling its leading
+    /// `charset_detect_bytes` arg bytes. If not, the charset is dropped and
+    /// left for clients to detect.
+    ///
+    /// Reading happens off the async runtime.
+    async fn detect_charset(&self, path: &Path, mime: mime::Mime) -> mime::Mime {
+        let len = self.args.charset_detect_bytes;
+        if len == 0 || mime.get_param(mime::CHARSET) != Some(mime::UTF_8) {
+            return mime;
+        }
+        match read_head(path, len).await {
+            Ok(sample) if !is_utf8_sample(&sample, sample.len() == len) => {
+                mime.essence_str().parse().unwrap_or(mime)
+            }
+            _ => mime,
+        }
+    }
+
+    async fn guess_path_mime<P: AsRef<Path>>(path: P, action: Action) -> mime::Mime {
         let path = path.as_ref();
-        path.mime()
-            .map(|x| match x.get_param(mime::CHARSET) {
+        if let Some(x) = path.mime() {
+            return match x.get_param(mime::CHARSET) {
                 Some(_) => x,
                 None => x
                     .guess_charset()
                     .and_then(|c| format!("{}; charset={}", x, c).parse().ok())
                     .unwrap_or(x),
-            })
-            .unwrap_or_else(|| match action {
-                Action::ListDir => mime::TEXT_HTML_UTF_8,
-                Action::DownloadFile => mime::TEXT_PLAIN_UTF_8,
-                Action::DownloadZip => mime::APPLICATION_OCTET_STREAM,
-            })
+            };
+        }
+        match action {
+            Action::ListDir(ListingFormat::Html) => mime::TEXT_HTML_UTF_8,
+            Action::ListDir(ListingFormat::Json) => mime::APPLICATION_JSON,
+            Action::ListDir(ListingFormat::Plain) => mime::TEXT_PLAIN_UTF_8,
+            Action::ListDir(ListingFormat::Ndjson) => NDJSON.parse().unwrap(),
+            // Browsers never sniff with `nosniff`, so binaries labeled as
+            // text would be shown as garbage instead of downloaded.
+            Action::DownloadFile if is_binary_file(path).await => mime::APPLICATION_OCTET_STREAM,
+            Action::DownloadFile => mime::TEXT_PLAIN_UTF_8,
+            Action::DownloadZip => mime::APPLICATION_OCTET_STREAM,
+        }
     }
 }
 
 #[cfg(test)]
 mod t_server {
     use super::*;
+    use crate::server::EtagAlgorithm;
     use crate::test_utils::{get_tests_dir, with_current_dir};
+    use headers::{IfMatch, IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince};
     use std::fs::File;
     use tempfile::Builder;
 
     fn bootstrap(args: Args) -> (InnerService, Response) {
-        (InnerService::new(args), Response::default())
+        (InnerService::new(args).unwrap(), Response::default())
     }
 
     const fn temp_name() -> &'static str {
         concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION"))
     }
 
+    /// Send a GET request with given URI to the service.
+    async fn send(service: &InnerService, uri: &str) -> Response {
+        let req = hyper::Request::get(uri).body(Body::empty()).unwrap();
+        service.handle_request(&req, None).await.unwrap()
+    }
+
     #[test]
     fn file_path_from_path() {
         let args = Args {
@@ -505,7 +2019,9 @@ mod t_server {
         let (service, _) = bootstrap(args);
         let path = "/%E4%BD%A0%E5%A5%BD%E4%B8%96%E7%95%8C";
         assert_eq!(
-            service.file_path_from_path(path).unwrap(),
+            service
+                .file_path_from_path(path, service.args.render_index)
+                .unwrap(),
             Some(PathBuf::from("/storage/你好世界"))
         );
 
@@ -517,308 +2033,2970 @@ mod t_server {
         };
         let (service, _) = bootstrap(args);
         assert_eq!(
-            service.file_path_from_path(".").unwrap(),
+            service
+                .file_path_from_path(".", service.args.render_index)
+                .unwrap(),
             Some(dir.path().join("index.html")),
         );
-    }
-
-    #[test]
-    fn guess_path_mime() {
-        let mime_type =
-            InnerService::guess_path_mime("file-wthout-extension", Action::DownloadFile);
-        assert_eq!(mime_type, mime::TEXT_PLAIN_UTF_8);
 
-        let mime_type = InnerService::guess_path_mime("file.json", Action::DownloadFile);
-        let json_utf8 = "application/json; charset=utf-8"
-            .parse::<mime::Mime>()
-            .unwrap();
-        assert_eq!(mime_type, json_utf8);
-        assert_eq!(mime_type.get_param(mime::CHARSET), Some(mime::UTF_8));
+        // So does the bare path prefix.
+        let args = Args {
+            path: dir.path().to_owned(),
+            path_prefix: Some("/app".to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for path in ["/app", "/app/"] {
+            assert_eq!(
+                service
+                    .file_path_from_path(path, service.args.render_index)
+                    .unwrap(),
+                Some(dir.path().join("index.html")),
+            );
+        }
+    }
 
-        let mime_type = InnerService::guess_path_mime("lib.wasm", Action::DownloadFile);
-        let wasm = "application/wasm".parse::<mime::Mime>().unwrap();
-        assert_eq!(mime_type, wasm);
-        assert_eq!(mime_type.get_param(mime::CHARSET), None);
+    #[tokio::test]
+    async fn handle_request_probe_exts() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("page.htm"), "htm").unwrap();
+        std::fs::write(dir.path().join("page.php"), "php").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "txt").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            probe_exts: vec!["html".to_owned(), "htm".to_owned(), "php".to_owned()],
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
 
-        let dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        let mime_type = InnerService::guess_path_mime(dir_path, Action::ListDir);
-        assert_eq!(mime_type, mime::TEXT_HTML_UTF_8);
+        // Resolved via the second extension, as no `page.html` exists.
+        let res = send(&service, "/page").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "htm");
 
-        let dir_path = PathBuf::from("./tests");
-        let mime_type = InnerService::guess_path_mime(dir_path, Action::DownloadZip);
-        assert_eq!(mime_type, mime::APPLICATION_OCTET_STREAM);
+        for uri in ["/other", "/page/", "/missing"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
     }
 
     #[test]
-    fn enable_cors() {
-        let args = Args::default();
-        let (service, mut res) = bootstrap(args);
-        service.enable_cors(&mut res);
-        assert_eq!(
-            res.headers()
-                .typed_get::<AccessControlAllowOrigin>()
-                .unwrap(),
-            AccessControlAllowOrigin::ANY,
-        );
-        assert_eq!(
-            res.headers()
-                .typed_get::<AccessControlAllowHeaders>()
-                .unwrap(),
-            vec![
-                hyper::header::RANGE,
-                hyper::header::CONTENT_TYPE,
-                hyper::header::ACCEPT,
-                hyper::header::ORIGIN,
-            ]
-            .into_iter()
-            .collect::<AccessControlAllowHeaders>(),
-        );
+    fn not_found_as_none() {
+        let vanished = io::Error::from(io::ErrorKind::NotFound);
+        let opened = InnerService::not_found_as_none(Err::<(), _>(vanished));
+        assert!(opened.unwrap().is_none());
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let opened = InnerService::not_found_as_none(Err::<(), _>(denied));
+        assert_eq!(opened.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(InnerService::not_found_as_none(Ok(1)).unwrap(), Some(1));
     }
 
-    #[test]
-    fn enable_coi() {
-        let args = Args::default();
-        let (service, mut res) = bootstrap(args);
-        service.enable_coi(&mut res);
-        assert_eq!(
-            res.headers().get(CROSS_ORIGIN_OPENER_POLICY).unwrap(),
-            "same-origin",
-        );
-        assert_eq!(
-            res.headers().get(CROSS_ORIGIN_EMBEDDER_POLICY).unwrap(),
-            "require-corp",
-        );
-    }
+    #[tokio::test]
+    async fn handle_request_charset_detection() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        // Non UTF-8 bytes come after leading multibyte characters.
+        let mut content = "héllo, 世界\n".repeat(64).into_bytes();
+        content.extend_from_slice(b"caf\xe9\n");
+        std::fs::write(base.join("latin1.txt"), &content).unwrap();
+        std::fs::write(base.join("utf8.txt"), "héllo, 世界\n").unwrap();
 
-    #[test]
-    fn disable_cors() {
-        let args = Args {
-            cors: false,
-            ..Default::default()
+        let content_type = |charset_detect_bytes, uri| {
+            let args = Args {
+                path: base.clone(),
+                charset_detect_bytes,
+                ..Default::default()
+            };
+            async move {
+                let (service, _) = bootstrap(args);
+                let res = send(&service, uri).await;
+                res.headers().typed_get::<ContentType>().unwrap()
+            }
         };
-        let (service, mut res) = bootstrap(args);
-        service.enable_cors(&mut res);
-        assert!(res
-            .headers()
-            .typed_get::<AccessControlAllowOrigin>()
-            .is_none());
+        let utf8 = ContentType::from(mime::TEXT_PLAIN_UTF_8);
+        let unlabeled = ContentType::from(mime::TEXT_PLAIN);
+
+        // A small window cutting a multibyte character apart.
+        assert_eq!(content_type(2, "/utf8.txt").await, utf8);
+        assert_eq!(content_type(2, "/latin1.txt").await, utf8);
+        // A large window covering the whole file.
+        assert_eq!(content_type(4096, "/utf8.txt").await, utf8);
+        assert_eq!(content_type(4096, "/latin1.txt").await, unlabeled);
+        // Detection disabled.
+        assert_eq!(content_type(0, "/latin1.txt").await, utf8);
     }
 
     #[test]
-    fn enable_cache_control() {
-        let args = Args::default();
-        let (service, mut res) = bootstrap(args);
-        service.enable_cache_control(&mut res);
-        assert_eq!(
-            res.headers().typed_get::<CacheControl>().unwrap(),
-            CacheControl::new()
-                .with_public()
-                .with_max_age(Duration::default()),
-        );
+    fn is_utf8_sample() {
+        let bytes = "世界".as_bytes();
+        assert!(super::is_utf8_sample(bytes, false));
+        assert!(super::is_utf8_sample(&bytes[..4], true));
+        assert!(!super::is_utf8_sample(&bytes[..4], false));
+        assert!(!super::is_utf8_sample(b"caf\xe9!", true));
+    }
 
-        let cache = 3600;
+    #[tokio::test]
+    async fn handle_request_root_redirect() {
         let args = Args {
-            cache,
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            root_redirect: Some("/dir/".to_owned()),
             ..Default::default()
         };
-        let (service, mut res) = bootstrap(args);
-        service.enable_cache_control(&mut res);
-        assert_eq!(
-            res.headers().typed_get::<CacheControl>().unwrap(),
-            CacheControl::new()
-                .with_public()
-                .with_max_age(Duration::from_secs(3600)),
-        );
-    }
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/").await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers()[hyper::header::LOCATION], "/dir/");
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
 
-    #[test]
-    fn can_compress() {
-        let args = Args::default();
+        // Path prefix is preserved.
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            path_prefix: Some("/prefix".to_owned()),
+            root_redirect: Some("/dir/".to_owned()),
+            ..Default::default()
+        };
         let (service, _) = bootstrap(args);
-        assert!(service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+        for uri in ["/prefix", "/prefix/"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::FOUND);
+            assert_eq!(res.headers()[hyper::header::LOCATION], "/prefix/dir/");
+        }
+        let res = send(&service, "/prefix/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(&service, "/prefixed").await;
+        assert_ne!(res.status(), StatusCode::FOUND);
     }
 
-    #[test]
-    fn cannot_compress() {
+    #[tokio::test]
+    async fn handle_request_prefix_root_index() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
         let args = Args {
-            compress: false,
+            path: dir.path().canonicalize().unwrap(),
+            path_prefix: Some("/app".to_owned()),
             ..Default::default()
         };
         let (service, _) = bootstrap(args);
-        assert!(!service.can_compress(StatusCode::OK, &mime::STAR_STAR));
-        assert!(!service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
-        assert!(!service.can_compress(StatusCode::OK, &mime::IMAGE_JPEG));
-
+        for uri in ["/app", "/app/", "/app//"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::OK, "failed on {}", uri);
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(body, "home");
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_request_sitemap() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(base.join("docs")).unwrap();
+        std::fs::create_dir_all(base.join(".private")).unwrap();
+        std::fs::write(base.join("index.html"), "").unwrap();
+        std::fs::write(base.join("docs/a b.html"), "").unwrap();
+        std::fs::write(base.join("docs/style.css"), "").unwrap();
+        std::fs::write(base.join(".private/secret.html"), "").unwrap();
+        std::fs::write(base.join("ignored.html"), "").unwrap();
+        // Gitignore rules apply while walking inside a repository only.
+        std::fs::create_dir_all(base.join(".git")).unwrap();
+        std::fs::write(base.join(".gitignore"), "ignored.html\n").unwrap();
+
+        let args = Args {
+            path: base.clone(),
+            all: false,
+            sitemap: true,
+            sitemap_base_url: Some("https://example.com/".to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/sitemap.xml").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[CONTENT_TYPE],
+            "application/xml; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        let locs = body
+            .lines()
+            .filter_map(|line| line.split("<loc>").nth(1)?.split("</loc>").next())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locs,
+            [
+                "https://example.com/docs/a%20b.html",
+                "https://example.com/index.html"
+            ]
+        );
+
+        // Locations fall back to the server address rather than `Host`
+        // header, with the scheme actually served.
+        let sitemap_of = |tls_self_signed| {
+            let args = Args {
+                path: base.clone(),
+                all: false,
+                sitemap: true,
+                path_prefix: Some("/site".to_owned()),
+                tls_self_signed,
+                ..Default::default()
+            };
+            async move {
+                let (service, _) = bootstrap(args);
+                let req = hyper::Request::get("/site/sitemap.xml")
+                    .header(hyper::header::HOST, "evil.example")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = service.handle_request(&req, None).await.unwrap();
+                let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let body = sitemap_of(false).await;
+        assert!(body.contains("<loc>http://127.0.0.1:5000/site/index.html</loc>"));
+        assert!(!body.contains("evil.example"));
+        let body = sitemap_of(true).await;
+        assert!(body.contains("<loc>https://127.0.0.1:5000/site/index.html</loc>"));
+    }
+
+    #[tokio::test]
+    async fn handle_request_version_endpoint() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            expose_version: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, VERSION_ENDPOINT).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::APPLICATION_JSON),
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["server"], SERVER_VERSION);
+
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, VERSION_ENDPOINT).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handle_request_uri_too_long() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            max_path_length: 16,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(&service, &format!("/{}", "a".repeat(17))).await;
+        assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+        // Checked after percent decoding.
+        let res = send(&service, &format!("/{}", "%61".repeat(16))).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handle_request_allowed_host() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            allowed_hosts: vec!["localhost".to_owned(), "example.com:8080".to_owned()],
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let service = &service;
+        let send_to = |host: &'static str| {
+            let req = hyper::Request::get("/file.txt")
+                .header(hyper::header::HOST, host)
+                .body(Body::empty())
+                .unwrap();
+            async move { service.handle_request(&req, None).await.unwrap().status() }
+        };
+        for host in ["localhost", "LOCALHOST:5000", "example.com:8080"] {
+            assert_eq!(send_to(host).await, StatusCode::OK, "failed on {}", host);
+        }
+        for host in ["attacker.example", "example.com", "example.com:80"] {
+            let status = send_to(host).await;
+            assert_eq!(
+                status,
+                StatusCode::MISDIRECTED_REQUEST,
+                "failed on {}",
+                host
+            );
+        }
+        let status = service
+            .handle_request(&Request::default(), None)
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, StatusCode::MISDIRECTED_REQUEST);
+
+        // Any host is allowed by default.
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert_eq!(send(&service, "/file.txt").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn handle_request_basic_auth() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            auth: Some(("user".to_owned(), "secret".to_owned())),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers()[hyper::header::WWW_AUTHENTICATE],
+            r#"Basic realm="sfz""#
+        );
+
+        let send_with_auth = |user, password| {
+            let mut req = hyper::Request::get("/file.txt")
+                .body(Body::empty())
+                .unwrap();
+            req.headers_mut()
+                .typed_insert(Authorization::basic(user, password));
+            let service = &service;
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+        let res = send_with_auth("user", "wrong").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send_with_auth("user", "secreT").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send_with_auth("usex", "secret").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send_with_auth("user", "secret").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn handle_request_htpasswd() {
+        let htpasswd = "alice:$apr1$r31..G..$3UUppPV2F0WcPRalJVo3i.";
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            htpasswd: Some(htpasswd.parse().unwrap()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let send_with_auth = |user, password| {
+            let mut req = hyper::Request::get("/file.txt")
+                .body(Body::empty())
+                .unwrap();
+            req.headers_mut()
+                .typed_insert(Authorization::basic(user, password));
+            let service = &service;
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+        let res = send_with_auth("alice", "wrong").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send_with_auth("bob", "secret").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = send_with_auth("alice", "secret").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn handle_request_exclude() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("app.log"), "log").unwrap();
+        std::fs::create_dir(dir.path().join("secret")).unwrap();
+        std::fs::write(dir.path().join("secret/key.txt"), "key").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            render_index: false,
+            exclude: vec!["*.log".to_owned(), "secret".to_owned()],
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for uri in ["/app.log", "/secret", "/secret/key.txt"] {
+            assert_eq!(send(&service, uri).await.status(), StatusCode::NOT_FOUND);
+        }
+        assert_eq!(send(&service, "/file.txt").await.status(), StatusCode::OK);
+
+        let res = send(&service, "/").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains("file.txt"));
+        assert!(!page.contains("app.log"));
+        assert!(!page.contains("secret"));
+
+        let res = send(&service, "/?action=zip").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let zip = zip::ZipArchive::new(io::Cursor::new(body)).unwrap();
+        assert!(zip.file_names().all(|name| !name.contains("secret")));
+    }
+
+    #[tokio::test]
+    async fn handle_request_git() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/config"), "[core]").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        std::fs::create_dir(dir.path().join(".GIT")).unwrap();
+        std::fs::write(dir.path().join(".GIT/HEAD"), "ref").unwrap();
+        let (service, _) = bootstrap(args.clone());
+        for uri in ["/.git/config", "/.GIT/HEAD", "/.Git/config"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        // Not even forbidden, which would reveal the repository.
+        let (service, _) = bootstrap(Args {
+            hidden_status: 403,
+            ..args.clone()
+        });
+        let res = send(&service, "/.git/config").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let args = Args {
+            allow_git: true,
+            ..args
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/.git/config").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn handle_request_git_in_listing() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/.GIT")).unwrap();
+        std::fs::write(dir.path().join("sub/.GIT/HEAD"), "ref").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/config"), "[core]").unwrap();
+        std::fs::write(dir.path().join(".env"), "").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            all: true,
+            render_index: false,
+            ..Default::default()
+        };
+        let is_git = |name: &str| name.split('/').any(|c| c.eq_ignore_ascii_case(".git"));
+
+        let (service, _) = bootstrap(args.clone());
+        let res = send(&service, "/?action=zip").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let zip = zip::ZipArchive::new(io::Cursor::new(body)).unwrap();
+        assert!(zip.file_names().any(|name| name.ends_with("/.env")));
+        assert!(zip.file_names().all(|name| !is_git(name)));
+
+        let res = send(&service, "/").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains(".env"));
+        assert!(!page.contains(".git"));
+
+        let args = Args {
+            allow_git: true,
+            ..args
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/?action=zip").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let zip = zip::ZipArchive::new(io::Cursor::new(body)).unwrap();
+        assert!(zip.file_names().any(|name| name.ends_with("/.git/config")));
+        assert!(zip
+            .file_names()
+            .any(|name| name.ends_with("/sub/.GIT/HEAD")));
+    }
+
+    #[tokio::test]
+    async fn handle_request_empty_zip() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/empty?action=zip").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let len = res.headers().typed_get::<ContentLength>().unwrap().0;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body.len() as u64, len);
+        let zip = zip::ZipArchive::new(io::Cursor::new(body)).unwrap();
+        assert_eq!(zip.file_names().collect::<Vec<_>>(), ["empty/"]);
+
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            render_index: false,
+            empty_zip_status: 204,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/empty?action=zip").await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(res.headers().get(CONTENT_DISPOSITION).is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn handle_request_hide_index_in_listing() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<h1>index</h1>").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            render_index: false,
+            hide_index_in_listing: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains("file.txt"));
+        assert!(!page.contains("index.html"));
+
+        // Still fetchable directly.
+        let res = send(&service, "/index.html").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"<h1>index</h1>");
+    }
+
+    #[tokio::test]
+    async fn call_with_error_pages() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("404.html"), "<h1>Not Found</h1>").unwrap();
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            error_page_dir: Some(dir.path().to_owned()),
+            log: false,
+            ..Default::default()
+        };
+        let service = Arc::new(InnerService::new(args).unwrap());
+        let call = |uri| {
+            let req = hyper::Request::get(uri).body(Body::empty()).unwrap();
+            service.clone().call(req, None, None)
+        };
+
+        let res = call("/nonexistent").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"<h1>Not Found</h1>");
+
+        let res = call("/file.txt?action=invalid").await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"500 Internal Server Error");
+    }
+
+    #[tokio::test]
+    async fn call_with_single_error_pages() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("404.html"), "<h1>From dir</h1>").unwrap();
+        std::fs::write(dir.path().join("missing.html"), "<h1>Missing</h1>").unwrap();
+        std::fs::write(dir.path().join("oops.html"), "<h1>Oops</h1>").unwrap();
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            error_page_dir: Some(dir.path().to_owned()),
+            not_found_page: Some(dir.path().join("missing.html")),
+            internal_error_page: Some(dir.path().join("oops.html")),
+            log: false,
+            ..Default::default()
+        };
+        let service = Arc::new(InnerService::new(args).unwrap());
+        let call = |uri| {
+            let req = hyper::Request::get(uri).body(Body::empty()).unwrap();
+            service.clone().call(req, None, None)
+        };
+
+        for (uri, status, page) in [
+            ("/nonexistent", StatusCode::NOT_FOUND, "<h1>Missing</h1>"),
+            (
+                "/file.txt?action=invalid",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "<h1>Oops</h1>",
+            ),
+        ] {
+            let res = call(uri).await.unwrap();
+            assert_eq!(res.status(), status);
+            assert_eq!(
+                res.headers().typed_get::<ContentType>(),
+                Some(ContentType::html())
+            );
+            assert_eq!(
+                res.headers().typed_get::<ContentLength>(),
+                Some(ContentLength(page.len() as u64))
+            );
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(&body[..], page.as_bytes());
+        }
+
+        // Unreadable pages fail at startup.
+        let args = Args {
+            forbidden_page: Some(dir.path().join("nonexistent.html")),
+            ..Default::default()
+        };
+        assert!(InnerService::new(args).is_err());
+        let args = Args {
+            error_page_dir: Some(dir.path().join("nonexistent")),
+            ..Default::default()
+        };
+        assert!(InnerService::new(args).is_err());
+    }
+
+    #[tokio::test]
+    async fn handle_request_strip_prefix_from_listing() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            path_prefix: Some("/prefix".to_owned()),
+            render_index: false,
+            strip_prefix_from_listing: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/prefix/dir_with_sub_dirs?sort=size").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            res.headers()[hyper::header::LOCATION],
+            "dir_with_sub_dirs/?sort=size"
+        );
+
+        let res = send(&service, "/prefix/dir_with_sub_dirs/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains(r#"href="file.txt""#));
+        assert!(!page.contains("prefix"));
+    }
+
+    #[tokio::test]
+    async fn handle_request_compression_threads() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("text.txt"), "a".repeat(4096)).unwrap();
+        let send_gzip = |compression_threads| {
+            let args = Args {
+                path: dir.path().canonicalize().unwrap(),
+                compression_threads,
+                ..Default::default()
+            };
+            let req = hyper::Request::get("/text.txt")
+                .header(hyper::header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let (service, _) = bootstrap(args);
+                let res = service.handle_request(&req, None).await.unwrap();
+                // Permits are held until bodies are sent.
+                assert_eq!(
+                    service
+                        .compression_permits
+                        .as_ref()
+                        .map(|p| p.available_permits()),
+                    compression_threads.map(|n| n.saturating_sub(1)),
+                );
+                res
+            }
+        };
+
+        // No permit left, so sent uncompressed.
+        let res = send_gzip(Some(0)).await;
+        assert!(res.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().typed_get::<ContentLength>().unwrap().0, 4096);
+        assert_eq!(
+            res.headers().get(hyper::header::VARY).unwrap(),
+            "accept-encoding"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "a".repeat(4096));
+
+        for compression_threads in [Some(1), None] {
+            let res = send_gzip(compression_threads).await;
+            assert_eq!(
+                res.headers().get(hyper::header::CONTENT_ENCODING).unwrap(),
+                "gzip"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_request_compress_if_smaller() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        // Bytes of a pseudo-random sequence are barely compressible.
+        let mut state = 0x2545_f491_u32;
+        let random = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        std::fs::write(dir.path().join("random.txt"), &random).unwrap();
+        std::fs::write(dir.path().join("text.txt"), "a".repeat(4096)).unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            compress_if_smaller: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let send_gzip = |uri| {
+            let req = hyper::Request::get(uri)
+                .header(hyper::header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            let service = &service;
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+
+        // Identity wins.
+        let res = send_gzip("/random.txt").await;
+        assert!(res.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers().typed_get::<ContentLength>().unwrap().0, 4096);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], &random[..]);
+
+        // Compressed wins, with a known length.
+        let res = send_gzip("/text.txt").await;
+        assert_eq!(
+            res.headers().get(hyper::header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        let len = res.headers().typed_get::<ContentLength>().unwrap().0;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body.len() as u64, len);
+        assert!(len < 4096);
+    }
+
+    #[test]
+    fn bind_address_in_use() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let err = super::bind(&address).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!(
+                "error: address {} is already in use. Stop the process using it or choose another port with --port",
+                address
+            )
+        );
+    }
+
+    #[test]
+    fn bind_error() {
+        let address = "0.0.0.0:80".parse().unwrap();
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            super::bind_error(&address, &denied),
+            "error: port 80 requires elevated privileges or CAP_NET_BIND_SERVICE. Choose a port above 1023 with --port"
+        );
+        let address = "10.1.2.3:5000".parse().unwrap();
+        assert!(super::bind_error(&address, &denied)
+            .starts_with("error: failed to bind 10.1.2.3:5000: "));
+        let unavailable = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        assert_eq!(
+            super::bind_error(&address, &unavailable),
+            "error: address 10.1.2.3 is not available on this machine. Choose another one with --bind"
+        );
+    }
+
+    #[test]
+    fn collapse_slashes() {
+        assert_eq!(super::collapse_slashes(""), "");
+        assert_eq!(super::collapse_slashes("/"), "");
+        assert_eq!(super::collapse_slashes("foo/bar.txt"), "foo/bar.txt");
+        assert_eq!(super::collapse_slashes("/foo///bar.txt"), "foo/bar.txt");
+        assert_eq!(super::collapse_slashes("//foo//dir//"), "foo/dir/");
+    }
+
+    #[test]
+    fn has_windows_unsafe_segment() {
+        for path in ["CON", "dir/nul", "nul.txt", "Com1", "lpt9.log", "aux /x"] {
+            assert!(super::has_windows_unsafe_segment(path), "{}", path);
+        }
+        for path in ["file.", "dir./file.txt", "file ", "dir /"] {
+            assert!(super::has_windows_unsafe_segment(path), "{}", path);
+        }
+        for path in [
+            "",
+            "console.txt",
+            "com0",
+            "com10",
+            "nul_file",
+            "dir/",
+            "../a.txt",
+            "aaé",
+            "é1",
+            "coé",
+        ] {
+            assert!(!super::has_windows_unsafe_segment(path), "{}", path);
+        }
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn handle_request_windows_unsafe_path() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for uri in ["/CON", "/dir/nul.txt", "/file.txt.", "/file.txt%20"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "failed on {}", uri);
+        }
+        assert_eq!(send(&service, "/file.txt").await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn file_path_from_path_with_duplicate_slashes() {
+        let args = Args {
+            render_index: false,
+            path: Path::new("/storage").to_owned(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert_eq!(
+            service
+                .file_path_from_path("//foo//bar.txt", service.args.render_index)
+                .unwrap(),
+            service
+                .file_path_from_path("/foo/bar.txt", service.args.render_index)
+                .unwrap(),
+        );
+        assert_eq!(
+            service
+                .file_path_from_path("///foo///bar.txt", service.args.render_index)
+                .unwrap(),
+            Some(PathBuf::from("/storage/foo/bar.txt")),
+        );
+    }
+
+    #[tokio::test]
+    async fn guess_path_mime() {
+        let mime_type =
+            InnerService::guess_path_mime("file-wthout-extension", Action::DownloadFile).await;
+        assert_eq!(mime_type, mime::TEXT_PLAIN_UTF_8);
+
+        let mime_type = InnerService::guess_path_mime("file.json", Action::DownloadFile).await;
+        let json_utf8 = "application/json; charset=utf-8"
+            .parse::<mime::Mime>()
+            .unwrap();
+        assert_eq!(mime_type, json_utf8);
+        assert_eq!(mime_type.get_param(mime::CHARSET), Some(mime::UTF_8));
+
+        let mime_type = InnerService::guess_path_mime("lib.wasm", Action::DownloadFile).await;
+        let wasm = "application/wasm".parse::<mime::Mime>().unwrap();
+        assert_eq!(mime_type, wasm);
+        assert_eq!(mime_type.get_param(mime::CHARSET), None);
+
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let binary = dir.path().join("binary");
+        std::fs::write(&binary, b"\x7fELF\x02\x01\x01\x00").unwrap();
+        let mime_type = InnerService::guess_path_mime(&binary, Action::DownloadFile).await;
+        assert_eq!(mime_type, mime::APPLICATION_OCTET_STREAM);
+        let text = dir.path().join("LICENSE");
+        std::fs::write(&text, "世界").unwrap();
+        let mime_type = InnerService::guess_path_mime(&text, Action::DownloadFile).await;
+        assert_eq!(mime_type, mime::TEXT_PLAIN_UTF_8);
+
+        let dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let mime_type =
+            InnerService::guess_path_mime(dir_path, Action::ListDir(ListingFormat::Html)).await;
+        assert_eq!(mime_type, mime::TEXT_HTML_UTF_8);
+
+        let dir_path = PathBuf::from("./tests");
+        let mime_type = InnerService::guess_path_mime(dir_path, Action::DownloadZip).await;
+        assert_eq!(mime_type, mime::APPLICATION_OCTET_STREAM);
+    }
+
+    #[test]
+    fn enable_cors() {
+        let args = Args::default();
+        let (service, mut res) = bootstrap(args);
+        service.enable_cors(&Request::default(), &mut res);
+        assert_eq!(
+            res.headers()
+                .typed_get::<AccessControlAllowOrigin>()
+                .unwrap(),
+            AccessControlAllowOrigin::ANY,
+        );
+        assert_eq!(
+            res.headers()
+                .typed_get::<AccessControlAllowHeaders>()
+                .unwrap(),
+            vec![
+                hyper::header::RANGE,
+                hyper::header::CONTENT_TYPE,
+                hyper::header::ACCEPT,
+                hyper::header::ORIGIN,
+            ]
+            .into_iter()
+            .collect::<AccessControlAllowHeaders>(),
+        );
+    }
+
+    #[test]
+    fn enable_coi() {
+        let args = Args::default();
+        let (service, mut res) = bootstrap(args);
+        service.enable_coi(&mut res, None);
+        assert_eq!(
+            res.headers().get(CROSS_ORIGIN_OPENER_POLICY).unwrap(),
+            "same-origin",
+        );
+        assert_eq!(
+            res.headers().get(CROSS_ORIGIN_EMBEDDER_POLICY).unwrap(),
+            "require-corp",
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_request_extra_headers() {
+        let header = |name: &'static str, value: &'static str| {
+            (
+                hyper::header::HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            )
+        };
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            extra_headers: vec![
+                header("x-robots-tag", "noindex"),
+                header("link", "</a.css>; rel=preload"),
+                header("link", "</b.js>; rel=preload"),
+                header("cache-control", "no-cache"),
+            ],
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-robots-tag"], "noindex");
+        let links: Vec<_> = res.headers().get_all("link").iter().collect();
+        assert_eq!(links, ["</a.css>; rel=preload", "</b.js>; rel=preload"]);
+        // Defaults are replaced.
+        let cache_control: Vec<_> = res.headers().get_all(CACHE_CONTROL).iter().collect();
+        assert_eq!(cache_control, ["no-cache"]);
+    }
+
+    #[test]
+    fn enable_coi_html_only() {
+        let args = Args {
+            coi_html_only: true,
+            ..Default::default()
+        };
+        let (service, mut res) = bootstrap(args);
+        service.enable_coi(&mut res, Some(&mime::TEXT_HTML_UTF_8));
+        assert_eq!(
+            res.headers().get(CROSS_ORIGIN_EMBEDDER_POLICY).unwrap(),
+            "require-corp",
+        );
+
+        let (service, mut res) = bootstrap(Args {
+            coi_html_only: true,
+            ..Default::default()
+        });
+        service.enable_coi(&mut res, Some(&mime::IMAGE_PNG));
+        service.enable_coi(&mut res, None);
+        assert!(res.headers().get(CROSS_ORIGIN_EMBEDDER_POLICY).is_none());
+        assert!(res.headers().get(CROSS_ORIGIN_OPENER_POLICY).is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_coi_errors() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args.clone());
+        let res = send(&service, "/not-found.txt").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()[CROSS_ORIGIN_EMBEDDER_POLICY], "require-corp");
+        assert_eq!(res.headers()[CROSS_ORIGIN_OPENER_POLICY], "same-origin");
+
+        let (service, _) = bootstrap(Args {
+            coi_html_only: true,
+            ..args
+        });
+        let res = send(&service, "/not-found.txt").await;
+        assert!(res.headers().get(CROSS_ORIGIN_EMBEDDER_POLICY).is_none());
+    }
+
+    #[test]
+    fn enable_cors_origin_regex() {
+        let args = Args {
+            cors: false,
+            cors_origin_regex: Some(r"^https://.*\.example\.com$".to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = |origin| {
+            hyper::Request::get("/")
+                .header(hyper::header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let mut res = Response::default();
+        service.enable_cors(&req("https://preview-1.example.com"), &mut res);
+        assert_eq!(
+            res.headers()
+                .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://preview-1.example.com",
+        );
+        assert_eq!(res.headers().get(hyper::header::VARY).unwrap(), "origin");
+
+        let mut res = Response::default();
+        service.enable_cors(&req("https://example.org"), &mut res);
+        assert!(res
+            .headers()
+            .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert!(res
+            .headers()
+            .typed_get::<AccessControlAllowHeaders>()
+            .is_none());
+        assert_eq!(res.headers().get(hyper::header::VARY).unwrap(), "origin");
+    }
+
+    #[test]
+    fn disable_cors() {
+        let args = Args {
+            cors: false,
+            ..Default::default()
+        };
+        let (service, mut res) = bootstrap(args);
+        service.enable_cors(&Request::default(), &mut res);
+        assert!(res
+            .headers()
+            .typed_get::<AccessControlAllowOrigin>()
+            .is_none());
+    }
+
+    #[test]
+    fn enable_cache_control() {
+        let args = Args::default();
+        let (service, mut res) = bootstrap(args);
+        let req = Request::default();
+        service.enable_cache_control(&req, &mut res);
+        assert_eq!(
+            res.headers().typed_get::<CacheControl>().unwrap(),
+            CacheControl::new()
+                .with_public()
+                .with_max_age(Duration::default()),
+        );
+
+        let cache = 3600;
+        let args = Args {
+            cache,
+            ..Default::default()
+        };
+        let (service, mut res) = bootstrap(args);
+        service.enable_cache_control(&req, &mut res);
+        assert_eq!(
+            res.headers().typed_get::<CacheControl>().unwrap(),
+            CacheControl::new()
+                .with_public()
+                .with_max_age(Duration::from_secs(3600)),
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_request_nocache() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let etag = send(&service, "/file.txt").await.headers()[hyper::header::ETAG].clone();
+        let send_if_none_match = |uri| {
+            let req = hyper::Request::get(uri)
+                .header(hyper::header::IF_NONE_MATCH, etag.clone())
+                .body(Body::empty())
+                .unwrap();
+            let service = &service;
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+
+        let res = send_if_none_match("/file.txt").await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = send_if_none_match("/file.txt?nocache=1").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CACHE_CONTROL], "no-store");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "01234567");
+    }
+
+    #[test]
+    fn enable_cache_control_immutable() {
+        let args = Args {
+            cache: 3600,
+            immutable_pattern: Some(r"\.[0-9a-f]{6,}\.(js|css)$".to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        let req = hyper::Request::get("/assets/app.abc123.js")
+            .body(Body::empty())
+            .unwrap();
+        let mut res = Response::default();
+        service.enable_cache_control(&req, &mut res);
+        assert_eq!(res.headers()[CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+
+        let req = hyper::Request::get("/assets/app.js")
+            .body(Body::empty())
+            .unwrap();
+        let mut res = Response::default();
+        service.enable_cache_control(&req, &mut res);
+        assert_eq!(
+            res.headers().typed_get::<CacheControl>().unwrap(),
+            CacheControl::new()
+                .with_public()
+                .with_max_age(Duration::from_secs(3600)),
+        );
+    }
+
+    #[test]
+    fn can_compress() {
+        let args = Args::default();
+        let (service, _) = bootstrap(args);
+        assert!(service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+        assert!(service.can_compress(StatusCode::OK, &mime::APPLICATION_JSON));
+    }
+
+    #[tokio::test]
+    async fn handle_request_compress_threshold() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            compress_threshold: 1024,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            res.headers().typed_get::<ContentLength>(),
+            Some(ContentLength(8))
+        );
+
+        assert!(!service.below_compress_threshold(None));
+        assert!(!service.below_compress_threshold(Some(1024)));
+        assert!(service.below_compress_threshold(Some(1023)));
+    }
+
+    #[test]
+    fn cannot_compress_when_overloaded() {
+        let args = Args {
+            compress_adaptive: true,
+            compress_adaptive_threshold: 10,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args.clone());
+        assert!(service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+        service.load.record(Duration::from_millis(160));
+        assert!(!service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+
+        // Threshold is configurable.
+        let (service, _) = bootstrap(Args {
+            compress_adaptive_threshold: 100,
+            ..args
+        });
+        service.load.record(Duration::from_millis(160));
+        assert!(service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+
+        // Load is ignored unless `compress_adaptive` is on.
+        let (service, _) = bootstrap(Args::default());
+        service.load.record(Duration::from_secs(10));
+        assert!(service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+    }
+
+    #[test]
+    fn cannot_compress() {
+        let args = Args {
+            compress: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(!service.can_compress(StatusCode::OK, &mime::STAR_STAR));
+        assert!(!service.can_compress(StatusCode::OK, &mime::TEXT_PLAIN));
+        assert!(!service.can_compress(StatusCode::OK, &mime::IMAGE_JPEG));
+
+        let args = Args::default();
+        let (service, _) = bootstrap(args);
+        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::STAR_STAR));
+        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::TEXT_PLAIN));
+        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::IMAGE_JPEG));
+        assert!(!service.can_compress(StatusCode::OK, &"video/*".parse::<mime::Mime>().unwrap()));
+        assert!(!service.can_compress(StatusCode::OK, &"audio/*".parse::<mime::Mime>().unwrap()));
+    }
+
+    #[test]
+    fn path_exists() {
+        with_current_dir(get_tests_dir(), || {
+            let args = Args::default();
+            let (service, _) = bootstrap(args);
+            // Exists but not hidden nor ignored
+            assert!(service.path_exists("file.txt"));
+        });
+    }
+
+    #[test]
+    fn path_does_not_exists() {
+        with_current_dir(get_tests_dir(), || {
+            let args = Args {
+                all: false,
+                ..Default::default()
+            };
+            let (service, _) = bootstrap(args);
+
+            // Not exists
+            let path = "NOT_EXISTS_README.md";
+            assert!(!PathBuf::from(path).exists());
+            assert!(!service.path_exists(path));
+
+            // Exists but hidden
+            let path = ".hidden.html";
+            assert!(PathBuf::from(path).exists());
+            assert!(service.path_is_hidden(path));
+            assert!(!service.path_exists(path));
+
+            // Exists but the file's parent is hidden
+            let path = ".hidden/nested.html";
+            assert!(PathBuf::from(path).exists());
+            assert!(service.path_is_hidden(path));
+            assert!(!service.path_exists(path));
+
+            // Exists and not hidden but ignored
+            let path = "ignore_pattern";
+            assert!(PathBuf::from(path).exists());
+            assert!(!service.path_is_hidden(path));
+            assert!(!service.path_exists(path));
+        });
+    }
+
+    #[test]
+    fn path_is_hidden() {
+        // A file prefixed with `.` is considered as hidden.
+        let args = Args {
+            all: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(service.path_is_hidden(".a-hidden-file"));
+    }
+
+    #[test]
+    fn path_is_not_hidden() {
+        // `--all` flag is on
+        let args = Args::default();
+        let (service, _) = bootstrap(args);
+        assert!(!service.path_is_hidden(".a-hidden-file"));
+        assert!(!service.path_is_hidden("a-public-file"));
+
+        // `--all` flag is off and the file is not prefixed with `.`
+        let args = Args {
+            all: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(!service.path_is_hidden("a-public-file"));
+    }
+
+    #[test]
+    fn path_is_git() {
+        let (service, _) = bootstrap(Args::default());
+        assert!(service.path_is_hidden(".git"));
+        assert!(service.path_is_hidden("dir/.git/config"));
+        assert!(service.path_is_hidden(".GIT/config"));
+        assert!(service.path_is_hidden("dir/.Git/HEAD"));
+        assert!(!service.path_is_hidden(".gitignore"));
+
+        let args = Args {
+            allow_git: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(!service.path_is_hidden("dir/.git/config"));
+    }
+
+    #[test]
+    fn path_is_ignored() {
+        with_current_dir(get_tests_dir(), || {
+            let args = Args::default();
+            let (service, _) = bootstrap(args);
+            assert!(service.path_is_ignored("ignore_pattern"));
+            assert!(service.path_is_ignored("dir/ignore_pattern"));
+        });
+    }
+
+    #[test]
+    fn path_is_not_ignored() {
+        with_current_dir(get_tests_dir(), || {
+            // `--no-ignore` flag is on
+            let args = Args {
+                ignore: false,
+                ..Default::default()
+            };
+            let (service, _) = bootstrap(args);
+            assert!(!service.path_is_ignored("ignore_pattern"));
+            assert!(!service.path_is_ignored("dir/ignore_pattern"));
+
+            // file.txt and .hidden.html is not ignored.
+            let args = Args::default();
+            let (service, _) = bootstrap(args);
+            assert!(!service.path_is_ignored("file.txt"));
+            assert!(!service.path_is_ignored(".hidden.html"));
+        });
+    }
+
+    #[test]
+    fn path_is_ignored_after_reload() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        std::fs::write(dir.join(".gitignore"), "old_pattern\n").unwrap();
+        let args = Args {
+            path: dir.clone(),
+            watch_ignore: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(service.path_is_ignored(dir.join("old_pattern")));
+        assert!(!service.path_is_ignored(dir.join("new_pattern")));
+
+        std::fs::write(dir.join(".gitignore"), "new_pattern\n").unwrap();
+        let reloaded = (0..50).any(|_| {
+            std::thread::sleep(Duration::from_millis(100));
+            service.path_is_ignored(dir.join("new_pattern"))
+        });
+        assert!(reloaded);
+        assert!(!service.path_is_ignored(dir.join("old_pattern")));
+    }
+
+    #[test]
+    fn path_is_under_basepath() {
+        #[cfg(unix)]
+        use std::os::unix::fs::symlink as symlink_file;
+        #[cfg(windows)]
+        use std::os::windows::fs::symlink_file;
+
+        let src_dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let src_dir = src_dir.path().canonicalize().unwrap();
+        let src_path = src_dir.join("src_file.txt");
+        let _ = File::create(&src_path);
+
+        // Is under service's base path
+        let symlink_path = src_dir.join("symlink");
+        let args = Args {
+            path: src_dir,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        symlink_file(&src_path, &symlink_path).unwrap();
+        assert!(service.path_is_under_basepath(&symlink_path));
+
+        // Not under base path.
+        let args = Args::default();
+        let (service, _) = bootstrap(args);
+        assert!(!service.path_is_under_basepath(&symlink_path));
+    }
+
+    #[test]
+    fn path_is_under_basepath_lexically() {
+        let args = Args {
+            path: PathBuf::from("/base"),
+            no_canonicalize: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        // Paths are never touched, so they need not exist.
+        assert!(service.path_is_under_basepath("/base/file.txt"));
+        assert!(service.path_is_under_basepath("/base/./dir/../file.txt"));
+        assert!(service.path_is_under_basepath("/base/dir/.."));
+        assert!(!service.path_is_under_basepath("/base/../etc/passwd"));
+        assert!(!service.path_is_under_basepath("/base/dir/../../etc/passwd"));
+        assert!(!service.path_is_under_basepath("/base-sibling/file.txt"));
+        assert!(!service.path_is_under_basepath("/base/../base-sibling"));
+    }
+
+    #[test]
+    fn strips_path_prefix() {
+        let args = Args {
+            path_prefix: Some("/foo".into()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        assert_eq!(
+            service.strip_path_prefix(&Path::new("foo/dir/to/bar.txt")),
+            Some(Path::new("dir/to/bar.txt"))
+        );
+
+        assert_eq!(
+            service.strip_path_prefix(&Path::new("dir/to/bar.txt")),
+            None
+        );
+
+        let args = Args::default();
+        let (service, _) = bootstrap(args);
+
+        assert_eq!(
+            service.strip_path_prefix(&Path::new("foo/dir/to/bar.txt")),
+            Some(Path::new("foo/dir/to/bar.txt"))
+        );
+    }
+
+    #[test]
+    fn enable_server_timing() {
+        let timings = [("open", Duration::from_micros(1500))];
+        let args = Args {
+            server_timing: true,
+            ..Default::default()
+        };
+        let (service, mut res) = bootstrap(args);
+        service.enable_server_timing(&mut res, &timings);
+        assert_eq!(res.headers().get(SERVER_TIMING).unwrap(), "open;dur=1.500");
+
+        let args = Args::default();
+        let (service, mut res) = bootstrap(args);
+        service.enable_server_timing(&mut res, &timings);
+        assert!(res.headers().get(SERVER_TIMING).is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_server_timing() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            server_timing: true,
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        let value = res.headers().get(SERVER_TIMING).unwrap().to_str().unwrap();
+        assert!(value.contains("open;dur="));
+        // Streamed compression happens after the response is returned.
+        assert!(!value.contains("compress;dur="));
+
+        let res = send(&service, "/").await;
+        let value = res.headers().get(SERVER_TIMING).unwrap().to_str().unwrap();
+        assert!(value.starts_with("render;dur="));
+
+        // Compression in memory is finished before responding.
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            server_timing: true,
+            compress_if_smaller: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = service.handle_request(&req, None).await.unwrap();
+        let value = res.headers().get(SERVER_TIMING).unwrap().to_str().unwrap();
+        assert!(value.contains("open;dur="));
+        assert!(value.contains("compress;dur="));
+    }
+
+    #[tokio::test]
+    async fn handle_request_accept_any_encoding() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for (accept_encoding, content_encoding) in [("*", Some("br")), ("*;q=0", None)] {
+            let req = hyper::Request::get("/file.txt")
+                .header(hyper::header::ACCEPT_ENCODING, accept_encoding)
+                .body(Body::empty())
+                .unwrap();
+            let res = service.handle_request(&req, None).await.unwrap();
+            let encoding = res.headers().get(hyper::header::CONTENT_ENCODING);
+            assert_eq!(encoding.map(|v| v.to_str().unwrap()), content_encoding);
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_request_x_accel() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub dir")).unwrap();
+        std::fs::write(dir.path().join("sub dir/large.mp4"), [0; 16]).unwrap();
+        std::fs::write(dir.path().join("small.txt"), "small").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            x_accel: Some("/internal/".to_owned()),
+            x_accel_min_size: 16,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        let res = send(&service, "/sub%20dir/large.mp4").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[X_ACCEL_REDIRECT],
+            "/internal/sub%20dir/large.mp4"
+        );
+        assert_eq!(res.headers()[CONTENT_TYPE], "video/mp4");
+        assert!(res.headers().contains_key(hyper::header::ETAG));
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        let res = send(&service, "/small.txt").await;
+        assert!(res.headers().get(X_ACCEL_REDIRECT).is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "small");
+    }
+
+    #[tokio::test]
+    async fn handle_request_root_page() {
+        let page = Builder::new().suffix(".html").tempfile().unwrap();
+        std::fs::write(page.path(), "<h1>landing</h1>").unwrap();
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            path_prefix: Some("/prefix".to_owned()),
+            render_index: false,
+            root_page: Some(page.path().to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for uri in ["/prefix", "/prefix/"] {
+            let res = send(&service, uri).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(body, "<h1>landing</h1>");
+        }
+
+        // Other directories are still listed.
+        let res = send(&service, "/prefix/dir_with_sub_dirs/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("sub_dir"));
+        assert!(!body.contains("landing"));
+    }
+
+    #[tokio::test]
+    async fn handle_request_robots() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            robots: Some("User-agent: *\nDisallow: /private/\n".to_owned()),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/robots.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().typed_get::<ContentType>(),
+            Some(ContentType::from(mime::TEXT_PLAIN_UTF_8))
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "User-agent: *\nDisallow: /private/\n");
+
+        // Unchanged if not configured.
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/robots.txt").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handle_request_spa() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<main></main>").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            spa: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/deep/route").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().typed_get::<ContentType>(),
+            Some(ContentType::from(mime::TEXT_HTML_UTF_8))
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"<main></main>");
+
+        // Missing assets are still missing.
+        let res = send(&service, "/missing.js").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let req = hyper::Request::post("/deep/route")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_file(dir.path().join("index.html")).unwrap();
+        let res = send(&service, "/deep/route").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handle_request_nosniff() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-1")
+            .body(Body::empty())
+            .unwrap();
+        let responses = [
+            send(&service, "/file.txt").await,
+            service.handle_request(&req, None).await.unwrap(),
+            send(&service, "/nonexistent").await,
+        ];
+        let statuses = responses.iter().map(|res| res.status()).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::PARTIAL_CONTENT,
+                StatusCode::NOT_FOUND
+            ]
+        );
+        for res in responses {
+            assert_eq!(res.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_request_hsts() {
+        let hsts = |hsts, hsts_include_subdomains| {
+            let args = Args {
+                path: get_tests_dir().as_ref().canonicalize().unwrap(),
+                hsts,
+                hsts_include_subdomains,
+                ..Default::default()
+            };
+            let (service, _) = bootstrap(args);
+            async move {
+                let res = send(&service, "/file.txt").await;
+                let hsts = res.headers().get(hyper::header::STRICT_TRANSPORT_SECURITY);
+                hsts.map(|value| value.to_str().unwrap().to_owned())
+            }
+        };
+        assert_eq!(hsts(Some(60), false).await.unwrap(), "max-age=60");
+        assert_eq!(
+            hsts(Some(60), true).await.unwrap(),
+            "max-age=60; includeSubDomains"
+        );
+        assert!(hsts(None, false).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_no_index_robots() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            no_index_robots: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        for uri in ["/file.txt", "/nonexistent"] {
+            let res = send(&service, uri).await;
+            assert_eq!(
+                res.headers().get(X_ROBOTS_TAG).unwrap(),
+                "noindex, nofollow"
+            );
+        }
+
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert!(res.headers().get(X_ROBOTS_TAG).is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_sniff() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        std::fs::write(dir.path().join("image"), png).unwrap();
+        std::fs::write(dir.path().join("text"), "plain").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            sniff: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let content_type = |res: Response| res.headers().typed_get::<ContentType>().unwrap();
+        assert_eq!(
+            content_type(send(&service, "/image").await),
+            ContentType::png()
+        );
+        assert_eq!(
+            content_type(send(&service, "/text").await),
+            ContentType::from(mime::TEXT_PLAIN_UTF_8),
+        );
+
+        // Without sniffing, binaries are only known to be binaries, even if
+        // charset detection is off.
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            charset_detect_bytes: 0,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert_eq!(
+            content_type(send(&service, "/image").await),
+            ContentType::octet_stream(),
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_request_transparent_gz() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let json = r#"{"hello":"world"}"#;
+        let chunks = vec![Ok::<_, io::Error>(bytes::Bytes::from(json))];
+        let gz = compress_stream(futures::stream::iter(chunks), GZIP, None).unwrap();
+        let gz = hyper::body::to_bytes(gz).await.unwrap();
+        std::fs::write(dir.path().join("data.json.gz"), gz).unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            transparent_gz: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        let res = send(&service, "/data.json").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let json_utf8 = "application/json; charset=utf-8"
+            .parse::<mime::Mime>()
+            .unwrap();
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(json_utf8),
+        );
+        assert!(res.headers().typed_get::<ContentLength>().is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], json.as_bytes());
+
+        // The gzipped file itself is still served as is.
+        let res = send(&service, "/data.json.gz").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().typed_get::<ContentLength>().is_some());
+
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/data.json").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handle_request_precompressed() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("app.js"), "original").unwrap();
+        std::fs::write(dir.path().join("app.js.br"), "brotli").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), "gzip").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let service = &service;
+        let get = |accept_encoding: Option<&'static str>| async move {
+            let mut req = hyper::Request::get("/app.js");
+            if let Some(accept_encoding) = accept_encoding {
+                req = req.header(hyper::header::ACCEPT_ENCODING, accept_encoding);
+            }
+            let req = req.body(Body::empty()).unwrap();
+            service.handle_request(&req, None).await.unwrap()
+        };
+
+        for (accept_encoding, encoding, body) in [
+            ("br", Some(BR), "brotli"),
+            ("gzip, br", Some(BR), "brotli"),
+            ("gzip, br;q=0.5", Some(GZIP), "gzip"),
+            ("zstd", Some(ZSTD), ""),
+        ] {
+            let res = get(Some(accept_encoding)).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let content_encoding = res.headers().get(hyper::header::CONTENT_ENCODING);
+            assert_eq!(content_encoding.and_then(|v| v.to_str().ok()), encoding);
+            assert!(res.headers()[hyper::header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("application/javascript"));
+            assert_eq!(
+                res.headers()[hyper::header::VARY],
+                hyper::header::ACCEPT_ENCODING.as_str()
+            );
+            // Without a `.zst` sidecar, contents are compressed on the fly.
+            if !body.is_empty() {
+                let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+                assert_eq!(&bytes[..], body.as_bytes());
+            }
+        }
+
+        let res = get(None).await;
+        assert!(res.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"original");
+
+        // Ranges apply to the bytes of the sidecar.
+        let req = hyper::Request::get("/app.js")
+            .header(hyper::header::ACCEPT_ENCODING, "br")
+            .header(hyper::header::RANGE, "bytes=1-3")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[hyper::header::CONTENT_ENCODING], BR);
+        assert_eq!(res.headers()[hyper::header::CONTENT_RANGE], "bytes 1-3/6");
+        assert_eq!(res.headers().typed_get::<ContentLength>().unwrap().0, 3);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"rot");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn handle_request_precompressed_permissions() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("app.js"), "original").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), "gzip").unwrap();
+        std::fs::write(dir.path().join("brotli"), "brotli").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("brotli"), dir.path().join("app.js.br"))
+            .unwrap();
+        let get = |args: Args| async move {
+            let (service, _) = bootstrap(args);
+            let req = hyper::Request::get("/app.js")
+                .header(hyper::header::ACCEPT_ENCODING, "br, gzip;q=0.5")
+                .body(Body::empty())
+                .unwrap();
+            let res = service.handle_request(&req, None).await.unwrap();
+            hyper::body::to_bytes(res.into_body()).await.unwrap()
+        };
+        let path = dir.path().canonicalize().unwrap();
+
+        let body = get(Args {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(&body[..], b"brotli");
+
+        // Symlinked sidecars are skipped per `disable_symlinks` arg.
+        let body = get(Args {
+            path: path.clone(),
+            disable_symlinks: true,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(&body[..], b"gzip");
+
+        // So are excluded ones.
+        let body = get(Args {
+            path: path.clone(),
+            exclude: vec!["*.br".to_owned(), "*.gz".to_owned()],
+            ..Default::default()
+        })
+        .await;
+        assert_ne!(&body[..], b"brotli");
+        assert_ne!(&body[..], b"gzip");
+    }
+
+    #[test]
+    fn enable_debug_mime() {
+        let args = Args {
+            debug_mime: true,
+            ..Default::default()
+        };
+        let (service, mut res) = bootstrap(args);
+        service.enable_debug_mime(&mut res, "file.json");
+        assert_eq!(
+            res.headers().get(X_GUESSED_MIME).unwrap(),
+            "application/json"
+        );
+        service.enable_debug_mime(&mut res, "file-without-extension");
+        assert_eq!(res.headers().get(X_GUESSED_MIME).unwrap(), "unknown");
+
         let args = Args::default();
+        let (service, mut res) = bootstrap(args);
+        service.enable_debug_mime(&mut res, "file.json");
+        assert!(res.headers().get(X_GUESSED_MIME).is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_debug_mime() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            debug_mime: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.headers().get(X_GUESSED_MIME).unwrap(), "text/plain");
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::TEXT_PLAIN_UTF_8),
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_request_hidden_status() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join(".hidden"), "hidden").unwrap();
+        for (status, expected) in [(404, StatusCode::NOT_FOUND), (403, StatusCode::FORBIDDEN)] {
+            let args = Args {
+                path: dir.path().canonicalize().unwrap(),
+                all: false,
+                hidden_status: status,
+                ..Default::default()
+            };
+            let (service, _) = bootstrap(args);
+            assert_eq!(send(&service, "/.hidden").await.status(), expected);
+            // Nonexistent paths are always 404.
+            assert_eq!(
+                send(&service, "/nonexistent").await.status(),
+                StatusCode::NOT_FOUND,
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_request_strip_ansi() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let content = "\x1b[1;31merror\x1b[0m: \x1b[32mok\x1b[0m";
+        std::fs::write(dir.path().join("build.txt"), content).unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            strip_ansi: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        let res = send(&service, "/build.txt").await;
+        assert!(res.headers().typed_get::<ContentLength>().is_none());
+        assert!(res.headers().typed_get::<AcceptRanges>().is_none());
+        let etag = res.headers()[hyper::header::ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/"));
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"error: ok");
+
+        // Ranges of the file on disk don't apply to stripped contents.
+        let req = hyper::Request::get("/build.txt")
+            .header(hyper::header::RANGE, "bytes=0-6")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"error: ok");
+
+        let req = hyper::Request::get("/build.txt")
+            .header(hyper::header::IF_NONE_MATCH, etag)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn handle_request_deny_dotfiles_in_archives() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            all: true,
+            render_index: false,
+            deny_dotfiles_in_archives: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+
+        let res = send(&service, "/?action=zip").await;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let zip = zip::ZipArchive::new(io::Cursor::new(body)).unwrap();
+        let root = dir.path().file_name().unwrap().to_str().unwrap();
+        let mut names = zip.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, [format!("{}/", root), format!("{}/file.txt", root)]);
+
+        // Listing still shows dotfiles.
+        let req = hyper::Request::get("/")
+            .header(hyper::header::ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let items: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let names: Vec<_> = items.iter().map(|item| &item["name"]).collect();
+        assert_eq!(names, [".env", "file.txt"]);
+    }
+
+    #[tokio::test]
+    async fn handle_request_plain_listing() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            compress: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/dir_with_sub_dirs")
+            .header(hyper::header::ACCEPT, "text/plain")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::TEXT_PLAIN_UTF_8),
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            "/dir_with_sub_dirs/sub_dir/\n/dir_with_sub_dirs/file.txt\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_request_etag_strength() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let is_weak = |res: &Response| {
+            let etag = res.headers()[hyper::header::ETAG].to_str().unwrap();
+            etag.starts_with("W/")
+        };
+        assert!(!is_weak(&send(&service, "/file.txt").await));
+        let listing = send(&service, "/dir_with_sub_dirs").await;
+        assert!(is_weak(&listing));
+        assert!(is_weak(
+            &send(&service, "/dir_with_sub_dirs?action=zip").await
+        ));
+
+        // Listings validate conditional requests with their weak ETags.
+        let listing_etag = listing.headers().typed_get::<ETag>().unwrap();
+        let mut req = hyper::Request::get("/dir_with_sub_dirs")
+            .body(Body::empty())
+            .unwrap();
+        req.headers_mut()
+            .typed_insert(IfNoneMatch::from(listing_etag.clone()));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().typed_get::<ETag>(), Some(listing_etag));
+    }
+
+    #[tokio::test]
+    async fn handle_request_ndjson_listing() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/dir_with_sub_dirs")
+            .header(hyper::header::ACCEPT, NDJSON)
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], NDJSON);
+        assert!(res.headers().typed_get::<ContentLength>().is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.ends_with('\n'));
+        let mut names = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].clone())
+            .collect::<Vec<_>>();
+        names.sort_unstable_by_key(|name| name.to_string());
+        assert_eq!(names, ["file.txt", "sub_dir"]);
+    }
+
+    #[tokio::test]
+    async fn handle_request_compressed_json_listing() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/dir_with_sub_dirs")
+            .header(hyper::header::ACCEPT, "application/json")
+            .header(hyper::header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::APPLICATION_JSON),
+        );
+        assert_eq!(
+            res.headers().get(hyper::header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        assert_eq!(
+            res.headers().get(hyper::header::VARY).unwrap(),
+            "accept-encoding"
+        );
+        assert!(res.headers().typed_get::<ContentLength>().is_none());
+
+        let body = decompress_stream(res.into_body().map_err(io::Error::other), GZIP).unwrap();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        let items: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let names: Vec<_> = items.iter().map(|item| &item["name"]).collect();
+        assert_eq!(names, ["sub_dir", "file.txt"]);
+    }
+
+    #[tokio::test]
+    async fn handle_request_zip_not_modified() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/dir_with_sub_dirs?action=zip").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers().typed_get::<ETag>().unwrap();
+        assert!(res.headers().typed_get::<LastModified>().is_some());
+
+        let mut req = hyper::Request::get("/dir_with_sub_dirs?action=zip")
+            .body(Body::empty())
+            .unwrap();
+        req.headers_mut().typed_insert(IfNoneMatch::from(etag));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn handle_request_listing_format_with_index() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<h1>index</h1>").unwrap();
+        std::fs::write(dir.path().join("file.txt"), "file").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            render_index: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let send_with_accept = |accept| {
+            let req = hyper::Request::get("/")
+                .header(hyper::header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap();
+            let service = &service;
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+
+        // HTML honors `render_index`.
+        let res = send_with_accept("text/html,application/json;q=0.9").await;
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::TEXT_HTML_UTF_8),
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"<h1>index</h1>");
+
+        // JSON always lists the directory.
+        let res = send_with_accept("application/json").await;
+        assert_eq!(
+            res.headers().typed_get::<ContentType>().unwrap(),
+            ContentType::from(mime::APPLICATION_JSON),
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let items: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let names: Vec<_> = items.iter().map(|item| &item["name"]).collect();
+        assert_eq!(names, ["file.txt", "index.html"]);
+    }
+
+    #[test]
+    fn compression_level() {
+        let req = |save_data: Option<&str>| {
+            let mut req = hyper::Request::get("/");
+            if let Some(value) = save_data {
+                req = req.header(SAVE_DATA, value);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let (service, _) = bootstrap(Args::default());
+        let level = |save_data| service.compression_level(&req(save_data));
+        assert!(level(None).is_none());
+        assert!(level(Some("off")).is_none());
+        assert!(matches!(level(Some("on")), Some(Level::Best)));
+        assert!(matches!(level(Some("On; foo")), Some(Level::Best)));
+
+        let args = Args {
+            compression_level: Some(4),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let level = |save_data| service.compression_level(&req(save_data));
+        assert!(matches!(level(None), Some(Level::Precise(4))));
+        assert!(matches!(level(Some("on")), Some(Level::Best)));
+    }
+
+    #[tokio::test]
+    async fn handle_request_save_data() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let text = (0..2000)
+            .map(|i| format!("line {}\n", i % 97))
+            .collect::<String>();
+        std::fs::write(dir.path().join("file.txt"), text).unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let send_br = |save_data| {
+            let req = hyper::Request::get("/file.txt")
+                .header(hyper::header::ACCEPT_ENCODING, "br")
+                .header(SAVE_DATA, save_data)
+                .body(Body::empty())
+                .unwrap();
+            let service = &service;
+            async move {
+                let res = service.handle_request(&req, None).await.unwrap();
+                assert_eq!(
+                    res.headers().get(hyper::header::CONTENT_ENCODING).unwrap(),
+                    "br"
+                );
+                hyper::body::to_bytes(res.into_body()).await.unwrap()
+            }
+        };
+        let normal = send_br("off").await;
+        let saved = send_br("on").await;
+        assert!(saved.len() < normal.len());
+    }
+
+    #[tokio::test]
+    async fn handle_request_head_with_range() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::head("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-3")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(hyper::header::CONTENT_RANGE).unwrap(),
+            "bytes 0-3/8",
+        );
+        assert_eq!(res.headers().typed_get::<ContentLength>().unwrap().0, 4);
+        // The entire entity might be compressed.
+        assert_eq!(res.headers()[hyper::header::VARY], "accept-encoding");
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn handle_request_attachment_ext() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("data.CSV"), "a,b\n1,2\n").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            attachment_ext: vec!["csv".to_owned(), "bin".to_owned()],
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/data.CSV").await;
+        assert_eq!(
+            res.headers()[CONTENT_DISPOSITION],
+            r#"attachment; filename="data.CSV""#
+        );
+        let res = send(&service, "/notes.txt").await;
+        assert!(res.headers().get(CONTENT_DISPOSITION).is_none());
+    }
+
+    #[tokio::test]
+    async fn handle_request_range_on_dir() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            render_index: false,
+            compress: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let full = send(&service, "/dir_with_sub_dirs").await;
+        let full = hyper::body::to_bytes(full.into_body()).await.unwrap();
+
+        // Range is ignored, and the entire listing is sent.
+        let req = hyper::Request::get("/dir_with_sub_dirs")
+            .header(hyper::header::RANGE, "bytes=0-1")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().typed_get::<AcceptRanges>().is_none());
+        assert!(res.headers().typed_get::<ContentRange>().is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, full);
+    }
+
+    #[tokio::test]
+    async fn handle_request_content_etag() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "same").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "same").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            etag: EtagAlgorithm::Xxhash,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let etag = send(&service, "/a.txt").await.headers()[hyper::header::ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with(r#""xxh3-"#));
+        let res = send(&service, "/b.txt").await;
+        assert_eq!(res.headers()[hyper::header::ETAG], etag);
+
+        // Digests follow content changes of the same size, even with the
+        // modified time set back.
+        let path = dir.path().join("a.txt");
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "diff").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let res = send(&service, "/a.txt").await;
+        assert_ne!(res.headers()[hyper::header::ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn handle_request_no_ranges_for() {
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt"), "01234567").unwrap();
+        std::fs::write(dir.path().join("file.mp4"), "01234567").unwrap();
+        let args = Args {
+            path: dir.path().canonicalize().unwrap(),
+            compress: false,
+            no_ranges_for: vec!["text/*".to_owned()],
+            ..Default::default()
+        };
         let (service, _) = bootstrap(args);
-        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::STAR_STAR));
-        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::TEXT_PLAIN));
-        assert!(!service.can_compress(StatusCode::PARTIAL_CONTENT, &mime::IMAGE_JPEG));
-        assert!(!service.can_compress(StatusCode::OK, &"video/*".parse::<mime::Mime>().unwrap()));
-        assert!(!service.can_compress(StatusCode::OK, &"audio/*".parse::<mime::Mime>().unwrap()));
+        let get_range = |uri| {
+            let service = &service;
+            let req = hyper::Request::get(uri)
+                .header(hyper::header::RANGE, "bytes=0-1")
+                .body(Body::empty())
+                .unwrap();
+            async move { service.handle_request(&req, None).await.unwrap() }
+        };
+
+        // Range is ignored for matching types.
+        let res = get_range("/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().typed_get::<AcceptRanges>().is_none());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "01234567");
+
+        let res = get_range("/file.mp4").await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().typed_get::<AcceptRanges>(),
+            Some(AcceptRanges::bytes())
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "01");
     }
 
-    #[test]
-    fn path_exists() {
-        with_current_dir(get_tests_dir(), || {
-            let args = Args::default();
-            let (service, _) = bootstrap(args);
-            // Exists but not hidden nor ignored
-            assert!(service.path_exists("file.txt"));
-        });
+    #[tokio::test]
+    async fn handle_request_range_with_unsupported_unit() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "items=0-1")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            res.headers().get(hyper::header::CONTENT_RANGE).unwrap(),
+            "bytes */8",
+        );
     }
 
-    #[test]
-    fn path_does_not_exists() {
-        with_current_dir(get_tests_dir(), || {
-            let args = Args {
-                all: false,
-                ..Default::default()
-            };
-            let (service, _) = bootstrap(args);
+    #[tokio::test]
+    async fn handle_request_unsatisfiable_range() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let range_of = |range: &'static str| {
+            hyper::Request::get("/file.txt")
+                .header(hyper::header::RANGE, range)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let res = service
+            .handle_request(&range_of("bytes=20-"), None)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[hyper::header::CONTENT_RANGE], "bytes */8");
 
-            // Not exists
-            let path = "NOT_EXISTS_README.md";
-            assert!(!PathBuf::from(path).exists());
-            assert!(!service.path_exists(path));
+        // Stale ranges per `If-Range` are ignored, even if unsatisfiable.
+        let mut req = range_of("bytes=20-");
+        req.headers_mut()
+            .typed_insert(IfRange::etag(r#""stale""#.parse().unwrap()));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
 
-            // Exists but hidden
-            let path = ".hidden.html";
-            assert!(PathBuf::from(path).exists());
-            assert!(service.path_is_hidden(path));
-            assert!(!service.path_exists(path));
+        // So are invalid ranges.
+        let res = service
+            .handle_request(&range_of("bytes=5-3"), None)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
 
-            // Exists but the file's parent is hidden
-            let path = ".hidden/nested.html";
-            assert!(PathBuf::from(path).exists());
-            assert!(service.path_is_hidden(path));
-            assert!(!service.path_exists(path));
+    #[tokio::test]
+    async fn handle_request_multiple_ranges() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-1,-2")
+            .header(hyper::header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(res.headers().get(hyper::header::CONTENT_RANGE).is_none());
+        // Multipart bodies are never compressed, but the entity might be.
+        assert!(res.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers()[hyper::header::VARY], "accept-encoding");
+        let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap().to_owned();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let length = res.headers().typed_get::<ContentLength>().unwrap().0;
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body.len() as u64, length);
 
-            // Exists and not hidden but ignored
-            let path = "ignore_pattern";
-            assert!(PathBuf::from(path).exists());
-            assert!(!service.path_is_hidden(path));
-            assert!(!service.path_exists(path));
-        });
+        // Each part carries its own headers.
+        let body = std::str::from_utf8(&body).unwrap();
+        let delimiter = format!("\r\n--{}", boundary);
+        let parts = body.split(&delimiter).collect::<Vec<_>>();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0], "");
+        for (part, range, content) in [(parts[1], "0-1", "01"), (parts[2], "6-7", "67")] {
+            let (headers, data) = part.split_once("\r\n\r\n").unwrap();
+            assert!(headers.contains("Content-Type: text/plain; charset=utf-8"));
+            assert!(headers.contains(&format!("Content-Range: bytes {}/8", range)));
+            assert_eq!(data, content);
+        }
+        assert_eq!(parts[3], "--\r\n");
     }
 
-    #[test]
-    fn path_is_hidden() {
-        // A file prefixed with `.` is considered as hidden.
+    #[tokio::test]
+    async fn handle_request_multipart_framing() {
         let args = Args {
-            all: false,
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
             ..Default::default()
         };
-        let (service, _) = bootstrap(args);
-        assert!(service.path_is_hidden(".a-hidden-file"));
+        let (mut service, _) = bootstrap(args);
+        service.boundaries = BoundaryGenerator::with_seed(0);
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-1,-2")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(
+            res.headers()[CONTENT_TYPE],
+            "multipart/byteranges; boundary=e220a8397b1dcdaf"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            concat!(
+                "\r\n--e220a8397b1dcdaf\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "Content-Range: bytes 0-1/8\r\n",
+                "\r\n",
+                "01",
+                "\r\n--e220a8397b1dcdaf\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "Content-Range: bytes 6-7/8\r\n",
+                "\r\n",
+                "67",
+                "\r\n--e220a8397b1dcdaf--\r\n",
+            )
+        );
+
+        // Each response gets a boundary of its own.
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_ne!(
+            res.headers()[CONTENT_TYPE],
+            "multipart/byteranges; boundary=e220a8397b1dcdaf"
+        );
     }
 
-    #[test]
-    fn path_is_not_hidden() {
-        // `--all` flag is on
-        let args = Args::default();
+    #[tokio::test]
+    async fn handle_request_overlapping_ranges() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
         let (service, _) = bootstrap(args);
-        assert!(!service.path_is_hidden(".a-hidden-file"));
-        assert!(!service.path_is_hidden("a-public-file"));
+        // Each byte is sent once, however many times it is requested.
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-,0-,2-5,-8")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            res.headers().get(hyper::header::CONTENT_RANGE).unwrap(),
+            "bytes 0-7/8",
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "01234567");
+    }
 
-        // `--all` flag is off and the file is not prefixed with `.`
+    #[tokio::test]
+    async fn handle_request_range_with_fresh_validators() {
         let args = Args {
-            all: false,
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
             ..Default::default()
         };
         let (service, _) = bootstrap(args);
-        assert!(!service.path_is_hidden("a-public-file"));
+        let res = send(&service, "/file.txt").await;
+        let etag = res.headers().typed_get::<ETag>().unwrap();
+        let last_modified = res.headers().typed_get::<LastModified>().unwrap();
+        fn range_with<H: headers::Header>(header: H) -> Request {
+            let mut req = hyper::Request::get("/file.txt")
+                .header(hyper::header::RANGE, "bytes=0-3")
+                .body(Body::empty())
+                .unwrap();
+            req.headers_mut().typed_insert(header);
+            req
+        }
+
+        // Not modified takes precedence over partial content.
+        let req = range_with(IfNoneMatch::from(etag));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let req = range_with(IfModifiedSince::from(SystemTime::from(last_modified)));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        // Stale validators proceed to partial content.
+        let req = range_with(IfNoneMatch::from(r#""0-0""#.parse::<ETag>().unwrap()));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let req = range_with(IfModifiedSince::from(SystemTime::UNIX_EPOCH));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
     }
 
-    #[test]
-    fn path_is_ignored() {
-        with_current_dir(get_tests_dir(), || {
-            let args = Args::default();
-            let (service, _) = bootstrap(args);
-            assert!(service.path_is_ignored("ignore_pattern"));
-            assert!(service.path_is_ignored("dir/ignore_pattern"));
+    #[tokio::test]
+    async fn handle_request_no_last_modified() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            no_last_modified: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().typed_get::<LastModified>().is_none());
+        let etag = res.headers().typed_get::<ETag>().unwrap();
+
+        let get_with = |header: &dyn Fn(&mut Request)| {
+            let mut req = hyper::Request::get("/file.txt")
+                .body(Body::empty())
+                .unwrap();
+            header(&mut req);
+            req
+        };
+
+        // ETag still validates.
+        let req = get_with(&|req| {
+            req.headers_mut()
+                .typed_insert(IfNoneMatch::from(etag.clone()))
+        });
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.headers().typed_get::<LastModified>().is_none());
+        assert_eq!(res.headers().typed_get::<ETag>(), Some(etag));
+
+        // Dates are ignored.
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        let req = get_with(&|req| {
+            req.headers_mut()
+                .typed_insert(IfModifiedSince::from(future))
         });
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = get_with(&|req| {
+            let since = IfUnmodifiedSince::from(SystemTime::UNIX_EPOCH);
+            req.headers_mut().typed_insert(since)
+        });
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
     }
 
-    #[test]
-    fn path_is_not_ignored() {
-        with_current_dir(get_tests_dir(), || {
-            // `--no-ignore` flag is on
-            let args = Args {
-                ignore: false,
-                ..Default::default()
-            };
-            let (service, _) = bootstrap(args);
-            assert!(!service.path_is_ignored("ignore_pattern"));
-            assert!(!service.path_is_ignored("dir/ignore_pattern"));
+    #[tokio::test]
+    async fn handle_request_range_with_if_match() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let etag = send(&service, "/file.txt")
+            .await
+            .headers()
+            .typed_get::<ETag>()
+            .unwrap();
 
-            // file.txt and .hidden.html is not ignored.
-            let args = Args::default();
-            let (service, _) = bootstrap(args);
-            assert!(!service.path_is_ignored("file.txt"));
-            assert!(!service.path_is_ignored(".hidden.html"));
-        });
+        // Entity changed. Precondition fails before serving partial content.
+        let req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-3")
+            .header(hyper::header::IF_MATCH, "\"changed\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        // Entity unchanged. Proceed to partial content.
+        let mut req = hyper::Request::get("/file.txt")
+            .header(hyper::header::RANGE, "bytes=0-3")
+            .body(Body::empty())
+            .unwrap();
+        req.headers_mut().typed_insert(IfMatch::from(etag));
+        let res = service.handle_request(&req, None).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"0123");
     }
 
-    #[test]
-    fn path_is_under_basepath() {
-        #[cfg(unix)]
-        use std::os::unix::fs::symlink as symlink_file;
-        #[cfg(windows)]
-        use std::os::windows::fs::symlink_file;
+    #[tokio::test]
+    async fn spawn_and_shutdown() {
+        let args = Args {
+            port: 0,
+            ..Default::default()
+        };
+        let (server, trigger) = spawn(args).unwrap();
+        trigger.shutdown();
+        assert!(server.await.unwrap().is_ok());
+    }
 
-        let src_dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
-        let src_dir = src_dir.path().canonicalize().unwrap();
-        let src_path = src_dir.join("src_file.txt");
-        let _ = File::create(&src_path);
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn spawn_with_listen_fd() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
 
-        // Is under service's base path
-        let symlink_path = src_dir.join("symlink");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
         let args = Args {
-            path: src_dir,
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            listen_fd: Some(listener.into_raw_fd()),
             ..Default::default()
         };
-        let (service, _) = bootstrap(args);
-        symlink_file(&src_path, &symlink_path).unwrap();
-        assert!(service.path_is_under_basepath(&symlink_path));
+        let (server, trigger) = spawn(args).unwrap();
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /file.txt HTTP/1.1\r\nHost: sfz\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("01234567"));
+        trigger.shutdown();
+        assert!(server.await.unwrap().is_ok());
+    }
 
-        // Not under base path.
-        let args = Args::default();
-        let (service, _) = bootstrap(args);
-        assert!(!service.path_is_under_basepath(&symlink_path));
+    #[tokio::test]
+    async fn spawn_sitemap_on_all_interfaces() {
+        let args = |sitemap_base_url: Option<&str>| Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            address: "0.0.0.0".to_owned(),
+            port: 0,
+            sitemap: true,
+            sitemap_base_url: sitemap_base_url.map(str::to_owned),
+            ..Default::default()
+        };
+        // No address to locate pages at.
+        assert!(spawn(args(None)).is_err());
+        let (server, trigger) = spawn(args(Some("https://example.com"))).unwrap();
+        trigger.shutdown();
+        server.await.unwrap().unwrap();
     }
 
-    #[test]
-    fn strips_path_prefix() {
+    #[tokio::test]
+    async fn spawn_with_tls() {
+        use std::io::Write;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{self, Certificate, RootCertStore};
+
+        const REQUEST: &[u8] = b"GET /file.txt HTTP/1.1\r\nHost: sfz\r\nConnection: close\r\n\r\n";
+
+        let dir = Builder::new().prefix(temp_name()).tempdir().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
         let args = Args {
-            path_prefix: Some("/foo".into()),
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            address: "127.0.0.1".to_owned(),
+            port,
+            tls_cert: Some(cert_path),
+            tls_key: Some(key_path),
             ..Default::default()
         };
-        let (service, _) = bootstrap(args);
+        let (server, trigger) = spawn(args).unwrap();
 
-        assert_eq!(
-            service.strip_path_prefix(&Path::new("foo/dir/to/bar.txt")),
-            Some(Path::new("dir/to/bar.txt"))
-        );
+        // Plain HTTP requests never get through.
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(REQUEST).unwrap();
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            response
+        })
+        .await
+        .unwrap();
+        assert!(!response.starts_with(b"HTTP/1.1 200"));
 
-        assert_eq!(
-            service.strip_path_prefix(&Path::new("dir/to/bar.txt")),
-            None
-        );
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&Certificate(cert.serialize_der().unwrap()))
+            .unwrap();
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let mut stream = connector
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("01234567"));
 
-        let args = Args::default();
-        let (service, _) = bootstrap(args);
+        trigger.shutdown();
+        assert!(server.await.unwrap().is_ok());
+    }
 
-        assert_eq!(
-            service.strip_path_prefix(&Path::new("foo/dir/to/bar.txt")),
-            Some(Path::new("foo/dir/to/bar.txt"))
-        );
+    #[tokio::test]
+    async fn spawn_with_tls_self_signed() {
+        use rustls::client::{ServerCertVerified, ServerCertVerifier};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        struct AcceptAny;
+
+        impl ServerCertVerifier for AcceptAny {
+            fn verify_server_cert(
+                &self,
+                _: &rustls::Certificate,
+                _: &[rustls::Certificate],
+                _: &rustls::ServerName,
+                _: &mut dyn Iterator<Item = &[u8]>,
+                _: &[u8],
+                _: SystemTime,
+            ) -> Result<ServerCertVerified, rustls::Error> {
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            address: "127.0.0.1".to_owned(),
+            port,
+            tls_self_signed: true,
+            ..Default::default()
+        };
+        let (server, trigger) = spawn(args).unwrap();
+
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny))
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let mut stream = connector
+            .connect("127.0.0.1".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /file.txt HTTP/1.1\r\nHost: sfz\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        trigger.shutdown();
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn spawn_with_admin_bind() {
+        use std::io::Write;
+
+        fn free_port() -> u16 {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        }
+
+        fn get(port: u16, path: &str) -> String {
+            let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(
+                stream,
+                "GET {path} HTTP/1.1\r\nHost: sfz\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        }
+
+        let (port, admin_port) = (free_port(), free_port());
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            address: "127.0.0.1".to_owned(),
+            port,
+            admin_bind: Some(([127, 0, 0, 1], admin_port).into()),
+            ..Default::default()
+        };
+        let (server, trigger) = spawn(args).unwrap();
+        let (file, main_metrics, metrics, health) = tokio::task::spawn_blocking(move || {
+            (
+                get(port, "/file.txt"),
+                get(port, "/metrics"),
+                get(admin_port, "/metrics"),
+                get(admin_port, "/healthz"),
+            )
+        })
+        .await
+        .unwrap();
+        assert!(file.starts_with("HTTP/1.1 200 OK"));
+        assert!(main_metrics.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        // Counters are shared with the main server.
+        assert!(metrics.contains("sfz_responses_total{class=\"2xx\"} 1\n"));
+        assert!(metrics.contains("sfz_responses_total{class=\"4xx\"} 1\n"));
+        assert!(health.starts_with("HTTP/1.1 200 OK"));
+        trigger.shutdown();
+        assert!(server.await.unwrap().is_ok());
     }
 
     #[ignore]
     #[test]
     fn handle_request() {}
 
+    #[test]
+    fn path_is_symlink() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            disable_symlinks: true,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let base = service.args.path.clone();
+        assert!(service.path_is_symlink(base.join("symlink_file.txt")));
+        assert!(service.path_is_symlink(base.join("symlink_dir")));
+        assert!(service.path_is_symlink(base.join("symlink_dir/ignore_pattern")));
+        assert!(!service.path_is_symlink(base.join("file.txt")));
+        assert!(!service.path_is_symlink(base.join("dir")));
+
+        // `--disable-symlinks` flag is off
+        let args = Args {
+            path: base.clone(),
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        assert!(!service.path_is_symlink(base.join("symlink_file.txt")));
+    }
+
+    #[tokio::test]
+    async fn handle_request_disable_symlinks() {
+        let args = Args {
+            path: get_tests_dir().as_ref().canonicalize().unwrap(),
+            disable_symlinks: true,
+            render_index: false,
+            ..Default::default()
+        };
+        let (service, _) = bootstrap(args);
+        let res = send(&service, "/symlink_file.txt").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = send(&service, "/symlink_dir").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = send(&service, "/file.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
     #[test]
     fn get_gzip_content_encoding() {
         let args = Args::default();
@@ -833,6 +5011,15 @@ mod t_server {
         assert_eq!(Some("gzip"), content_encoding);
     }
 
+    #[test]
+    fn get_zstd_content_encoding() {
+        let (service, _) = bootstrap(Args::default());
+        let accept_encoding = &HeaderValue::from_static("gzip, zstd");
+        let content_encoding =
+            service.get_content_encoding(Some(accept_encoding), StatusCode::OK, &mime::TEXT_PLAIN);
+        assert_eq!(content_encoding, Some(ZSTD));
+    }
+
     #[test]
     fn get_identity_content_encoding() {
         let args = Args::default();
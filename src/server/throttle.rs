@@ -0,0 +1,176 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Bandwidth caps shared by all transfers to the same client.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// Budget of bytes per second, shared by transfers holding it.
+#[derive(Debug)]
+pub struct Bucket {
+    rate: u64,
+    /// When the budget is available again.
+    next: Mutex<Instant>,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserve budget for `len` bytes. Returns when they may be sent.
+    fn reserve(&self, len: usize) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let start = (*next).max(Instant::now());
+        *next = start + Duration::from_secs_f64(len as f64 / self.rate as f64);
+        start
+    }
+}
+
+/// Per-IP buckets of a bandwidth cap.
+///
+/// Buckets live as long as transfers to the IP do, so idle clients start
+/// over with a full budget.
+#[derive(Debug)]
+pub struct Throttles {
+    rate: u64,
+    buckets: Mutex<HashMap<IpAddr, Weak<Bucket>>>,
+}
+
+impl Throttles {
+    /// Cap each IP to `rate` bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the bucket shared by transfers to the IP.
+    pub fn bucket(&self, ip: IpAddr) -> Arc<Bucket> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get(&ip).and_then(Weak::upgrade) {
+            return bucket;
+        }
+        buckets.retain(|_, bucket| bucket.strong_count() > 0);
+        let bucket = Arc::new(Bucket::new(self.rate));
+        buckets.insert(ip, Arc::downgrade(&bucket));
+        bucket
+    }
+}
+
+/// Delay chunks of a stream to stay within the budget of a bucket.
+pub struct Throttled<S> {
+    inner: S,
+    bucket: Arc<Bucket>,
+    /// Chunk waiting for its turn.
+    pending: Option<(Bytes, Pin<Box<Sleep>>)>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, bucket: Arc<Bucket>) -> Self {
+        Self {
+            inner,
+            bucket,
+            pending: None,
+        }
+    }
+}
+
+impl<S> Stream for Throttled<S>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let start = this.bucket.reserve(chunk.len());
+                    this.pending = Some((chunk, Box::pin(sleep_until(start))));
+                }
+                poll => return poll,
+            }
+        }
+        let (_, delay) = this.pending.as_mut().unwrap();
+        match delay.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(this.pending.take().map(|(chunk, _)| Ok(chunk))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use futures::StreamExt;
+
+    const CHUNK: usize = 10_000;
+
+    /// Download 4 chunks through the bucket of the IP.
+    async fn download(throttles: &Throttles, ip: &str) -> usize {
+        let chunks = (0..4).map(|_| Ok(Bytes::from(vec![0; CHUNK])));
+        let bucket = throttles.bucket(ip.parse().unwrap());
+        let stream = Throttled::new(futures::stream::iter(chunks), bucket);
+        stream
+            .map(|chunk| chunk.unwrap().len())
+            .fold(0, |a, b| async move { a + b })
+            .await
+    }
+
+    #[tokio::test]
+    async fn share_budget_per_ip() {
+        // Each download alone takes 0.3s after the first chunk.
+        let throttles = Throttles::new(100_000);
+        let timer = std::time::Instant::now();
+        let (a, b) = futures::join!(
+            download(&throttles, "10.0.0.1"),
+            download(&throttles, "10.0.0.1"),
+        );
+        assert_eq!(a + b, 8 * CHUNK);
+        assert!(timer.elapsed() >= Duration::from_millis(700));
+
+        // Other IPs have their own budgets.
+        let throttles = Throttles::new(100_000);
+        let timer = std::time::Instant::now();
+        futures::join!(
+            download(&throttles, "10.0.0.1"),
+            download(&throttles, "10.0.0.2"),
+        );
+        let elapsed = timer.elapsed();
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_millis(700));
+    }
+
+    #[test]
+    fn drop_idle_buckets() {
+        let throttles = Throttles::new(1);
+        let ip = "10.0.0.1".parse().unwrap();
+        let bucket = throttles.bucket(ip);
+        assert!(Arc::ptr_eq(&bucket, &throttles.bucket(ip)));
+        drop(bucket);
+        throttles.bucket("10.0.0.2".parse().unwrap());
+        assert_eq!(throttles.buckets.lock().unwrap().len(), 1);
+    }
+}
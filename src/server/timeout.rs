@@ -0,0 +1,119 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Stream adapter terminating stalled transfers.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// Terminate a stream with a `TimedOut` error if no chunk is delivered
+/// within the timeout, either because the inner stream stalls or because the
+/// consumer stops pulling chunks.
+///
+/// The inner stream is dropped once timed out, releasing resources it holds
+/// such as file handles.
+pub struct IdleTimeout<S> {
+    inner: Option<S>,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<S> IdleTimeout<S> {
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        Self {
+            inner: Some(inner),
+            timeout,
+            deadline: Box::pin(sleep_until(Instant::now() + timeout)),
+        }
+    }
+}
+
+impl<S> Stream for IdleTimeout<S>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let inner = match this.inner.as_mut() {
+            Some(inner) => inner,
+            None => return Poll::Ready(None),
+        };
+        // The consumer might have stalled longer than the timeout since the
+        // last chunk was delivered.
+        if this.deadline.is_elapsed() {
+            this.inner = None;
+            return Poll::Ready(Some(Err(timed_out())));
+        }
+        match Pin::new(inner).poll_next(cx) {
+            Poll::Ready(item) => {
+                let deadline = Instant::now() + this.timeout;
+                this.deadline.as_mut().reset(deadline);
+                Poll::Ready(item)
+            }
+            Poll::Pending => match this.deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    this.inner = None;
+                    Poll::Ready(Some(Err(timed_out())))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "body transfer timed out")
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use futures::StreamExt;
+
+    fn chunks(n: usize) -> impl Stream<Item = io::Result<Bytes>> + Unpin {
+        futures::stream::iter((0..n).map(|_| Ok(Bytes::from_static(b"chunk"))))
+    }
+
+    #[tokio::test]
+    async fn deliver_without_stall() {
+        let mut stream = IdleTimeout::new(chunks(3), Duration::from_millis(50));
+        for _ in 0..3 {
+            assert!(stream.next().await.unwrap().is_ok());
+        }
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stalled_consumer() {
+        let mut stream = IdleTimeout::new(chunks(3), Duration::from_millis(50));
+        assert!(stream.next().await.unwrap().is_ok());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // Terminated after timed out.
+        assert!(stream.inner.is_none());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stalled_producer() {
+        let pending = futures::stream::pending::<io::Result<Bytes>>();
+        let mut stream = IdleTimeout::new(pending, Duration::from_millis(50));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
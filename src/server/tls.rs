@@ -0,0 +1,145 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! HTTPS on top of accepted TCP connections.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::BoxResult;
+
+/// Max handshakes in progress at once. Connections beyond wait in the
+/// backlog of the listener.
+const MAX_HANDSHAKES: usize = 64;
+
+/// Time for a client to complete the handshake before being dropped, so
+/// that stalled clients never hold handshake slots forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build a TLS config serving the certificate chain signed by `key`.
+pub fn server_config(certs: Vec<Certificate>, key: PrivateKey) -> BoxResult<Arc<ServerConfig>> {
+    let mut config = match ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+    {
+        Ok(config) => config,
+        Err(err) => bail!("error: invalid TLS certificate or key: {}", err),
+    };
+    // Only HTTP/1 is served.
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Names a self-signed certificate is valid for.
+const SELF_SIGNED_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+/// Generate an ephemeral ECDSA P-256 certificate for local hosts, kept only
+/// in memory.
+fn self_signed_cert() -> BoxResult<rcgen::Certificate> {
+    let names = SELF_SIGNED_NAMES.map(str::to_owned).to_vec();
+    rcgen::generate_simple_self_signed(names)
+        .or_else(|err| bail!("error: failed to generate certificate: {}", err))
+}
+
+/// Build a TLS config serving a freshly generated self-signed certificate.
+pub fn self_signed_server_config() -> BoxResult<Arc<ServerConfig>> {
+    let cert = self_signed_cert()?;
+    let der = match cert.serialize_der() {
+        Ok(der) => der,
+        Err(err) => bail!("error: failed to generate certificate: {}", err),
+    };
+    let key = PrivateKey(cert.serialize_private_key_der());
+    server_config(vec![Certificate(der)], key)
+}
+
+/// Load a TLS config from PEM files of a certificate chain and its private
+/// key.
+pub fn load_server_config(cert: &Path, key: &Path) -> BoxResult<Arc<ServerConfig>> {
+    let certs = read_pem(cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        bail!("error: no certificate found in \"{}\"", cert.display());
+    }
+    let key_der = read_pem(key)?.into_iter().find_map(|item| match item {
+        rustls_pemfile::Item::PKCS8Key(der)
+        | rustls_pemfile::Item::RSAKey(der)
+        | rustls_pemfile::Item::ECKey(der) => Some(der),
+        _ => None,
+    });
+    match key_der {
+        Some(der) => server_config(certs, PrivateKey(der)),
+        None => bail!("error: no private key found in \"{}\"", key.display()),
+    }
+}
+
+fn read_pem(path: &Path) -> BoxResult<Vec<rustls_pemfile::Item>> {
+    File::open(path)
+        .and_then(|file| rustls_pemfile::read_all(&mut BufReader::new(file)))
+        .or_else(|err| bail!("error: failed to read \"{}\": {}", path.display(), err))
+}
+
+/// Accept TLS connections over plain ones from `incoming`.
+///
+/// Handshakes run concurrently, and failed ones are dropped silently, as
+/// plain HTTP requests to an HTTPS port are.
+pub fn tls_incoming(
+    mut incoming: AddrIncoming,
+    config: Arc<ServerConfig>,
+) -> impl Accept<Conn = TlsStream<AddrStream>, Error = io::Error> {
+    let acceptor = TlsAcceptor::from(config);
+    let conns =
+        futures::stream::poll_fn(move |cx| std::pin::Pin::new(&mut incoming).poll_accept(cx));
+    hyper::server::accept::from_stream(handshake(conns, acceptor))
+}
+
+fn handshake(
+    conns: impl Stream<Item = io::Result<AddrStream>>,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = io::Result<TlsStream<AddrStream>>> {
+    conns
+        .filter_map(|conn| async move { conn.ok() })
+        .map(move |conn| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(conn)))
+        .buffer_unordered(MAX_HANDSHAKES)
+        .filter_map(|res| async move {
+            match res {
+                Ok(Ok(stream)) => Some(Ok(stream)),
+                _ => None,
+            }
+        })
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+    use rcgen::SanType;
+
+    #[test]
+    fn self_signed() {
+        let cert = self_signed_cert().unwrap();
+        let names = &cert.get_params().subject_alt_names;
+        assert!(names.contains(&SanType::DnsName("localhost".to_owned())));
+        assert!(names.contains(&SanType::IpAddress([127, 0, 0, 1].into())));
+        assert!(self_signed_server_config().is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2018 Weihang Lo
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reload ignore and exclude rules when their files change.
+
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use ignore::gitignore::Gitignore;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::server::exclude::{read_patterns, Exclude};
+
+/// A value swappable at runtime.
+///
+/// Readers take a snapshot with `load`, so no lock is held while using it.
+#[derive(Debug)]
+pub struct Reloadable<T>(RwLock<Arc<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(Arc::new(value)))
+    }
+
+    /// Get a snapshot of current value.
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Replace current value.
+    pub fn store(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+/// Watch `.gitignore` under `base_path` and the optional exclude file.
+///
+/// Matchers are rebuilt on change. An exclude file failing to load keeps the
+/// previous rules. The returned watcher stops watching once dropped.
+pub fn watch_rules(
+    base_path: &Path,
+    exclude_from: Option<&Path>,
+    gitignore: Arc<Reloadable<Gitignore>>,
+    exclude: Arc<Reloadable<Option<Exclude>>>,
+) -> notify::Result<RecommendedWatcher> {
+    let base_path = base_path.to_owned();
+    let gitignore_path = base_path.join(".gitignore");
+    let exclude_from = exclude_from.map(Path::to_owned);
+
+    // Watch parent directories, as editors often replace files on save.
+    let mut dirs = vec![base_path.clone()];
+    if let Some(dir) = exclude_from.as_deref().and_then(Path::parent) {
+        if !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_owned());
+        }
+    }
+
+    let handler = move |event: notify::Result<Event>| {
+        let paths = match event {
+            Ok(event) => event.paths,
+            Err(_) => return,
+        };
+        if paths.contains(&gitignore_path) {
+            gitignore.store(Gitignore::new(&gitignore_path).0);
+        }
+        if let Some(file) = exclude_from.as_ref().filter(|f| paths.contains(f)) {
+            if let Some(rules) = load_exclude(&base_path, file) {
+                exclude.store(rules);
+            }
+        }
+    };
+    let mut watcher = notify::recommended_watcher(handler)?;
+    for dir in dirs {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
+
+fn load_exclude(base_path: &Path, file: &Path) -> Option<Option<Exclude>> {
+    let patterns = read_patterns(file).ok()?;
+    if patterns.is_empty() {
+        return Some(None);
+    }
+    Exclude::new(base_path, &patterns).ok().map(Some)
+}